@@ -0,0 +1,59 @@
+#![allow(dead_code)]
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// The system allocator, counting allocations and the bytes currently allocated.
+pub struct CountingAlloc;
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        LIVE_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+/// What building a value cost, see `measure`.
+pub struct Usage {
+    pub allocations: usize,
+    /// Bytes still allocated once the value was built, i.e. what keeping it costs.
+    pub retained_bytes: usize,
+}
+
+/// Builds a value, counting the allocations it took and the bytes it holds on to.
+pub fn measure<T, F: FnOnce() -> T>(build: F) -> (T, Usage) {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let live_bytes = LIVE_BYTES.load(Ordering::Relaxed);
+    let value = build();
+    let usage = Usage {
+        allocations: ALLOCATIONS.load(Ordering::Relaxed) - allocations,
+        retained_bytes: LIVE_BYTES
+            .load(Ordering::Relaxed)
+            .saturating_sub(live_bytes),
+    };
+
+    (value, usage)
+}
+
+/// Distinct, normalized wind report lines shaped like the live feed's.
+pub fn report_lines(count: usize) -> Vec<String> {
+    (0..count)
+        .map(|i| {
+            format!(
+                r#"Icon: 43.{:06},-94.639999,000,0,5,"Reported By: Test Human {}\nHigh Wind\nTime: 2018-09-20 22:{:02}:00 UTC\n60 mph [Measured]\nNotes: None""#,
+                i,
+                i % 97,
+                i % 60
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,39 @@
+mod common;
+
+use common::{measure, report_lines, CountingAlloc};
+use std::time::Instant;
+use wx_sn_loader::{get_comparison_with_capacity, SeenSet};
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+const ROUNDS: u32 = 1_000;
+
+/**
+ * Compares a poll's comparison with and without pre-allocating for the previous feed's size, as
+ * the producer does, on feeds about as big as a quiet and a busy day.
+ */
+fn main() {
+    for &reports in &[30, 300] {
+        let body = report_lines(reports).join("\n");
+        let seen = SeenSet::default();
+
+        for &capacity in &[0, reports * 2] {
+            let (comparison, usage) =
+                measure(|| get_comparison_with_capacity(&body, &seen, capacity));
+            assert_eq!(comparison.new.len(), reports);
+
+            let started = Instant::now();
+            for _ in 0..ROUNDS {
+                drop(get_comparison_with_capacity(&body, &seen, capacity));
+            }
+            println!(
+                "{} reports, capacity {}: {} allocations, {:?} per comparison",
+                reports,
+                capacity,
+                usage.allocations,
+                started.elapsed() / ROUNDS
+            );
+        }
+    }
+}
@@ -0,0 +1,30 @@
+mod common;
+
+use common::{measure, report_lines, CountingAlloc};
+use std::time::Instant;
+use wx_sn_loader::{DedupKey, ReportSet, SeenSet};
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+const REPORTS: usize = 10_000;
+
+/// Compares holding 10,000 seen reports as normalized lines and as hashes by each `DedupKey`.
+fn main() {
+    let lines = report_lines(REPORTS);
+
+    let (set, usage) = measure(|| lines.iter().cloned().collect::<ReportSet>());
+    println!("lines: {} bytes", usage.retained_bytes);
+    drop(set);
+
+    for &key in &[DedupKey::Line, DedupKey::Identity] {
+        let started = Instant::now();
+        let (set, usage) = measure(|| lines.iter().map(|x| key.hash(x)).collect::<SeenSet>());
+        let elapsed = started.elapsed();
+        println!(
+            "{:?} hashes: {} bytes, built in {:?}",
+            key, usage.retained_bytes, elapsed
+        );
+        assert_eq!(set.len(), REPORTS);
+    }
+}
@@ -0,0 +1,239 @@
+use crate::producer::{PollResult, Producer};
+use crate::sink::EventSink;
+use crate::wakeup::Wakeup;
+use crate::{read_body, request_error, ClientSettings, Config};
+use futures::future::{self, Either, Loop};
+use futures::sync::mpsc::UnboundedReceiver;
+use futures::{Future, Stream};
+use reqwest::header;
+use reqwest::r#async::{Client, RequestBuilder};
+use std::cell::{Cell, RefCell};
+use std::time::Instant;
+use tokio::timer::Delay;
+use wx::error::Error;
+
+/**
+ * Fetches the feed with reqwest's async client, built from the same settings as `HttpFetcher`.
+ * Drive a producer holding one with `poll_async` or `run_async`.
+ */
+pub struct AsyncHttpFetcher {
+    client: RefCell<Client>,
+    settings: ClientSettings,
+    last_request: Cell<Option<Instant>>,
+    url: String,
+    user_agent: String,
+    basic_auth: Option<(String, Option<String>)>,
+}
+
+impl AsyncHttpFetcher {
+    pub fn new(config: &Config) -> Result<AsyncHttpFetcher, Error> {
+        let settings = ClientSettings::from_config(config)?;
+        let client = RefCell::new(settings.build_async()?);
+
+        let basic_auth = config
+            .basic_auth_user
+            .as_ref()
+            .map(|user| (user.clone(), config.basic_auth_pass.clone()));
+
+        Ok(AsyncHttpFetcher {
+            client,
+            settings,
+            last_request: Cell::new(None),
+            url: config.api_url.clone(),
+            user_agent: config.user_agent.clone(),
+            basic_auth,
+        })
+    }
+
+    fn request(&self) -> RequestBuilder {
+        let request = self
+            .client
+            .borrow()
+            .get(&self.url)
+            .header(header::USER_AGENT, self.user_agent.as_str());
+
+        match &self.basic_auth {
+            Some((user, pass)) => request.basic_auth(user, pass.as_ref()),
+            None => request,
+        }
+    }
+
+    pub fn fetch_async(&self) -> impl Future<Item = String, Error = Error> {
+        if self.settings.needs_new_client(self.last_request.get()) {
+            match self.settings.build_async() {
+                Ok(client) => *self.client.borrow_mut() = client,
+                Err(e) => return Either::A(future::err(e)),
+            }
+        }
+
+        self.last_request.set(Some(Instant::now()));
+        let fetched = self
+            .request()
+            .send()
+            .map_err(request_error)
+            .and_then(|mut response| {
+                let status = response.status();
+                response.text().then(move |text| read_body(status, text))
+            });
+
+        Either::B(fetched)
+    }
+}
+
+/// Fetches without blocking, then runs the rest of the poll cycle, handing the producer back.
+pub fn poll_async<S: EventSink>(
+    mut producer: Producer<AsyncHttpFetcher, S>,
+) -> impl Future<Item = (Producer<AsyncHttpFetcher, S>, PollResult), Error = ()> {
+    let started = Instant::now();
+    producer.fetcher().fetch_async().then(move |fetched| {
+        let result = producer.poll_with(fetched, started.elapsed());
+        Ok((producer, result))
+    })
+}
+
+/**
+ * Polls until `wakeups` asks for a shutdown, waiting `Producer::next_wait` between polls without
+ * blocking the thread. A `ForcePoll` wakeup ends the wait early. Hand it signals with
+ * `wakeup::forward_signals_with`. Resolves to an error if the store becomes unavailable, see
+ * `exit_on_store_unavailable`. The producer isn't `Send`, so run this on a current thread
+ * runtime, e.g. `tokio::runtime::current_thread::run(run_async(producer, wakeups))`.
+ */
+pub fn run_async<S: EventSink>(
+    producer: Producer<AsyncHttpFetcher, S>,
+    wakeups: UnboundedReceiver<Wakeup>,
+) -> impl Future<Item = (), Error = ()> {
+    future::loop_fn((producer, wakeups), |(producer, wakeups)| {
+        poll_async(producer).and_then(|(mut producer, result)| {
+            if result.store_unavailable {
+                return Either::A(future::err(()));
+            }
+
+            let delay = Delay::new(Instant::now() + producer.next_wait(&result));
+            let waited = delay.select2(wakeups.into_future()).then(|waited| {
+                let (wakeup, wakeups) = match waited {
+                    Ok(Either::A((_, wakeup))) => (None, wakeup.into_inner()),
+                    Ok(Either::B(((wakeup, wakeups), _))) => (wakeup, Some(wakeups)),
+                    Err(_) => return Err(()),
+                };
+
+                match (wakeup, wakeups) {
+                    (Some(Wakeup::Shutdown), _) | (_, None) => Ok(Loop::Break(())),
+                    (_, Some(wakeups)) => Ok(Loop::Continue((producer, wakeups))),
+                }
+            });
+
+            Either::B(waited)
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{test_logger, wind_reports, CapturingDrain, MockSink};
+    use futures::sync::mpsc;
+    use slog::Drain;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use tokio::runtime::current_thread::Runtime;
+
+    /// Serves a single response with `body`, returning the feed URL.
+    fn serve_once(body: String) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "http://{}/feeds/reports.txt",
+            listener.local_addr().unwrap()
+        );
+
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request);
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).unwrap();
+        });
+
+        url
+    }
+
+    #[test]
+    fn poll_async_should_store_fetched_reports() {
+        let config = Config {
+            api_url: serve_once(wind_reports(3)),
+            ..Config::default()
+        };
+        let fetcher = AsyncHttpFetcher::new(&config).unwrap();
+        let sink = MockSink::default();
+        let producer = Producer::new(config, fetcher, sink.clone(), test_logger());
+
+        let mut runtime = Runtime::new().unwrap();
+        let (producer, result) = runtime.block_on(poll_async(producer)).unwrap();
+
+        assert!(!result.fetch_failed);
+        assert_eq!(result.new_events, 3);
+        assert_eq!(result.stored, 3);
+        assert_eq!(sink.events.borrow().len(), 3);
+        assert_eq!(producer.last_poll_result(), Some(&result));
+    }
+
+    #[test]
+    fn poll_async_should_log_fetch_size_and_duration() {
+        let body = wind_reports(1);
+        let body_bytes = body.len() as u64;
+        let config = Config {
+            api_url: serve_once(body),
+            ..Config::default()
+        };
+        let fetcher = AsyncHttpFetcher::new(&config).unwrap();
+        let drain = CapturingDrain::default();
+        let logger = slog::Logger::root(drain.clone().fuse(), o!());
+        let producer = Producer::new(config, fetcher, MockSink::default(), logger);
+
+        let mut runtime = Runtime::new().unwrap();
+        runtime.block_on(poll_async(producer)).unwrap();
+        let fields = drain.fields.lock().unwrap();
+        let field = |key: &str| {
+            let (_, value) = fields.iter().find(|(k, _)| k == key).unwrap();
+            value.parse::<u64>().unwrap()
+        };
+
+        assert_eq!(field("body_bytes"), body_bytes);
+        assert!(field("fetch_ms") < 5_000);
+    }
+
+    #[test]
+    fn async_fetcher_should_reject_invalid_bind_address() {
+        let config = Config {
+            bind_address: Some("not an address".to_string()),
+            ..Config::default()
+        };
+
+        assert!(AsyncHttpFetcher::new(&config).is_err());
+    }
+
+    #[test]
+    fn run_async_should_stop_on_shutdown() {
+        let config = Config {
+            api_url: serve_once(wind_reports(2)),
+            ..Config::default()
+        };
+        let fetcher = AsyncHttpFetcher::new(&config).unwrap();
+        let sink = MockSink::default();
+        let producer = Producer::new(config, fetcher, sink.clone(), test_logger());
+        let (waker, wakeups) = mpsc::unbounded();
+        waker.unbounded_send(Wakeup::Shutdown).unwrap();
+
+        let mut runtime = Runtime::new().unwrap();
+        let started = Instant::now();
+        runtime.block_on(run_async(producer, wakeups)).unwrap();
+
+        // Stopped after the first poll rather than waiting out the 60s poll interval
+        assert_eq!(sink.events.borrow().len(), 2);
+        assert!(started.elapsed().as_secs() < 30);
+    }
+}
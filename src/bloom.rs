@@ -0,0 +1,113 @@
+use bloomfilter::Bloom;
+
+/**
+ * A bloom filter over content hashes, used to skip seen set lookups for reports that are
+ * definitely new. Wraps `bloomfilter::Bloom` to also count the keys inserted, so the producer can
+ * tell when it has outgrown its size.
+ */
+pub struct BloomFilter {
+    bloom: Bloom<u64>,
+    capacity: usize,
+    len: usize,
+}
+
+impl BloomFilter {
+    /// Sizes the filter to hold `expected_items` at about `false_positive_probability`.
+    pub fn new(expected_items: usize, false_positive_probability: f64) -> BloomFilter {
+        BloomFilter {
+            bloom: Bloom::new_for_fp_rate(expected_items.max(1), false_positive_probability),
+            capacity: expected_items,
+            len: 0,
+        }
+    }
+
+    pub fn insert(&mut self, key: u64) {
+        self.bloom.set(&key);
+        self.len += 1;
+    }
+
+    /// How many keys have been inserted since the filter was created or cleared.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// `true` once it holds more keys than it was sized for, past its false positive target.
+    #[must_use]
+    pub fn is_full(&self) -> bool {
+        self.len > self.capacity
+    }
+
+    /// `false` means `key` was never inserted; `true` means it probably was.
+    #[must_use]
+    pub fn might_contain(&self, key: u64) -> bool {
+        self.bloom.check(&key)
+    }
+
+    pub fn clear(&mut self) {
+        self.bloom.clear();
+        self.len = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::content_hash;
+    use crate::test_util::WIND_REPORT;
+
+    /// A distinct report for each `i`, moved north a little at a time.
+    fn report_hash(i: usize) -> u64 {
+        content_hash(&WIND_REPORT.replace("43.112000", &format!("43.{:06}", i)))
+    }
+
+    #[test]
+    fn bloom_filter_should_not_have_false_negatives() {
+        let mut bloom = BloomFilter::new(1000, 0.001);
+        for i in 0..1000 {
+            bloom.insert(report_hash(i));
+        }
+
+        assert!((0..1000).all(|i| bloom.might_contain(report_hash(i))));
+    }
+
+    #[test]
+    fn bloom_filter_false_positive_rate_should_be_near_target() {
+        let mut bloom = BloomFilter::new(1000, 0.001);
+        for i in 0..1000 {
+            bloom.insert(report_hash(i));
+        }
+
+        let false_positives = (1000..11_000)
+            .filter(|&i| bloom.might_contain(report_hash(i)))
+            .count();
+        // 0.1% of 10,000 is 10, allow for some variance
+        assert!(false_positives <= 30, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn clear_should_empty_bloom_filter() {
+        let mut bloom = BloomFilter::new(10, 0.001);
+        bloom.insert(report_hash(0));
+        bloom.clear();
+
+        assert!(!bloom.might_contain(report_hash(0)));
+        assert!(bloom.is_empty());
+    }
+
+    #[test]
+    fn bloom_filter_should_be_full_past_expected_items() {
+        let mut bloom = BloomFilter::new(2, 0.001);
+        bloom.insert(report_hash(0));
+        bloom.insert(report_hash(1));
+        assert!(!bloom.is_full());
+
+        bloom.insert(report_hash(2));
+        assert!(bloom.is_full());
+    }
+}
@@ -0,0 +1,425 @@
+use crate::sink::{CompletedWrite, EventSink, StoreError};
+use slog::Logger;
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use wx::domain::Event;
+use wx::error::{Error, WxError};
+
+/// What to do with a new item when the queue is full.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum DropPolicy {
+    /// Wait for the consumer to make room.
+    Block,
+    /// Evict the oldest queued item to make room.
+    DropOldest,
+    /// Discard the new item.
+    DropNewest,
+}
+
+struct QueueState<T> {
+    items: VecDeque<T>,
+    dropped: usize,
+    closed: bool,
+}
+
+/// A fixed capacity FIFO shared between a producer and a consumer thread.
+pub struct BoundedQueue<T> {
+    state: Mutex<QueueState<T>>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    capacity: usize,
+    policy: DropPolicy,
+}
+
+impl<T> BoundedQueue<T> {
+    pub fn new(capacity: usize, policy: DropPolicy) -> BoundedQueue<T> {
+        BoundedQueue {
+            state: Mutex::new(QueueState {
+                items: VecDeque::with_capacity(capacity),
+                dropped: 0,
+                closed: false,
+            }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            capacity: capacity.max(1),
+            policy,
+        }
+    }
+
+    /**
+     * Queues an item according to the drop policy, returning `false` if it was discarded. Evicting
+     * an older item to make room still queues this one, so that only shows up in `dropped`.
+     */
+    pub fn push(&self, item: T) -> bool {
+        let mut state = self.state.lock().unwrap();
+
+        if state.items.len() >= self.capacity {
+            match self.policy {
+                DropPolicy::Block => {
+                    while state.items.len() >= self.capacity && !state.closed {
+                        state = self.not_full.wait(state).unwrap();
+                    }
+                }
+                DropPolicy::DropOldest => {
+                    state.items.pop_front();
+                    state.dropped += 1;
+                }
+                DropPolicy::DropNewest => {
+                    state.dropped += 1;
+                    return false;
+                }
+            }
+        }
+
+        state.items.push_back(item);
+        self.not_empty.notify_one();
+        true
+    }
+
+    /// Blocks until an item is available, or returns `None` once closed and drained.
+    pub fn pop(&self) -> Option<T> {
+        let mut state = self.state.lock().unwrap();
+
+        loop {
+            if let Some(item) = state.items.pop_front() {
+                self.not_full.notify_one();
+                return Some(item);
+            }
+            if state.closed {
+                return None;
+            }
+            state = self.not_empty.wait(state).unwrap();
+        }
+    }
+
+    /// Stops the consumer once the remaining items are drained.
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.not_empty.notify_all();
+        self.not_full.notify_all();
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Total items dropped by the drop policy.
+    pub fn dropped(&self) -> usize {
+        self.state.lock().unwrap().dropped
+    }
+}
+
+/**
+ * Writes queued events to `inner` until the queue is closed and drained, keeping each outcome in
+ * `completed` for the producer to collect.
+ */
+fn consume<S: EventSink + ?Sized>(
+    queue: &BoundedQueue<Event>,
+    inner: &S,
+    completed: &Mutex<Vec<CompletedWrite>>,
+) {
+    while let Some(event) = queue.pop() {
+        let result = inner.put_event(&event);
+        completed
+            .lock()
+            .unwrap()
+            .push(CompletedWrite { event, result });
+    }
+}
+
+/**
+ * Hands events off to background threads that write them to the inner sink, so a slow store
+ * doesn't hold up polling. `put_event` only fails if the event itself was discarded by the queue's
+ * drop policy, and events evicted by `DropOldest` are only logged. Write failures are handed back
+ * through `take_completed`, so the producer can dead-letter them on its next poll. Dropping the
+ * sink waits for queued events to be written, but failures from then on are lost.
+ */
+pub struct ChannelSink {
+    queue: Arc<BoundedQueue<Event>>,
+    consumers: Vec<JoinHandle<()>>,
+    completed: Arc<Mutex<Vec<CompletedWrite>>>,
+    logger: Logger,
+}
+
+impl ChannelSink {
+    pub fn new<S: EventSink + Send + 'static>(
+        inner: S,
+        capacity: usize,
+        policy: DropPolicy,
+        logger: Logger,
+    ) -> ChannelSink {
+        let queue = Arc::new(BoundedQueue::new(capacity, policy));
+        let completed = Arc::new(Mutex::new(Vec::new()));
+        let consumer_queue = Arc::clone(&queue);
+        let consumer_completed = Arc::clone(&completed);
+        let consumer = thread::spawn(move || consume(&consumer_queue, &inner, &consumer_completed));
+
+        ChannelSink {
+            queue,
+            consumers: vec![consumer],
+            completed,
+            logger,
+        }
+    }
+
+    /**
+     * Like `new`, but with `workers` threads writing concurrently, e.g. to keep up during an
+     * outbreak. Events may be written out of order, but each is still written exactly once.
+     */
+    pub fn with_workers<S: EventSink + Send + Sync + 'static>(
+        inner: S,
+        capacity: usize,
+        policy: DropPolicy,
+        workers: usize,
+        logger: Logger,
+    ) -> ChannelSink {
+        let queue = Arc::new(BoundedQueue::new(capacity, policy));
+        let completed = Arc::new(Mutex::new(Vec::new()));
+        let inner = Arc::new(inner);
+
+        let consumers = (0..workers.max(1))
+            .map(|_| {
+                let consumer_queue = Arc::clone(&queue);
+                let consumer_inner = Arc::clone(&inner);
+                let consumer_completed = Arc::clone(&completed);
+                thread::spawn(move || {
+                    consume(&consumer_queue, &*consumer_inner, &consumer_completed)
+                })
+            })
+            .collect();
+
+        ChannelSink {
+            queue,
+            consumers,
+            completed,
+            logger,
+        }
+    }
+
+    pub fn dropped(&self) -> usize {
+        self.queue.dropped()
+    }
+}
+
+impl EventSink for ChannelSink {
+    fn put_event(&self, event: &Event) -> Result<(), StoreError> {
+        let dropped_before = self.queue.dropped();
+
+        if self.queue.push(event.clone()) {
+            let dropped = self.queue.dropped();
+            if dropped > dropped_before {
+                warn!(self.logger, "event channel full, evicted oldest event"; "dropped" => dropped);
+            }
+            return Ok(());
+        }
+
+        let dropped = self.queue.dropped();
+        warn!(self.logger, "event channel full, dropped event"; "dropped" => dropped);
+        let reason = format!("event channel full, {} dropped", dropped);
+        Err(StoreError::Store(Error::Wx(<WxError>::new(&reason))))
+    }
+
+    fn writes_later(&self) -> bool {
+        true
+    }
+
+    fn take_completed(&self) -> Vec<CompletedWrite> {
+        self.completed.lock().unwrap().drain(..).collect()
+    }
+}
+
+impl Drop for ChannelSink {
+    fn drop(&mut self) {
+        self.queue.close();
+        for consumer in self.consumers.drain(..) {
+            let _ = consumer.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::producer::Producer;
+    use crate::test_util::{
+        test_logger, wind_event, wind_reports, CapturingDrain, FailingSink, MockFetcher,
+    };
+    use crate::Config;
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[derive(Clone, Default)]
+    struct SharedSink {
+        events: Arc<Mutex<Vec<Event>>>,
+    }
+
+    impl EventSink for SharedSink {
+        fn put_event(&self, event: &Event) -> Result<(), StoreError> {
+            self.events.lock().unwrap().push(event.clone());
+            Ok(())
+        }
+    }
+
+    fn drain(queue: &BoundedQueue<u32>) -> Vec<u32> {
+        queue.close();
+        std::iter::from_fn(|| queue.pop()).collect()
+    }
+
+    #[test]
+    fn drop_oldest_should_evict_front_of_queue() {
+        let queue = BoundedQueue::new(2, DropPolicy::DropOldest);
+        assert!(queue.push(1));
+        assert!(queue.push(2));
+        assert!(queue.push(3));
+
+        assert_eq!(queue.dropped(), 1);
+        assert_eq!(drain(&queue), vec![2, 3]);
+    }
+
+    #[test]
+    fn drop_newest_should_discard_new_items() {
+        let queue = BoundedQueue::new(2, DropPolicy::DropNewest);
+        assert!(queue.push(1));
+        assert!(queue.push(2));
+        assert!(!queue.push(3));
+
+        assert_eq!(queue.dropped(), 1);
+        assert_eq!(drain(&queue), vec![1, 2]);
+    }
+
+    #[test]
+    fn block_should_wait_for_room() {
+        let queue = Arc::new(BoundedQueue::new(2, DropPolicy::Block));
+        queue.push(1);
+        queue.push(2);
+
+        let producer_queue = Arc::clone(&queue);
+        let producer = thread::spawn(move || producer_queue.push(3));
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(queue.len(), 2);
+
+        assert_eq!(queue.pop(), Some(1));
+        assert!(producer.join().unwrap());
+        assert_eq!(queue.dropped(), 0);
+        assert_eq!(drain(&queue), vec![2, 3]);
+    }
+
+    #[test]
+    fn channel_sink_should_write_queued_events_before_dropping() {
+        let inner = SharedSink::default();
+        let logger = Logger::root(slog::Discard, o!());
+        let sink = ChannelSink::new(inner.clone(), 8, DropPolicy::Block, logger);
+        let event = wind_event();
+
+        for _ in 0..5 {
+            sink.put_event(&event).unwrap();
+        }
+        drop(sink);
+
+        assert_eq!(inner.events.lock().unwrap().len(), 5);
+    }
+
+    /// Records events like `SharedSink`, but slowly, tracking how many writes overlap.
+    #[derive(Clone, Default)]
+    struct SlowSink {
+        events: Arc<Mutex<Vec<Event>>>,
+        in_flight: Arc<AtomicUsize>,
+        max_in_flight: Arc<AtomicUsize>,
+    }
+
+    impl EventSink for SlowSink {
+        fn put_event(&self, event: &Event) -> Result<(), StoreError> {
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(5));
+            self.events.lock().unwrap().push(event.clone());
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn worker_pool_should_write_each_event_once() {
+        let inner = SlowSink::default();
+        let logger = Logger::root(slog::Discard, o!());
+        let sink = ChannelSink::with_workers(inner.clone(), 8, DropPolicy::Block, 4, logger);
+
+        let event = wind_event();
+        for i in 0..40 {
+            let mut event = event.clone();
+            event.event_ts += i;
+            sink.put_event(&event).unwrap();
+        }
+        drop(sink);
+
+        let events = inner.events.lock().unwrap();
+        let distinct: HashSet<u64> = events.iter().map(|x| x.event_ts).collect();
+        assert_eq!(events.len(), 40);
+        assert_eq!(distinct.len(), 40);
+        assert!(inner.max_in_flight.load(Ordering::SeqCst) > 1);
+    }
+
+    #[test]
+    fn drop_oldest_should_not_write_events_twice_behind_a_producer() {
+        let inner = SlowSink::default();
+        let drain = CapturingDrain::default();
+        let logger = Logger::root(drain.clone(), o!());
+        let sink = ChannelSink::new(inner.clone(), 1, DropPolicy::DropOldest, logger);
+        let fetcher = MockFetcher::new(vec![wind_reports(20), wind_reports(20)]);
+        let mut producer = Producer::new(Config::default(), fetcher, sink, test_logger());
+
+        // Every event was queued, so none are dead-lettered and retried on the second poll
+        assert_eq!(producer.poll_once().stored, 20);
+        assert_eq!(producer.poll_once().stored, 0);
+        drop(producer);
+
+        let events = inner.events.lock().unwrap();
+        let distinct: HashSet<u64> = events.iter().map(|x| x.event_ts).collect();
+        let messages = drain.messages.lock().unwrap();
+        let evicted = messages
+            .iter()
+            .filter(|x| *x == "event channel full, evicted oldest event")
+            .count();
+        assert_eq!(distinct.len(), events.len());
+        assert_eq!(events.len() + evicted, 20);
+    }
+
+    #[test]
+    fn write_failures_should_reach_the_producer() {
+        let config = Config {
+            exit_on_store_unavailable: true,
+            store_failure_threshold: 3,
+            ..Config::default()
+        };
+        let inner = FailingSink {
+            reason: "store down",
+        };
+        let sink = ChannelSink::new(inner, 8, DropPolicy::Block, test_logger());
+        let fetcher = MockFetcher::new(vec![wind_reports(3)]);
+        let mut producer = Producer::new(config, fetcher, sink, test_logger());
+
+        // Queued events are accepted, and their failures show up on a later poll
+        let mut result = producer.poll_once();
+        assert_eq!(result.stored, 3);
+        let mut store_failures = result.store_failures;
+
+        for _ in 0..100 {
+            if result.store_unavailable {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+            result = producer.poll_once();
+            store_failures += result.store_failures;
+        }
+
+        assert!(result.store_unavailable);
+        assert!(result.dead_letters > 0);
+        assert!(store_failures >= 3);
+    }
+}
@@ -0,0 +1,76 @@
+use crate::{content_hash, normalize_line};
+use indexmap::IndexMap;
+
+/**
+ * Reports that differ between two feed snapshots, matched by `content_hash` so an edited report
+ * shows up as changed rather than removed and added. Lines are normalized, so a report that only
+ * aged between snapshots is unchanged.
+ */
+#[derive(Debug, Default, PartialEq)]
+pub struct FeedDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// Reports with the same identity but different content, e.g. edited notes, as `(old, new)`.
+    pub changed: Vec<(String, String)>,
+}
+
+/// Compares two captured feed bodies, keeping each snapshot's order.
+#[must_use]
+pub fn diff_feeds(old: &str, new: &str) -> FeedDiff {
+    let old = index_reports(old);
+    let mut new = index_reports(new);
+    let mut diff = FeedDiff::default();
+
+    for (hash, old_line) in old {
+        match new.shift_remove(&hash) {
+            Some(new_line) if new_line != old_line => diff.changed.push((old_line, new_line)),
+            Some(_) => {}
+            None => diff.removed.push(old_line),
+        }
+    }
+
+    diff.added = new.into_iter().map(|(_, line)| line).collect();
+    diff
+}
+
+/// Normalized icon lines by identity, keeping the first of any that share one.
+fn index_reports(body: &str) -> IndexMap<u64, String> {
+    let mut reports = IndexMap::new();
+
+    for line in body.lines().filter(|x| x.starts_with("Icon:")) {
+        let report = normalize_line(line);
+        reports
+            .entry(content_hash(&report))
+            .or_insert_with(|| report.into_owned());
+    }
+
+    reports
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn diff_feeds_should_find_added_removed_and_changed_reports() {
+        let old = fs::read_to_string("data/reports-diff-old").unwrap();
+        let new = fs::read_to_string("data/reports-diff-new").unwrap();
+        let diff = diff_feeds(&old, &new);
+
+        assert_eq!(diff.added.len(), 1);
+        assert!(diff.added[0].contains("Time: 2018-09-20 23:02:00 UTC"));
+        assert_eq!(diff.removed.len(), 1);
+        assert!(diff.removed[0].contains("Rotating Wall Cloud"));
+        assert_eq!(diff.changed.len(), 1);
+        assert!(diff.changed[0].0.ends_with(r#"US 18""#));
+        assert!(diff.changed[0].1.ends_with(r#"road now closed""#));
+    }
+
+    #[test]
+    fn diff_feeds_should_be_empty_for_identical_feeds() {
+        let body = fs::read_to_string("data/reports").unwrap();
+
+        assert_eq!(diff_feeds(&body, &body), FeedDiff::default());
+    }
+}
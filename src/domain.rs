@@ -1,7 +1,16 @@
-use wx::domain::HazardType;
+use serde::de::{self, Deserialize, Deserializer, Unexpected, Visitor};
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+use wx::domain::{Coordinates, Event, HazardType, Report, Units};
 use wx::error::{Error, WxError};
 
-#[derive(Deserialize, Eq, PartialEq, Serialize, Clone)]
+/**
+ * Serializes as the variant name, but also deserializes from the legacy integer discriminant.
+ * The discriminants are NOT feed codes: `Tornado` is discriminant 0 but feed code `"1"`, so
+ * always go through `get_by_code` for feed values.
+ */
+#[derive(Debug, Eq, PartialEq, Serialize, Clone)]
 pub enum Hazard {
     Tornado = 0isize,
     Funnel,
@@ -15,7 +24,41 @@ pub enum Hazard {
     Snow,
 }
 
+/// Every code `Hazard::get_by_code` understands.
+pub const KNOWN_HAZARD_CODES: [&str; 10] = ["1", "2", "3", "4", "5", "6", "7", "8", "9", "10"];
+
+/// In feed code order, which is also discriminant order.
+const ALL_HAZARDS: [Hazard; 10] = [
+    Hazard::Tornado,
+    Hazard::Funnel,
+    Hazard::WallCloud,
+    Hazard::Hail,
+    Hazard::Wind,
+    Hazard::Flood,
+    Hazard::FlashFlood,
+    Hazard::Other,
+    Hazard::FreezingRain,
+    Hazard::Snow,
+];
+
 impl Hazard {
+    /// Every hazard in feed code order, e.g. to list `code()` and `to_string()` pairs in a UI.
+    #[must_use]
+    pub fn all() -> &'static [Hazard] {
+        &ALL_HAZARDS
+    }
+
+    /// The feed code `get_by_code` maps to this hazard.
+    #[must_use]
+    pub fn code(&self) -> &'static str {
+        KNOWN_HAZARD_CODES[self.clone() as usize]
+    }
+
+    /**
+     * Maps a feed hazard code, `"1"` (tornado) through `"10"` (snow). Spotter Network has never
+     * sent `"0"`, so it's rejected like any other unknown code rather than guessed at, and the
+     * report is counted as a parse error.
+     */
     pub fn get_by_code(code: &str) -> Result<Hazard, Error> {
         match code {
             "1" => Ok(Hazard::Tornado),
@@ -28,6 +71,10 @@ impl Hazard {
             "8" => Ok(Hazard::Other),
             "9" => Ok(Hazard::FreezingRain),
             "10" => Ok(Hazard::Snow),
+            "0" => {
+                let reason = "unknown code: 0, feed codes start at 1";
+                Err(Error::Wx(<WxError>::new(reason)))
+            }
             _ => {
                 let reason = format!("unknown code: {}", code.to_string());
                 Err(Error::Wx(<WxError>::new(&reason)))
@@ -35,6 +82,7 @@ impl Hazard {
         }
     }
 
+    #[must_use]
     pub fn to_hazard_type(&self) -> HazardType {
         match self {
             Hazard::Tornado => HazardType::Tornado,
@@ -50,6 +98,23 @@ impl Hazard {
         }
     }
 
+    /// Higher is more severe. Ranks are unique, so this is also the `Ord` for `Hazard`.
+    #[must_use]
+    pub fn severity_rank(&self) -> u8 {
+        match self {
+            Hazard::Tornado => 9,
+            Hazard::Funnel => 8,
+            Hazard::WallCloud => 7,
+            Hazard::FlashFlood => 6,
+            Hazard::Hail => 5,
+            Hazard::Wind => 4,
+            Hazard::Flood => 3,
+            Hazard::Snow => 2,
+            Hazard::FreezingRain => 1,
+            Hazard::Other => 0,
+        }
+    }
+
     pub fn to_string(&self) -> String {
         match self {
             Hazard::Tornado => "Tornado",
@@ -66,3 +131,615 @@ impl Hazard {
         .to_string()
     }
 }
+
+/// Parses the display name from `to_string`, e.g. `Wall Cloud`.
+impl FromStr for Hazard {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Hazard, Error> {
+        match ALL_HAZARDS.iter().find(|x| x.to_string() == name) {
+            Some(hazard) => Ok(hazard.clone()),
+            None => {
+                let reason = format!("unknown hazard name: {}", name);
+                Err(Error::Wx(<WxError>::new(&reason)))
+            }
+        }
+    }
+}
+
+impl PartialOrd for Hazard {
+    fn partial_cmp(&self, other: &Hazard) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Hazard {
+    fn cmp(&self, other: &Hazard) -> Ordering {
+        self.severity_rank().cmp(&other.severity_rank())
+    }
+}
+
+/// A coarser grouping than `Hazard` by physical mechanism, e.g. for dashboards.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum EventCategory {
+    Convective,
+    Precipitation,
+    FloodHydro,
+    WinterWeather,
+    Other,
+}
+
+impl fmt::Display for EventCategory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            EventCategory::Convective => "Convective",
+            EventCategory::Precipitation => "Precipitation",
+            EventCategory::FloodHydro => "Flood/Hydro",
+            EventCategory::WinterWeather => "Winter Weather",
+            EventCategory::Other => "Other",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/**
+ * Hail counts as convective since it only falls from thunderstorms. Freezing rain is plain
+ * precipitation that freezes on contact and shows up well outside of winter storms, so it isn't
+ * lumped in with snow.
+ */
+#[must_use]
+pub fn category_for_hazard(hazard: &Hazard) -> EventCategory {
+    match hazard {
+        Hazard::Tornado | Hazard::Funnel | Hazard::WallCloud | Hazard::Hail | Hazard::Wind => {
+            EventCategory::Convective
+        }
+        Hazard::FreezingRain => EventCategory::Precipitation,
+        Hazard::Flood | Hazard::FlashFlood => EventCategory::FloodHydro,
+        Hazard::Snow => EventCategory::WinterWeather,
+        Hazard::Other => EventCategory::Other,
+    }
+}
+
+/**
+ * A lat/lon box with inclusive edges. If `min_lon` is greater than `max_lon` the box is treated as
+ * wrapping across the anti-meridian, e.g. `min_lon: 160.0, max_lon: -170.0` spans 30 degrees.
+ */
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct BoundingBox {
+    pub min_lat: f32,
+    pub max_lat: f32,
+    pub min_lon: f32,
+    pub max_lon: f32,
+}
+
+impl BoundingBox {
+    #[must_use]
+    pub fn contains(&self, point: &Coordinates) -> bool {
+        let lat_inside = point.lat >= self.min_lat && point.lat <= self.max_lat;
+
+        let lon_inside = if self.min_lon <= self.max_lon {
+            point.lon >= self.min_lon && point.lon <= self.max_lon
+        } else {
+            point.lon >= self.min_lon || point.lon <= self.max_lon
+        };
+
+        lat_inside && lon_inside
+    }
+}
+
+/// Mean radius, close enough for distances of a few kilometers.
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// A circle around a known source of junk reports, like a radar site or test rig.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ExclusionZone {
+    pub lat: f32,
+    pub lon: f32,
+    pub radius_km: f32,
+}
+
+impl ExclusionZone {
+    /// Whether the point is within `radius_km` of the zone's center, inclusive.
+    #[must_use]
+    pub fn contains(&self, point: &Coordinates) -> bool {
+        let center = Coordinates {
+            lat: self.lat,
+            lon: self.lon,
+        };
+        distance_km(&center, point) <= f64::from(self.radius_km)
+    }
+}
+
+/// Great-circle distance using the haversine formula.
+#[must_use]
+pub fn distance_km(a: &Coordinates, b: &Coordinates) -> f64 {
+    let (lat_a, lat_b) = (f64::from(a.lat).to_radians(), f64::from(b.lat).to_radians());
+    let d_lat = lat_b - lat_a;
+    let d_lon = (f64::from(b.lon) - f64::from(a.lon)).to_radians();
+    let h = (d_lat / 2.0).sin().powi(2) + lat_a.cos() * lat_b.cos() * (d_lon / 2.0).sin().powi(2);
+
+    2.0 * EARTH_RADIUS_KM * h.sqrt().asin()
+}
+
+/// Rounds a point to `decimals` places, e.g. 2 for roughly 1km precision.
+pub fn round_coordinates(point: &mut Coordinates, decimals: u32) {
+    let scale = 10f64.powi(decimals as i32);
+    let round = |x: f32| ((f64::from(x) * scale).round() / scale) as f32;
+
+    point.lat = round(point.lat);
+    point.lon = round(point.lon);
+}
+
+/**
+ * A report magnitude together with its units. `wx::domain::Report` keeps these in two separate
+ * `Option`s that can disagree, so build reports through `apply` and read them with `from_report`.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Magnitude {
+    Mph(f64),
+    Knots(f64),
+    Inches(f64),
+}
+
+impl Magnitude {
+    /// Reads a report's magnitude, or `None` unless both the value and units are set.
+    #[must_use]
+    pub fn from_report(report: &Report) -> Option<Magnitude> {
+        match (report.magnitude, report.units.as_ref()?) {
+            (Some(value), Units::Mph) => Some(Magnitude::Mph(value)),
+            (Some(value), Units::Knots) => Some(Magnitude::Knots(value)),
+            (Some(value), Units::Inches) => Some(Magnitude::Inches(value)),
+            (None, _) => None,
+        }
+    }
+
+    /// Sets both of a report's magnitude fields.
+    pub fn apply(self, report: &mut Report) {
+        report.magnitude = Some(self.value());
+        report.units = Some(self.units());
+    }
+
+    #[must_use]
+    pub fn value(self) -> f64 {
+        match self {
+            Magnitude::Mph(value) | Magnitude::Knots(value) | Magnitude::Inches(value) => value,
+        }
+    }
+
+    #[must_use]
+    pub fn units(self) -> Units {
+        match self {
+            Magnitude::Mph(_) => Units::Mph,
+            Magnitude::Knots(_) => Units::Knots,
+            Magnitude::Inches(_) => Units::Inches,
+        }
+    }
+}
+
+/// Hail to two decimals with an inch mark, e.g. `0.75"`, and wind as a whole number, e.g. `60 mph`.
+impl fmt::Display for Magnitude {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Magnitude::Mph(mph) => write!(f, "{} mph", mph.round()),
+            Magnitude::Knots(knots) => write!(f, "{} kt", knots.round()),
+            Magnitude::Inches(inches) => write!(f, "{:.2}\"", inches),
+        }
+    }
+}
+
+/**
+ * Orders events by `event_ts` so they can go in a `BinaryHeap`, wrapping `Event` since it's
+ * defined in `wx`. Equality is also by `event_ts` alone to stay consistent with `Ord`, which
+ * means two different events reported at the same second compare equal here even though
+ * `Event`'s own `PartialEq` says otherwise. Wrap in `Reverse` to pop the oldest first.
+ */
+pub struct ByEventTs(pub Event);
+
+impl PartialEq for ByEventTs {
+    fn eq(&self, other: &ByEventTs) -> bool {
+        self.0.event_ts == other.0.event_ts
+    }
+}
+
+impl Eq for ByEventTs {}
+
+impl PartialOrd for ByEventTs {
+    fn partial_cmp(&self, other: &ByEventTs) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ByEventTs {
+    fn cmp(&self, other: &ByEventTs) -> Ordering {
+        self.0.event_ts.cmp(&other.0.event_ts)
+    }
+}
+
+const HAZARD_VARIANTS: &[&str] = &[
+    "Tornado",
+    "Funnel",
+    "WallCloud",
+    "Hail",
+    "Wind",
+    "Flood",
+    "FlashFlood",
+    "Other",
+    "FreezingRain",
+    "Snow",
+];
+
+impl<'de> Deserialize<'de> for Hazard {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Hazard, D::Error> {
+        deserializer.deserialize_any(HazardVisitor)
+    }
+}
+
+struct HazardVisitor;
+
+impl<'de> Visitor<'de> for HazardVisitor {
+    type Value = Hazard;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a hazard name or discriminant")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Hazard, E> {
+        match value {
+            "Tornado" => Ok(Hazard::Tornado),
+            "Funnel" => Ok(Hazard::Funnel),
+            "WallCloud" => Ok(Hazard::WallCloud),
+            "Hail" => Ok(Hazard::Hail),
+            "Wind" => Ok(Hazard::Wind),
+            "Flood" => Ok(Hazard::Flood),
+            "FlashFlood" => Ok(Hazard::FlashFlood),
+            "Other" => Ok(Hazard::Other),
+            "FreezingRain" => Ok(Hazard::FreezingRain),
+            "Snow" => Ok(Hazard::Snow),
+            _ => Err(E::unknown_variant(value, HAZARD_VARIANTS)),
+        }
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Hazard, E> {
+        match value {
+            0 => Ok(Hazard::Tornado),
+            1 => Ok(Hazard::Funnel),
+            2 => Ok(Hazard::WallCloud),
+            3 => Ok(Hazard::Hail),
+            4 => Ok(Hazard::Wind),
+            5 => Ok(Hazard::Flood),
+            6 => Ok(Hazard::FlashFlood),
+            7 => Ok(Hazard::Other),
+            8 => Ok(Hazard::FreezingRain),
+            9 => Ok(Hazard::Snow),
+            _ => Err(E::invalid_value(Unexpected::Unsigned(value), &self)),
+        }
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Hazard, E> {
+        if value < 0 {
+            return Err(E::invalid_value(Unexpected::Signed(value), &self));
+        }
+
+        self.visit_u64(value as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::WIND_REPORT;
+    use std::cmp::Reverse;
+    use std::collections::{BinaryHeap, HashSet};
+    use wx::domain::EventType;
+
+    const PLAINS: BoundingBox = BoundingBox {
+        min_lat: 35.0,
+        max_lat: 45.0,
+        min_lon: -105.0,
+        max_lon: -95.0,
+    };
+
+    fn point(lat: f32, lon: f32) -> Coordinates {
+        Coordinates { lat, lon }
+    }
+
+    #[test]
+    fn distance_km_should_match_known_distance() {
+        // Oklahoma City to Tulsa is about 160km
+        let distance = distance_km(&point(35.4676, -97.5164), &point(36.154, -95.9928));
+        assert!((distance - 158.0).abs() < 2.0, "{}", distance);
+        assert_eq!(distance_km(&point(35.0, -97.0), &point(35.0, -97.0)), 0.0);
+    }
+
+    #[test]
+    fn exclusion_zone_should_contain_points_within_radius() {
+        // KTLX, with 0.01 degrees of latitude about 1.1km
+        let zone = ExclusionZone {
+            lat: 35.3331,
+            lon: -97.2778,
+            radius_km: 1.0,
+        };
+
+        assert!(zone.contains(&point(35.3331, -97.2778)));
+        assert!(zone.contains(&point(35.3411, -97.2778)));
+        assert!(!zone.contains(&point(35.3431, -97.2778)));
+    }
+
+    #[test]
+    fn all_should_round_trip_each_hazard_through_code_and_name() {
+        assert_eq!(Hazard::all().len(), 10);
+
+        for hazard in Hazard::all() {
+            assert!(Hazard::get_by_code(hazard.code()).unwrap() == *hazard);
+            assert!(hazard.to_string().parse::<Hazard>().unwrap() == *hazard);
+        }
+
+        let codes: Vec<&str> = Hazard::all().iter().map(Hazard::code).collect();
+        assert_eq!(codes, KNOWN_HAZARD_CODES);
+        assert!("Hurricane".parse::<Hazard>().is_err());
+    }
+
+    #[test]
+    fn known_hazard_codes_should_all_parse() {
+        for code in KNOWN_HAZARD_CODES.iter() {
+            assert!(Hazard::get_by_code(code).is_ok());
+        }
+        assert!(Hazard::get_by_code("11").is_err());
+    }
+
+    #[test]
+    fn every_category_should_have_a_hazard() {
+        let categories: HashSet<EventCategory> =
+            ALL_HAZARDS.iter().map(category_for_hazard).collect();
+
+        assert_eq!(categories.len(), 5);
+        assert_eq!(
+            category_for_hazard(&Hazard::Hail),
+            EventCategory::Convective
+        );
+        assert_eq!(
+            category_for_hazard(&Hazard::FlashFlood),
+            EventCategory::FloodHydro
+        );
+        assert_eq!(
+            category_for_hazard(&Hazard::Snow),
+            EventCategory::WinterWeather
+        );
+    }
+
+    #[test]
+    fn event_category_should_display_readable_names() {
+        assert_eq!(EventCategory::FloodHydro.to_string(), "Flood/Hydro");
+        assert_eq!(EventCategory::WinterWeather.to_string(), "Winter Weather");
+    }
+
+    #[test]
+    fn hazard_ord_should_be_antisymmetric() {
+        for a in ALL_HAZARDS.iter() {
+            for b in ALL_HAZARDS.iter() {
+                if a < b {
+                    assert!(!b.lt(a));
+                }
+                assert_eq!(a.cmp(b) == Ordering::Equal, a == b);
+            }
+        }
+    }
+
+    #[test]
+    fn hazard_ord_should_be_transitive() {
+        for a in ALL_HAZARDS.iter() {
+            for b in ALL_HAZARDS.iter() {
+                for c in ALL_HAZARDS.iter() {
+                    if a < b && b < c {
+                        assert!(a < c);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tornado_should_be_most_and_other_least_severe() {
+        for hazard in ALL_HAZARDS.iter() {
+            if *hazard != Hazard::Tornado {
+                assert!(Hazard::Tornado > *hazard);
+            }
+            if *hazard != Hazard::Other {
+                assert!(Hazard::Other < *hazard);
+            }
+        }
+    }
+
+    fn event_at(event_ts: u64, title: &str) -> ByEventTs {
+        ByEventTs(Event {
+            event_ts,
+            event_type: EventType::SnReport,
+            expires_ts: None,
+            ext_uri: None,
+            ingest_ts: 0,
+            location: None,
+            md: None,
+            outlook: None,
+            report: None,
+            text: None,
+            title: title.to_string(),
+            valid_ts: None,
+            warning: None,
+            watch: None,
+        })
+    }
+
+    #[test]
+    fn by_event_ts_should_order_consistently() {
+        let earlier = event_at(1, "a");
+        let later = event_at(2, "b");
+
+        assert!(earlier < later);
+        assert!(!later.lt(&earlier));
+        assert_eq!(earlier.cmp(&later), Ordering::Less);
+        assert_eq!(later.cmp(&earlier), Ordering::Greater);
+    }
+
+    #[test]
+    fn by_event_ts_should_treat_same_timestamp_as_equal() {
+        let first = event_at(1, "a");
+        let second = event_at(1, "b");
+
+        assert_eq!(first.cmp(&second), Ordering::Equal);
+        assert!(first == second);
+        assert!(first.0 != second.0);
+    }
+
+    #[test]
+    fn by_event_ts_should_pop_oldest_first_when_reversed() {
+        let mut heap = BinaryHeap::new();
+        heap.push(Reverse(event_at(3, "c")));
+        heap.push(Reverse(event_at(1, "a")));
+        heap.push(Reverse(event_at(2, "b")));
+
+        let titles: Vec<String> = std::iter::from_fn(|| heap.pop())
+            .map(|Reverse(x)| x.0.title)
+            .collect();
+        assert_eq!(titles, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn bounding_box_should_contain_inside_point() {
+        assert!(PLAINS.contains(&point(40.0, -100.0)));
+    }
+
+    #[test]
+    fn bounding_box_should_not_contain_outside_points() {
+        assert!(!PLAINS.contains(&point(50.0, -100.0)));
+        assert!(!PLAINS.contains(&point(30.0, -100.0)));
+        assert!(!PLAINS.contains(&point(40.0, -110.0)));
+        assert!(!PLAINS.contains(&point(40.0, -90.0)));
+    }
+
+    #[test]
+    fn bounding_box_edges_should_be_inclusive() {
+        assert!(PLAINS.contains(&point(35.0, -100.0)));
+        assert!(PLAINS.contains(&point(45.0, -100.0)));
+        assert!(PLAINS.contains(&point(40.0, -105.0)));
+        assert!(PLAINS.contains(&point(40.0, -95.0)));
+    }
+
+    #[test]
+    fn bounding_box_corners_should_be_inclusive() {
+        assert!(PLAINS.contains(&point(35.0, -105.0)));
+        assert!(PLAINS.contains(&point(35.0, -95.0)));
+        assert!(PLAINS.contains(&point(45.0, -105.0)));
+        assert!(PLAINS.contains(&point(45.0, -95.0)));
+    }
+
+    #[test]
+    fn bounding_box_should_wrap_across_anti_meridian() {
+        let aleutians = BoundingBox {
+            min_lat: 50.0,
+            max_lat: 55.0,
+            min_lon: 160.0,
+            max_lon: -170.0,
+        };
+
+        assert!(aleutians.contains(&point(52.0, 170.0)));
+        assert!(aleutians.contains(&point(52.0, 180.0)));
+        assert!(aleutians.contains(&point(52.0, -180.0)));
+        assert!(aleutians.contains(&point(52.0, -175.0)));
+        assert!(aleutians.contains(&point(52.0, -170.0)));
+        assert!(aleutians.contains(&point(52.0, 160.0)));
+        assert!(!aleutians.contains(&point(52.0, -165.0)));
+        assert!(!aleutians.contains(&point(52.0, 155.0)));
+        assert!(!aleutians.contains(&point(52.0, 0.0)));
+        assert!(!aleutians.contains(&point(60.0, -175.0)));
+    }
+
+    #[test]
+    fn hazard_should_round_trip_through_json() {
+        for hazard in ALL_HAZARDS.iter() {
+            let json = serde_json::to_string(hazard).unwrap();
+            let deserialized: Hazard = serde_json::from_str(&json).unwrap();
+            assert!(deserialized == *hazard, "{}", json);
+        }
+    }
+
+    #[test]
+    fn hazard_should_serialize_as_variant_name() {
+        assert_eq!(
+            serde_json::to_string(&Hazard::WallCloud).unwrap(),
+            "\"WallCloud\""
+        );
+    }
+
+    #[test]
+    fn hazard_should_deserialize_from_legacy_discriminant() {
+        for (discriminant, hazard) in ALL_HAZARDS.iter().enumerate() {
+            let deserialized: Hazard = serde_json::from_str(&discriminant.to_string()).unwrap();
+            assert!(deserialized == *hazard);
+        }
+
+        let tornado: Hazard = serde_json::from_str("0").unwrap();
+        assert!(tornado == Hazard::Tornado);
+    }
+
+    #[test]
+    fn hazard_should_reject_unknown_values() {
+        assert!(serde_json::from_str::<Hazard>("10").is_err());
+        assert!(serde_json::from_str::<Hazard>("-1").is_err());
+        assert!(serde_json::from_str::<Hazard>("\"Hurricane\"").is_err());
+    }
+
+    fn parse_magnitude(report: &str) -> Option<Magnitude> {
+        let event = crate::parser::ReportParser::new()
+            .parse(report)
+            .unwrap()
+            .unwrap();
+        Magnitude::from_report(event.report.as_ref().unwrap())
+    }
+
+    #[test]
+    fn wind_report_should_have_mph_magnitude() {
+        assert_eq!(parse_magnitude(WIND_REPORT), Some(Magnitude::Mph(60.0)));
+    }
+
+    #[test]
+    fn hail_report_should_have_inches_magnitude() {
+        let report = r#"Icon: 47.617706,-111.215248,000,4,4,"Reported By: Test Human\nHail\nTime: 2018-09-20 22:49:29 UTC\nSize: 0.75" (Penny)\nNotes: None""#;
+        assert_eq!(parse_magnitude(report), Some(Magnitude::Inches(0.75)));
+    }
+
+    #[test]
+    fn magnitude_should_display_consistently() {
+        assert_eq!(Magnitude::Inches(0.75).to_string(), "0.75\"");
+        assert_eq!(Magnitude::Inches(1.0).to_string(), "1.00\"");
+        assert_eq!(Magnitude::Inches(1.968_503_9).to_string(), "1.97\"");
+        assert_eq!(Magnitude::Mph(60.0).to_string(), "60 mph");
+        assert_eq!(Magnitude::Mph(60.5).to_string(), "61 mph");
+        assert_eq!(Magnitude::Knots(52.1).to_string(), "52 kt");
+    }
+
+    #[test]
+    fn magnitude_should_ignore_half_populated_reports() {
+        let mut report = Report {
+            hazard: HazardType::Wind,
+            magnitude: Some(60.0),
+            report_ts: None,
+            reporter: "Test Human".to_string(),
+            units: None,
+            was_measured: None,
+        };
+        assert_eq!(Magnitude::from_report(&report), None);
+
+        report.magnitude = None;
+        report.units = Some(Units::Mph);
+        assert_eq!(Magnitude::from_report(&report), None);
+
+        Magnitude::Knots(50.0).apply(&mut report);
+        assert!(report.magnitude == Some(50.0));
+        assert!(report.units == Some(Units::Knots));
+        assert_eq!(
+            Magnitude::from_report(&report),
+            Some(Magnitude::Knots(50.0))
+        );
+    }
+}
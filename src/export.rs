@@ -0,0 +1,177 @@
+use crate::producer::DryRunEvent;
+use geojson::{Feature, FeatureCollection, Geometry, JsonObject, Value};
+use std::collections::BTreeMap;
+use wx::domain::Event;
+
+/**
+ * Converts to a GeoJSON `Feature`, wrapping `Event` since neither it nor `Feature` are defined in
+ * this crate. The geometry is the report's point, or null if it has none, and the properties are
+ * `event_ts`, `title`, every report field that's set, and `raw_captures` if there are any.
+ */
+pub struct GeoJsonEvent {
+    pub event: Event,
+    /// The report's raw capture groups, see `ParseOptions::include_raw_captures`.
+    pub raw_captures: Option<BTreeMap<String, String>>,
+}
+
+impl From<Event> for GeoJsonEvent {
+    fn from(event: Event) -> GeoJsonEvent {
+        GeoJsonEvent {
+            event,
+            raw_captures: None,
+        }
+    }
+}
+
+impl From<DryRunEvent> for GeoJsonEvent {
+    fn from(event: DryRunEvent) -> GeoJsonEvent {
+        GeoJsonEvent {
+            event: event.event,
+            raw_captures: event.raw_captures,
+        }
+    }
+}
+
+impl From<GeoJsonEvent> for Feature {
+    fn from(event: GeoJsonEvent) -> Feature {
+        let raw_captures = event.raw_captures;
+        let event = event.event;
+        let geometry = event
+            .location
+            .as_ref()
+            .and_then(|x| x.point.as_ref())
+            // RFC 7946 positions are [longitude, latitude]
+            .map(|point| Geometry::new(Value::Point(vec![point.lon.into(), point.lat.into()])));
+
+        let mut properties = JsonObject::new();
+        properties.insert("event_ts".to_string(), event.event_ts.into());
+        properties.insert("title".to_string(), event.title.into());
+
+        if let Some(serde_json::Value::Object(report)) = event
+            .report
+            .map(|x| serde_json::to_value(x).unwrap_or_default())
+        {
+            properties.extend(report.into_iter().filter(|(_, value)| !value.is_null()));
+        }
+
+        if let Some(raw_captures) = raw_captures {
+            let raw_captures = serde_json::to_value(raw_captures).unwrap_or_default();
+            properties.insert("raw_captures".to_string(), raw_captures);
+        }
+
+        Feature {
+            bbox: None,
+            geometry,
+            id: None,
+            properties: Some(properties),
+            foreign_members: None,
+        }
+    }
+}
+
+/// One feature per event, e.g. for a poll's events to paste into geojson.io.
+pub fn feature_collection<T, I>(events: I) -> FeatureCollection
+where
+    T: Into<GeoJsonEvent>,
+    I: IntoIterator<Item = T>,
+{
+    FeatureCollection {
+        bbox: None,
+        features: events
+            .into_iter()
+            .map(|event| Feature::from(event.into()))
+            .collect(),
+        foreign_members: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{ParseOptions, ReportParser};
+    use crate::test_util::{wind_event, WIND_REPORT};
+
+    #[test]
+    fn feature_should_use_lon_lat_order() {
+        let feature = Feature::from(GeoJsonEvent::from(wind_event()));
+
+        match feature.geometry.unwrap().value {
+            Value::Point(position) => {
+                assert!((position[0] - -94.639999).abs() < 1e-4);
+                assert!((position[1] - 43.112).abs() < 1e-4);
+            }
+            _ => panic!("expected a point"),
+        }
+    }
+
+    #[test]
+    fn feature_should_include_set_report_fields() {
+        let feature = Feature::from(GeoJsonEvent::from(wind_event()));
+        let properties = feature.properties.unwrap();
+
+        assert_eq!(properties["event_ts"], 1_537_483_920_000_000u64);
+        assert_eq!(properties["title"], "Report: 60 mph Wind");
+        assert_eq!(properties["hazard"], "Wind");
+        assert_eq!(properties["magnitude"], 60.0);
+        assert_eq!(properties["reporter"], "Test Human");
+        assert_eq!(properties["units"], "Mph");
+        assert_eq!(properties["was_measured"], true);
+        // Only set when the feed has a posted time, so left out rather than null
+        assert!(!properties.contains_key("report_ts"));
+        assert!(!properties.contains_key("raw_captures"));
+    }
+
+    #[test]
+    fn feature_should_include_raw_captures_when_set() {
+        let options = ParseOptions {
+            include_raw_captures: true,
+            ..ParseOptions::default()
+        };
+        let parsed = ReportParser::new().parse_report_with_options(WIND_REPORT, &options);
+        let parsed = parsed.unwrap().unwrap();
+        let feature = Feature::from(GeoJsonEvent {
+            event: parsed.to_event(),
+            raw_captures: parsed.raw_captures,
+        });
+        let properties = feature.properties.unwrap();
+
+        assert_eq!(properties["raw_captures"]["mph"], "60");
+        assert_eq!(properties["raw_captures"]["label"], "High Wind");
+        assert_eq!(properties["title"], "Report: 60 mph Wind");
+    }
+
+    #[test]
+    fn feature_collection_should_be_valid_geojson_with_a_feature_per_event() {
+        let report = r#"Icon: 47.617706,-111.215248,000,0,4,"Reported By: Test User\nHail\nTime: 2018-09-20 22:49:29 UTC\nSize: 0.75" (Penny)\nNotes: None""#;
+        let hail_event = ReportParser::new().parse(report).unwrap().unwrap();
+        let collection = feature_collection(vec![wind_event(), hail_event]);
+
+        let parsed: geojson::GeoJson = collection.to_string().parse().unwrap();
+        let features = match parsed {
+            geojson::GeoJson::FeatureCollection(collection) => collection.features,
+            _ => panic!("expected a feature collection"),
+        };
+        assert_eq!(features.len(), 2);
+
+        match &features[1].geometry.as_ref().unwrap().value {
+            Value::Point(position) => {
+                assert!((position[0] - -111.215248).abs() < 1e-4);
+                assert!((position[1] - 47.617706).abs() < 1e-4);
+            }
+            _ => panic!("expected a point"),
+        }
+        let properties = features[1].properties.as_ref().unwrap();
+        assert_eq!(properties["hazard"], "Hail");
+        assert_eq!(properties["magnitude"], 0.75);
+    }
+
+    #[test]
+    fn feature_should_have_null_geometry_without_point() {
+        let mut event = wind_event();
+        event.location = None;
+        let feature = Feature::from(GeoJsonEvent::from(event));
+
+        assert!(feature.geometry.is_none());
+        assert!(feature.to_string().contains(r#""geometry":null"#));
+    }
+}
@@ -0,0 +1,1414 @@
+#[macro_use]
+extern crate lazy_static;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate slog;
+extern crate serde_json;
+
+#[cfg(feature = "async")]
+pub mod asynchronous;
+pub mod bloom;
+pub mod channel;
+pub mod diff;
+pub mod domain;
+pub mod export;
+pub mod metrics;
+#[cfg(feature = "nats")]
+pub mod nats;
+pub mod parser;
+pub mod producer;
+pub mod random;
+pub mod schema;
+pub mod sink;
+pub mod state;
+#[cfg(test)]
+mod test_util;
+pub mod wakeup;
+
+use self::bloom::BloomFilter;
+use self::channel::DropPolicy;
+use self::domain::{BoundingBox, ExclusionZone, Hazard};
+use self::producer::Fetcher;
+use self::sink::Encoding;
+use chrono::prelude::*;
+use fnv::{FnvBuildHasher, FnvHashSet, FnvHasher};
+use indexmap::IndexSet;
+use regex::Regex;
+use reqwest::{header, Client, RequestBuilder, StatusCode};
+use serde::Serializer;
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::net::{IpAddr, Ipv6Addr};
+use std::time::{Duration, Instant};
+use wx::error::{Error, WxError};
+
+/// Normalized report lines, iterated in the order they appeared in the feed.
+pub type ReportSet = IndexSet<String, FnvBuildHasher>;
+
+/// Reports already seen, by `content_hash`.
+pub type SeenSet = FnvHashSet<u64>;
+
+#[derive(Debug)]
+pub struct Comparison {
+    pub icon_lines: usize,
+    /// Icon lines identical to an earlier line in the body, age digit included.
+    pub exact_duplicates: usize,
+    pub latest_set: ReportSet,
+    /// `content_hash` of each report in `latest_set`.
+    pub latest_hashes: SeenSet,
+    pub new: Vec<String>,
+    /// Hash of each report in `new`, in the same order.
+    pub new_hashes: Vec<u64>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub enum Output {
+    Store,
+    Stdout,
+    File(String),
+    /// Path of a Unix domain socket to write newline-delimited events to.
+    #[cfg(unix)]
+    UnixSocket(String),
+    /// Address of a NATS server and the subject to publish events to.
+    #[cfg(feature = "nats")]
+    Nats {
+        address: String,
+        subject: String,
+    },
+}
+
+/**
+ * Settings come from defaults, then an optional TOML file, then environment variables, with each
+ * layer overriding the one before it. Fields missing from the file keep their defaults.
+ */
+#[derive(Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Store reports without a point instead of rejecting them. Downstream mapping needs one.
+    pub allow_missing_coordinates: bool,
+    pub app_name: String,
+    pub api_url: String,
+    /// Local IP address to send feed requests from, e.g. on a multi-homed host.
+    pub bind_address: Option<String>,
+    #[serde(serialize_with = "redact")]
+    pub basic_auth_user: Option<String>,
+    #[serde(serialize_with = "redact")]
+    pub basic_auth_pass: Option<String>,
+    /// Minimum size of the bloom filter enabled by `use_bloom_filter`, which grows with `seen`.
+    pub bloom_filter_expected_items: usize,
+    /// Reports outside this area are dropped.
+    pub bounding_box: Option<BoundingBox>,
+    /// Queue up to this many events for a background writer instead of writing inline.
+    pub channel_capacity: Option<usize>,
+    /// What to do when the background writer's queue is full.
+    pub channel_drop_policy: DropPolicy,
+    /// Threads writing queued events concurrently, only used with `channel_capacity`.
+    pub channel_workers: usize,
+    /// Compresses the seen-state file with gzip. Paths ending in `.gz` are always compressed.
+    pub compress_seen_state: bool,
+    /// Extra delay before the next poll after the feed returns an empty body.
+    pub empty_body_backoff_ms: u64,
+    /// Only emit new reports once they've been in this many consecutive polls.
+    pub confirm_after_polls: usize,
+    /// How many events that failed to store are kept for retrying. `0` disables retries.
+    pub dead_letter_capacity: usize,
+    /// What makes a report new, see `DedupKey`.
+    pub dedup_key: DedupKey,
+    /// Where heartbeats and parse failure events are written, required if either is enabled.
+    pub diagnostic_output: Option<Output>,
+    pub emit_empty_other: bool,
+    /**
+     * Exempts reports in the first non-empty feed from `max_report_age_hours`, including ones later
+     * deferred or held for confirmation, unless seen state was loaded from disk.
+     */
+    pub emit_stale_on_cold_start: bool,
+    /// Write a diagnostic event when `parse_failure_ratio` of a poll's reports fail to parse.
+    pub emit_parse_failure_events: bool,
+    pub enable_secondary_dedup: bool,
+    /// Store reports with an unparseable timestamp at the ingest time instead of dropping them.
+    pub estimate_bad_timestamps: bool,
+    /// Maps report labels to hazards, overriding the numeric hazard code, e.g. `"Dense Fog" = "Other"`.
+    pub hazard_label_overrides: HashMap<String, Hazard>,
+    /// Reports within any of these are dropped, e.g. junk from a radar site's test rig.
+    pub exclusion_zones: Vec<ExclusionZone>,
+    /// Warn when a body has more than this many byte-for-byte duplicate icon lines.
+    pub exact_duplicate_threshold: usize,
+    pub exit_on_store_unavailable: bool,
+    /**
+     * Pooled connections idle this long aren't reused, since firewalls often drop them silently.
+     * If that's no longer than `poll_interval_ms`, nothing is pooled, see `pools_connections`.
+     */
+    pub http_connection_pool_idle_timeout_ms: u64,
+    /// Writes a heartbeat event after this many seconds without storing a report, if set.
+    pub heartbeat_interval_secs: Option<u64>,
+    pub http_read_timeout_ms: u64,
+    /// Skips writing events identical to one of this many recent writes, see `IdempotentSink`.
+    pub idempotent_write_window: Option<usize>,
+    /// Adds each report's raw capture groups to `--geojson` output as a `raw_captures` property.
+    pub include_raw_captures: bool,
+    pub latency_summary_interval_secs: u64,
+    pub max_events_per_cycle: Option<usize>,
+    /// Stores at most this many reports a poll and skips the rest, as a guard against flooding.
+    pub max_events_per_poll: Option<usize>,
+    /// The longest wait between polls while fetches keep failing, see `Producer::next_wait`.
+    pub max_fetch_backoff_ms: u64,
+    /// Drops reports older than this many hours. `0` disables the check.
+    pub max_report_age_hours: u64,
+    /// Warn about clock drift when new reports are this far from our clock on average.
+    pub max_clock_offset_secs: u64,
+    /// Notes longer than this many bytes are truncated before storing. `None` keeps notes whole.
+    pub max_notes_bytes: Option<usize>,
+    /// Unescape report notes and collapse their whitespace, treating blank notes like `None`.
+    pub normalize_notes: bool,
+    /**
+     * A feed with fewer reports than this fraction of the seen set is treated as truncated, and
+     * its reports are added to the seen set instead of replacing it. While that keeps happening the
+     * seen set only grows, and a feed that shrinks for real, e.g. after a quiet evening, keeps
+     * being treated as truncated until enough new reports arrive. `0.0`, the default, disables it.
+     */
+    pub min_expected_fraction: f64,
+    pub output: Output,
+    pub output_encoding: Encoding,
+    /// Rounds stored coordinates to this many decimals. Dedup always uses the feed's full precision.
+    pub output_coord_precision: Option<u32>,
+    pub parse_failure_ratio: f64,
+    pub poll_interval_ms: u64,
+    /// Adds up to this much random delay to each poll interval. `0` disables jitter.
+    pub poll_jitter_ms: u64,
+    /// Fetch once and check the sink before the first poll, see `producer::preflight`.
+    pub preflight: bool,
+    /// Connect to the feed over IPv6 only, ignoring its IPv4 addresses. `bind_address` wins if set.
+    pub ipv6_only: bool,
+    /// How many of the most recently stored events to keep in memory for debugging, 0 to disable.
+    pub recent_events_capacity: usize,
+    pub secondary_dedup_window_minutes: u64,
+    /// Warn once the seen set holds more than this many reports, e.g. if the feed stops aging them
+    /// out. `0` disables the warning.
+    pub seen_warning_size: usize,
+    /// Persists the seen set here between polls so a restart doesn't re-emit the whole feed.
+    pub seen_state_path: Option<String>,
+    pub store_failure_threshold: usize,
+    /**
+     * Sets each event's `ext_uri` to `api_url`, to record which feed it came from. Off by default
+     * since it's the same for every event rather than a link for the report.
+     */
+    pub tag_source_url: bool,
+    /// Template for event text, see `parser::render_event_template` for placeholders.
+    pub text_template: Option<String>,
+    /// Template for event titles, see `parser::render_event_template` for placeholders.
+    pub title_template: Option<String>,
+    pub user_agent: String,
+    /// Checks a bloom filter before the seen set, so definitely new reports skip the set lookup.
+    pub use_bloom_filter: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            allow_missing_coordinates: false,
+            app_name: "sn_loader".to_string(),
+            api_url: "http://www.spotternetwork.org/feeds/reports.txt".to_string(),
+            bind_address: None,
+            basic_auth_user: None,
+            basic_auth_pass: None,
+            bloom_filter_expected_items: 10_000,
+            bounding_box: None,
+            channel_capacity: None,
+            channel_drop_policy: DropPolicy::Block,
+            channel_workers: 1,
+            compress_seen_state: false,
+            empty_body_backoff_ms: 30_000,
+            confirm_after_polls: 1,
+            dead_letter_capacity: 1000,
+            dedup_key: DedupKey::Identity,
+            diagnostic_output: None,
+            emit_empty_other: false,
+            emit_stale_on_cold_start: false,
+            emit_parse_failure_events: false,
+            enable_secondary_dedup: false,
+            estimate_bad_timestamps: false,
+            exact_duplicate_threshold: 5,
+            exclusion_zones: Vec::new(),
+            hazard_label_overrides: HashMap::new(),
+            exit_on_store_unavailable: false,
+            http_connection_pool_idle_timeout_ms: 55_000,
+            heartbeat_interval_secs: None,
+            http_read_timeout_ms: 30_000,
+            idempotent_write_window: None,
+            include_raw_captures: false,
+            latency_summary_interval_secs: 600,
+            max_events_per_cycle: None,
+            max_events_per_poll: None,
+            max_fetch_backoff_ms: 600_000,
+            max_report_age_hours: 0,
+            max_clock_offset_secs: 900,
+            max_notes_bytes: None,
+            normalize_notes: false,
+            min_expected_fraction: 0.0,
+            output: Output::Store,
+            output_encoding: Encoding::Json,
+            output_coord_precision: None,
+            parse_failure_ratio: 0.5,
+            poll_interval_ms: 60_000,
+            poll_jitter_ms: 0,
+            preflight: false,
+            ipv6_only: false,
+            recent_events_capacity: 50,
+            secondary_dedup_window_minutes: 5,
+            seen_state_path: None,
+            seen_warning_size: 50_000,
+            store_failure_threshold: 10,
+            tag_source_url: false,
+            text_template: None,
+            title_template: None,
+            user_agent: "sigtor.org".to_string(),
+            use_bloom_filter: false,
+        }
+    }
+}
+
+impl Config {
+    /// Defaults, with credentials read from `SN_BASIC_AUTH_USER` and `SN_BASIC_AUTH_PASS`.
+    pub fn from_env() -> Config {
+        Config::default().with_env_overrides()
+    }
+
+    /**
+     * Loads a TOML config file over the defaults. Only the credentials are then read from the
+     * environment, overriding the file; every other setting comes from the file or its default.
+     */
+    pub fn from_file(path: &str) -> Result<Config, Error> {
+        let contents = fs::read_to_string(path)?;
+        let config: Config = toml::from_str(&contents).map_err(|e| {
+            let reason = format!("invalid config file {}: {}", path, e);
+            Error::Wx(<WxError>::new(&reason))
+        })?;
+
+        Ok(config.with_env_overrides())
+    }
+
+    /**
+     * Rejects settings that can't work together, e.g. heartbeats without a `diagnostic_output`,
+     * which would otherwise be silently dropped.
+     */
+    pub fn validate(&self) -> Result<(), Error> {
+        let needs_diagnostics =
+            self.heartbeat_interval_secs.is_some() || self.emit_parse_failure_events;
+
+        if needs_diagnostics && self.diagnostic_output.is_none() {
+            let reason =
+                "heartbeat_interval_secs and emit_parse_failure_events need a diagnostic_output";
+            return Err(Error::Wx(<WxError>::new(reason)));
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the basic auth credentials with `SN_BASIC_AUTH_USER`/`SN_BASIC_AUTH_PASS` if set.
+    fn with_env_overrides(mut self) -> Config {
+        if let Ok(user) = env::var("SN_BASIC_AUTH_USER") {
+            self.basic_auth_user = Some(user);
+        }
+        if let Ok(pass) = env::var("SN_BASIC_AUTH_PASS") {
+            self.basic_auth_pass = Some(pass);
+        }
+
+        self
+    }
+}
+
+/// Serializes secrets as `"***"` so the config can be logged; unset values stay `null`.
+fn redact<S: Serializer>(value: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(_) => serializer.serialize_str("***"),
+        None => serializer.serialize_none(),
+    }
+}
+
+/**
+ * Splits a feed body into the full set of normalized reports and the ones not already in `seen`.
+ * The caller must replace `seen` with `latest_hashes`, otherwise every report is re-emitted
+ * forever.
+ *
+ * ```compile_fail
+ * #![deny(unused_must_use)]
+ * wx_sn_loader::get_comparison("", wx_sn_loader::SeenSet::default());
+ * ```
+ */
+#[must_use]
+pub fn get_comparison(body: &str, seen: SeenSet) -> Comparison {
+    get_comparison_with_capacity(body, &seen, 0)
+}
+
+/**
+ * Like `get_comparison`, but pre-allocates `latest_set` to avoid rehashing as it fills, and
+ * borrows `seen` so the caller can keep it.
+ */
+#[must_use]
+pub fn get_comparison_with_capacity(body: &str, seen: &SeenSet, capacity: usize) -> Comparison {
+    get_comparison_filtered(body, seen, None, capacity, DedupKey::Identity)
+}
+
+/**
+ * Like `get_comparison_with_capacity`, but hashes reports by `key` instead of `content_hash`, and
+ * only checks `seen` for reports `bloom` might contain.
+ */
+#[must_use]
+pub fn get_comparison_filtered(
+    body: &str,
+    seen: &SeenSet,
+    bloom: Option<&BloomFilter>,
+    capacity: usize,
+    key: DedupKey,
+) -> Comparison {
+    let icon_lines: Vec<&str> = body.lines().filter(|x| x.starts_with("Icon:")).collect();
+    let distinct_lines: FnvHashSet<&str> = icon_lines.iter().cloned().collect();
+    let mut latest_set = ReportSet::with_capacity_and_hasher(capacity, FnvBuildHasher::default());
+    let mut latest_hashes = SeenSet::with_capacity_and_hasher(capacity, FnvBuildHasher::default());
+    let mut new = Vec::new();
+    let mut new_hashes = Vec::new();
+
+    for line in &icon_lines {
+        let report = normalize_line(line);
+        let hash = key.hash(&report);
+
+        // Keep the first of any lines that share a key
+        if !latest_hashes.insert(hash) {
+            continue;
+        }
+        if !is_seen(hash, seen, bloom) {
+            new.push(report.to_string());
+            new_hashes.push(hash);
+        }
+        latest_set.insert(report.into_owned());
+    }
+
+    Comparison {
+        icon_lines: icon_lines.len(),
+        exact_duplicates: icon_lines.len() - distinct_lines.len(),
+        latest_set,
+        latest_hashes,
+        new,
+        new_hashes,
+    }
+}
+
+fn is_seen(hash: u64, seen: &SeenSet, bloom: Option<&BloomFilter>) -> bool {
+    match bloom {
+        Some(bloom) if !bloom.might_contain(hash) => false,
+        _ => seen.contains(&hash),
+    }
+}
+
+/// What identifies a report when deciding whether it's new.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum DedupKey {
+    /// Coordinates, hazard code, reporter and time, so edited reports still count as seen.
+    Identity,
+    /// The whole normalized line, so a report whose notes are edited is emitted again.
+    Line,
+}
+
+impl DedupKey {
+    #[must_use]
+    pub fn hash(self, line: &str) -> u64 {
+        match self {
+            DedupKey::Identity => content_hash(line),
+            DedupKey::Line => line_hash(line),
+        }
+    }
+}
+
+/**
+ * Hashes the parts of a report line that identify it: coordinates, hazard code, reporter and
+ * time. The age digit and notes aren't included, so a report whose notes are edited hashes the
+ * same. Lines without the expected shape fall back to `line_hash`.
+ *
+ * Seen sets hold these instead of the lines themselves. With ~140 byte lines, 10,000 reports
+ * take about 1.9MB as an `IndexSet<String>` and about 144KB as an `FnvHashSet<u64>`, see
+ * `benches/seen_set.rs`. The cost is that a collision would drop a genuinely new report as
+ * already seen, but with 64 bit hashes the odds of any collision within a 10,000 report set are
+ * around 1 in 10^11.
+ */
+#[must_use]
+pub fn content_hash(line: &str) -> u64 {
+    match get_report_identity(line) {
+        Some(identity) => {
+            let mut hasher = FnvHasher::default();
+            identity.hash(&mut hasher);
+            hasher.finish()
+        }
+        None => line_hash(line),
+    }
+}
+
+/// Hashes a report line whole, after normalizing away its age digit.
+#[must_use]
+pub fn line_hash(line: &str) -> u64 {
+    let mut hasher = FnvHasher::default();
+    normalize_line(line).hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Splits out `[lat, lon, hazard code, reporter, time]` from an icon line.
+pub(crate) fn get_report_identity(line: &str) -> Option<[&str; 5]> {
+    let mut fields = line.strip_prefix("Icon: ")?.splitn(6, ',');
+    let lat = fields.next()?;
+    let lon = fields.next()?;
+    let _icon = fields.next()?;
+    let _age = fields.next()?;
+    let hazard_code = fields.next()?;
+    let details = fields.next()?;
+
+    let reporter_start = details.find("Reported By: ")? + "Reported By: ".len();
+    let reporter = details[reporter_start..].split("\\n").next()?;
+    let time_start = details.find("\\nTime: ")? + "\\nTime: ".len();
+    let time = details[time_start..].split(" UTC").next()?;
+
+    Some([lat, lon, hazard_code, reporter, time])
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ComparisonStats {
+    pub total: usize,
+    pub new: usize,
+    pub unchanged: usize,
+}
+
+/**
+ * Same counts as `get_comparison_filtered` with `key`, for callers that only need metrics. This
+ * skips building the `new` list and leaves `seen` untouched.
+ */
+#[must_use]
+pub fn get_comparison_stats(body: &str, seen: &SeenSet, key: DedupKey) -> ComparisonStats {
+    let latest_hashes: SeenSet = body
+        .lines()
+        .filter(|x| x.starts_with("Icon:"))
+        .map(|x| key.hash(x))
+        .collect();
+
+    let unchanged = latest_hashes.iter().filter(|x| seen.contains(x)).count();
+
+    ComparisonStats {
+        total: latest_hashes.len(),
+        new: latest_hashes.len() - unchanged,
+        unchanged,
+    }
+}
+
+/**
+ * Splits a trailing partial line off a body that was cut off mid-record, e.g. by a dropped
+ * connection, returning the complete part and the partial line. A body that doesn't end in a
+ * newline is only considered truncated if its last line is also missing the record's closing quote.
+ */
+pub(crate) fn split_partial_line(body: &str) -> (&str, Option<&str>) {
+    if body.ends_with('\n') {
+        return (body, None);
+    }
+
+    let start = body.rfind('\n').map_or(0, |i| i + 1);
+    let last_line = &body[start..];
+
+    if last_line.starts_with("Icon:") && !last_line.trim_end().ends_with('"') {
+        (&body[..start], Some(last_line))
+    } else {
+        (body, None)
+    }
+}
+
+/**
+ * Looks for a generation timestamp (`YYYY-MM-DD HH:MM:SS`, UTC) in the metadata lines ahead of
+ * the first report, returning it in microseconds since epoch. The feed doesn't currently include
+ * one, in which case this returns `None`.
+ */
+fn get_feed_timestamp(body: &str) -> Option<u64> {
+    lazy_static! {
+        static ref PATTERN: Regex = Regex::new(r"\d{4}-\d{2}-\d{2} \d{2}:\d{2}:\d{2}").unwrap();
+    }
+
+    body.lines()
+        .take_while(|x| !x.starts_with("Icon:"))
+        .filter_map(|x| PATTERN.find(x))
+        .filter_map(|x| Utc.datetime_from_str(x.as_str(), "%Y-%m-%d %H:%M:%S").ok())
+        .map(|x| x.timestamp() as u64 * 1_000_000)
+        .next()
+}
+
+/// How feed clients are built, shared by `HttpFetcher` and the async fetcher.
+#[derive(Debug, PartialEq)]
+pub(crate) struct ClientSettings {
+    timeout: Duration,
+    local_address: Option<IpAddr>,
+    pool_idle_timeout: Duration,
+    /// Whether connections are kept between polls, see `pools_connections`.
+    pool_connections: bool,
+}
+
+impl ClientSettings {
+    pub(crate) fn from_config(config: &Config) -> Result<ClientSettings, Error> {
+        Ok(ClientSettings {
+            timeout: Duration::from_millis(config.http_read_timeout_ms),
+            local_address: get_local_address(config)?,
+            pool_idle_timeout: Duration::from_millis(config.http_connection_pool_idle_timeout_ms),
+            pool_connections: pools_connections(config),
+        })
+    }
+
+    fn build(&self) -> Result<Client, Error> {
+        let builder = Client::builder()
+            .timeout(self.timeout)
+            .local_address(self.local_address);
+        let builder = if self.pool_connections {
+            builder
+        } else {
+            builder.max_idle_per_host(0)
+        };
+
+        Ok(builder.build()?)
+    }
+
+    #[cfg(feature = "async")]
+    pub(crate) fn build_async(&self) -> Result<reqwest::r#async::Client, Error> {
+        let builder = reqwest::r#async::Client::builder()
+            .timeout(self.timeout)
+            .local_address(self.local_address);
+        let builder = if self.pool_connections {
+            builder
+        } else {
+            builder.max_idle_per_host(0)
+        };
+
+        Ok(builder.build()?)
+    }
+
+    /// Whether a fresh client is needed before the next request, see `is_pool_stale`.
+    pub(crate) fn needs_new_client(&self, last_request: Option<Instant>) -> bool {
+        self.pool_connections && is_pool_stale(last_request, self.pool_idle_timeout)
+    }
+}
+
+/**
+ * Pooled connections only get reused if the next poll comes before they go stale. When it can't,
+ * e.g. with the default 55s idle timeout and 60s poll interval, every poll would rebuild the client
+ * anyway, so the client keeps no idle connections and each poll opens a new one.
+ */
+pub fn pools_connections(config: &Config) -> bool {
+    config.http_connection_pool_idle_timeout_ms > config.poll_interval_ms
+}
+
+/**
+ * Whether pooled connections have sat idle long enough that a firewall may have silently dropped
+ * them. reqwest 0.9 has no `pool_idle_timeout`, so `HttpFetcher` builds a fresh client, and with
+ * it a fresh pool, instead of reusing them.
+ */
+fn is_pool_stale(last_request: Option<Instant>, idle_timeout: Duration) -> bool {
+    match last_request {
+        Some(last_request) => last_request.elapsed() >= idle_timeout,
+        None => false,
+    }
+}
+
+pub struct HttpFetcher {
+    client: RefCell<Client>,
+    settings: ClientSettings,
+    last_request: Cell<Option<Instant>>,
+    /// Clients built so far, including the first.
+    client_builds: Cell<usize>,
+    url: String,
+    user_agent: String,
+    basic_auth: Option<(String, Option<String>)>,
+}
+
+impl HttpFetcher {
+    pub fn new(config: &Config) -> Result<HttpFetcher, Error> {
+        let settings = ClientSettings::from_config(config)?;
+        let client = RefCell::new(settings.build()?);
+
+        let basic_auth = config
+            .basic_auth_user
+            .as_ref()
+            .map(|user| (user.clone(), config.basic_auth_pass.clone()));
+
+        Ok(HttpFetcher {
+            client,
+            settings,
+            last_request: Cell::new(None),
+            client_builds: Cell::new(1),
+            url: config.api_url.clone(),
+            user_agent: config.user_agent.clone(),
+            basic_auth,
+        })
+    }
+
+    fn request(&self) -> RequestBuilder {
+        let request = self
+            .client
+            .borrow()
+            .get(&self.url)
+            .header(header::USER_AGENT, self.user_agent.as_str());
+
+        match &self.basic_auth {
+            Some((user, pass)) => request.basic_auth(user, pass.as_ref()),
+            None => request,
+        }
+    }
+}
+
+impl Fetcher for HttpFetcher {
+    fn fetch(&self) -> Result<String, Error> {
+        if self.settings.needs_new_client(self.last_request.get()) {
+            *self.client.borrow_mut() = self.settings.build()?;
+            self.client_builds.set(self.client_builds.get() + 1);
+        }
+
+        self.last_request.set(Some(Instant::now()));
+        fetch_reports(self.request())
+    }
+}
+
+/**
+ * The address to bind outgoing connections to. Binding to the unspecified IPv6 address means
+ * connections to the feed's IPv4 addresses fail, so only its IPv6 addresses get used.
+ */
+fn get_local_address(config: &Config) -> Result<Option<IpAddr>, Error> {
+    match (&config.bind_address, config.ipv6_only) {
+        (Some(addr), _) => addr.parse().map(Some).map_err(|e| {
+            let reason = format!("invalid bind_address {}: {}", addr, e);
+            Error::Wx(<WxError>::new(&reason))
+        }),
+        (None, true) => Ok(Some(IpAddr::V6(Ipv6Addr::UNSPECIFIED))),
+        (None, false) => Ok(None),
+    }
+}
+
+fn fetch_reports(request: RequestBuilder) -> Result<String, Error> {
+    let mut response = request.send().map_err(request_error)?;
+    read_body(response.status(), response.text())
+}
+
+/// Maps a failed request onto an `Error`, saying plainly when it timed out.
+pub(crate) fn request_error(e: reqwest::Error) -> Error {
+    if e.is_timeout() {
+        let reason = format!("Request timed out: {}", e);
+        Error::Wx(<WxError>::new(&reason))
+    } else {
+        Error::from(e)
+    }
+}
+
+/**
+ * Turns a response's status and text into the feed body, for both the blocking and async
+ * clients. Anything but a 200 is an error page rather than reports.
+ */
+pub(crate) fn read_body(
+    status: StatusCode,
+    text: Result<String, reqwest::Error>,
+) -> Result<String, Error> {
+    if status != StatusCode::OK {
+        let reason = format!("Unexpected status code: {}", status);
+        return Err(Error::Wx(<WxError>::new(&reason)));
+    }
+
+    match text {
+        Ok(text) => Ok(text),
+        Err(_) => {
+            let reason = "Unable to read text".to_string();
+            Err(Error::Wx(<WxError>::new(&reason)))
+        }
+    }
+}
+
+/// A leading byte order mark would hide the first line's `Icon:` prefix, dropping its report.
+pub(crate) fn strip_bom(text: &str) -> &str {
+    text.strip_prefix('\u{feff}').unwrap_or(text)
+}
+
+/**
+ * Normalizes raw report lines as returned by the SpotterNetwork API. Since there is no offset,
+ * you will see the same report multiple times and need to de-dupe. Unfortunately, the same
+ * report will have the icon image digit change as the report ages so we need to normalize.
+ */
+#[must_use]
+pub(crate) fn normalize_line(line: &str) -> Cow<'_, str> {
+    const AGING_ICONS: [&str; 3] = [",000,3", ",000,4", ",000,5"];
+
+    if !AGING_ICONS.iter().any(|x| line.contains(x)) {
+        return Cow::Borrowed(line);
+    }
+
+    Cow::Owned(
+        line.replace(",000,3", ",000,0")
+            .replace(",000,4", ",000,0")
+            .replace(",000,5", ",000,0"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::WriterSink;
+    use crate::test_util::{CapturingDrain, WIND_REPORT};
+    use slog::Drain;
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Instant;
+
+    #[test]
+    fn fetch_reports_should_time_out_on_hung_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "http://{}/feeds/reports.txt",
+            listener.local_addr().unwrap()
+        );
+
+        // Accept the connection but never respond
+        thread::spawn(move || {
+            let (_stream, _) = listener.accept().unwrap();
+            thread::sleep(Duration::from_secs(5));
+        });
+
+        let timeout_ms = 500;
+        let client = Client::builder()
+            .timeout(Duration::from_millis(timeout_ms))
+            .build()
+            .unwrap();
+        let started = Instant::now();
+        let result = fetch_reports(client.get(&url));
+        let elapsed = started.elapsed();
+
+        assert!(elapsed < Duration::from_millis(timeout_ms * 2));
+        let reason = result.expect_err("fetch should fail").to_string();
+        assert!(reason.contains("timed out"), "unexpected error: {}", reason);
+        assert!(!reason.contains("status code"));
+    }
+
+    /// Serves a single canned HTTP response, returning the feed URL.
+    fn serve_once(response: &'static str) -> String {
+        serve_once_on(TcpListener::bind("127.0.0.1:0").unwrap(), response)
+    }
+
+    fn serve_once_on(listener: TcpListener, response: &'static str) -> String {
+        let url = format!(
+            "http://{}/feeds/reports.txt",
+            listener.local_addr().unwrap()
+        );
+
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 4096];
+            let _ = stream.read(&mut request).unwrap();
+            stream.write_all(response.as_bytes()).unwrap();
+        });
+
+        url
+    }
+
+    const EMPTY_RESPONSE: &str = "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n";
+
+    #[test]
+    fn fetch_reports_should_return_empty_body() {
+        let url = serve_once(EMPTY_RESPONSE);
+        let body = fetch_reports(Client::new().get(&url)).unwrap();
+        assert_eq!(body, "");
+
+        let comparison = get_comparison(&body, SeenSet::default());
+        assert_eq!(comparison.latest_set.len(), 0);
+        assert_eq!(comparison.new.len(), 0);
+    }
+
+    #[test]
+    fn poll_once_should_flag_empty_http_body() {
+        let config = Config {
+            api_url: serve_once(EMPTY_RESPONSE),
+            ..Config::default()
+        };
+        let fetcher = HttpFetcher::new(&config).unwrap();
+        let sink = WriterSink::new(Vec::new(), Encoding::Json);
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let mut producer = producer::Producer::new(config, fetcher, sink, logger);
+
+        let result = producer.poll_once();
+        assert!(!result.fetch_failed);
+        assert!(result.empty_body);
+    }
+
+    #[test]
+    fn poll_once_should_log_fetch_size_and_duration() {
+        let config = Config {
+            api_url: serve_once("HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\nRefresh: 1\n"),
+            ..Config::default()
+        };
+        let fetcher = HttpFetcher::new(&config).unwrap();
+        let sink = WriterSink::new(Vec::new(), Encoding::Json);
+        let drain = CapturingDrain::default();
+        let logger = slog::Logger::root(drain.clone().fuse(), o!());
+        let mut producer = producer::Producer::new(config, fetcher, sink, logger);
+
+        producer.poll_once();
+        let fields = drain.fields.lock().unwrap();
+        let field = |key: &str| {
+            let (_, value) = fields.iter().find(|(k, _)| k == key).unwrap();
+            value.parse::<u64>().unwrap()
+        };
+
+        assert_eq!(field("body_bytes"), 11);
+        assert!(field("fetch_ms") < 5_000);
+    }
+
+    #[test]
+    fn request_should_include_basic_auth_when_configured() {
+        let config = Config {
+            basic_auth_user: Some("user".to_string()),
+            basic_auth_pass: Some("pass".to_string()),
+            ..Config::default()
+        };
+        let request = HttpFetcher::new(&config)
+            .unwrap()
+            .request()
+            .build()
+            .unwrap();
+
+        let authorization = request.headers().get(header::AUTHORIZATION).unwrap();
+        assert_eq!(authorization, "Basic dXNlcjpwYXNz");
+    }
+
+    #[test]
+    fn request_should_not_include_basic_auth_by_default() {
+        let request = HttpFetcher::new(&Config::default())
+            .unwrap()
+            .request()
+            .build()
+            .unwrap();
+
+        assert!(request.headers().get(header::AUTHORIZATION).is_none());
+    }
+
+    #[test]
+    fn local_address_should_follow_config() {
+        let config = Config {
+            bind_address: Some("::1".to_string()),
+            ipv6_only: true,
+            ..Config::default()
+        };
+        assert_eq!(
+            get_local_address(&config).unwrap(),
+            Some("::1".parse().unwrap())
+        );
+
+        let config = Config {
+            ipv6_only: true,
+            ..Config::default()
+        };
+        let expected = IpAddr::V6(Ipv6Addr::UNSPECIFIED);
+        assert_eq!(get_local_address(&config).unwrap(), Some(expected));
+        assert_eq!(get_local_address(&Config::default()).unwrap(), None);
+    }
+
+    #[test]
+    fn client_settings_should_follow_config() {
+        let config = Config {
+            http_connection_pool_idle_timeout_ms: 20_000,
+            http_read_timeout_ms: 5_000,
+            ..Config::default()
+        };
+        let expected = ClientSettings {
+            timeout: Duration::from_millis(5_000),
+            local_address: None,
+            pool_idle_timeout: Duration::from_millis(20_000),
+            pool_connections: false,
+        };
+        assert_eq!(ClientSettings::from_config(&config).unwrap(), expected);
+
+        let settings = ClientSettings::from_config(&Config::default()).unwrap();
+        assert_eq!(settings.pool_idle_timeout, Duration::from_millis(55_000));
+        assert!(!settings.pool_connections);
+
+        let config = Config {
+            poll_interval_ms: 10_000,
+            ..Config::default()
+        };
+        let settings = ClientSettings::from_config(&config).unwrap();
+        assert!(settings.pool_connections);
+    }
+
+    #[test]
+    fn pool_should_go_stale_after_idle_timeout() {
+        let timeout = Duration::from_millis(50);
+        assert!(!is_pool_stale(None, timeout));
+        assert!(!is_pool_stale(Some(Instant::now()), timeout));
+
+        let last_request = Instant::now() - Duration::from_millis(60);
+        assert!(is_pool_stale(Some(last_request), timeout));
+    }
+
+    #[test]
+    fn http_fetcher_should_rebuild_client_when_pool_is_stale() {
+        let config = Config {
+            api_url: serve_once(EMPTY_RESPONSE),
+            http_connection_pool_idle_timeout_ms: 50,
+            poll_interval_ms: 10,
+            ..Config::default()
+        };
+        let fetcher = HttpFetcher::new(&config).unwrap();
+        fetcher.last_request.set(Some(Instant::now()));
+        assert_eq!(fetcher.fetch().unwrap(), "");
+        assert_eq!(fetcher.client_builds.get(), 1);
+
+        // The server only answers once, but the client is rebuilt before the request fails
+        let last_request = Instant::now() - Duration::from_millis(60);
+        fetcher.last_request.set(Some(last_request));
+        assert!(fetcher.fetch().is_err());
+        assert_eq!(fetcher.client_builds.get(), 2);
+    }
+
+    #[test]
+    fn http_fetcher_should_not_rebuild_client_without_pooling() {
+        let config = Config {
+            api_url: serve_once(EMPTY_RESPONSE),
+            ..Config::default()
+        };
+        let fetcher = HttpFetcher::new(&config).unwrap();
+        let last_request = Instant::now() - Duration::from_secs(120);
+        fetcher.last_request.set(Some(last_request));
+
+        assert_eq!(fetcher.fetch().unwrap(), "");
+        assert_eq!(fetcher.client_builds.get(), 1);
+    }
+
+    #[test]
+    fn http_fetcher_should_reject_invalid_bind_address() {
+        let config = Config {
+            bind_address: Some("eth0".to_string()),
+            ..Config::default()
+        };
+        assert!(HttpFetcher::new(&config).is_err());
+    }
+
+    #[test]
+    fn http_fetcher_should_fetch_over_ipv6() {
+        // Hosts and containers without IPv6 can't bind the loopback address, nothing to test there
+        let listener = match TcpListener::bind("[::1]:0") {
+            Ok(listener) => listener,
+            Err(_) => return,
+        };
+        let config = Config {
+            api_url: serve_once_on(listener, EMPTY_RESPONSE),
+            ipv6_only: true,
+            ..Config::default()
+        };
+        let fetcher = HttpFetcher::new(&config).unwrap();
+
+        assert_eq!(fetcher.fetch().unwrap(), "");
+    }
+
+    #[test]
+    fn serialized_config_should_not_include_credentials() {
+        let config = Config {
+            basic_auth_user: Some("sn-mirror-user".to_string()),
+            basic_auth_pass: Some("hunter2".to_string()),
+            ..Config::default()
+        };
+        let serialized = serde_json::to_string(&config).unwrap();
+
+        assert!(!serialized.contains("sn-mirror-user"));
+        assert!(!serialized.contains("hunter2"));
+    }
+
+    #[test]
+    fn serialized_config_should_mask_credentials() {
+        let config = Config {
+            basic_auth_user: Some("sn-mirror-user".to_string()),
+            basic_auth_pass: Some("hunter2".to_string()),
+            ..Config::default()
+        };
+        let serialized: serde_json::Value = serde_json::to_value(&config).unwrap();
+
+        assert_eq!(serialized["basic_auth_user"], "***");
+        assert_eq!(serialized["basic_auth_pass"], "***");
+        assert_eq!(serialized["user_agent"], "sigtor.org");
+    }
+
+    #[test]
+    fn serialized_config_should_leave_unset_credentials_null() {
+        let serialized: serde_json::Value = serde_json::to_value(Config::default()).unwrap();
+
+        assert!(serialized["basic_auth_user"].is_null());
+        assert!(serialized["basic_auth_pass"].is_null());
+    }
+
+    #[test]
+    fn from_file_should_override_defaults() {
+        let config = Config::from_file("data/config.toml").unwrap();
+
+        assert_eq!(config.app_name, "sn_loader_test");
+        assert_eq!(config.poll_interval_ms, 30_000);
+        assert_eq!(config.max_events_per_cycle, Some(50));
+        assert!(config.bounding_box.unwrap().min_lat == 35.0);
+        assert!(match config.output {
+            Output::File(path) => path == "/tmp/sn.ndjson",
+            _ => false,
+        });
+        assert_eq!(
+            config.hazard_label_overrides.get("Freezing Drizzle"),
+            Some(&Hazard::FreezingRain)
+        );
+        // Not in the file, so the default stands
+        assert_eq!(config.user_agent, "sigtor.org");
+        assert_eq!(config.http_read_timeout_ms, 30_000);
+    }
+
+    #[test]
+    fn validate_should_require_diagnostic_output_for_heartbeats() {
+        let config = Config {
+            heartbeat_interval_secs: Some(60),
+            ..Config::default()
+        };
+        assert!(config.validate().is_err());
+
+        let config = Config {
+            emit_parse_failure_events: true,
+            ..Config::default()
+        };
+        assert!(config.validate().is_err());
+
+        let config = Config {
+            heartbeat_interval_secs: Some(60),
+            diagnostic_output: Some(Output::Stdout),
+            ..Config::default()
+        };
+        assert!(config.validate().is_ok());
+        assert!(Config::default().validate().is_ok());
+    }
+
+    #[test]
+    fn from_file_should_reject_unknown_fields() {
+        let path = env::temp_dir().join("sn_loader_unknown_field.toml");
+        fs::write(&path, "poll_interval = 1000\n").unwrap();
+
+        assert!(Config::from_file(path.to_str().unwrap()).is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn normalize_line_should_zero_icon_digit() {
+        let line = r#"Icon: 47.617706,-111.215248,000,4,4,"Reported By: Test User\nHail\nTime: 2018-09-20 22:49:29 UTC\nSize: 0.75" (Penny)\nNotes: None""#;
+        let expected = r#"Icon: 47.617706,-111.215248,000,0,4,"Reported By: Test User\nHail\nTime: 2018-09-20 22:49:29 UTC\nSize: 0.75" (Penny)\nNotes: None""#;
+        let normalized = normalize_line(line);
+        assert_eq!(normalized, expected);
+    }
+
+    #[test]
+    fn normalize_line_should_borrow_normalized_lines() {
+        let line = line_with_age_digit(0);
+        let normalized = normalize_line(&line);
+
+        assert_eq!(normalized, line);
+        assert!(matches!(normalized, Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn normalize_line_should_borrow_lines_without_icon_field() {
+        let line = "Title: Spotter Network - Reports Only";
+        let normalized = normalize_line(line);
+
+        assert_eq!(normalized, line);
+        assert!(matches!(normalized, Cow::Borrowed(_)));
+    }
+
+    fn line_with_age_digit(digit: u32) -> String {
+        format!(
+            r#"Icon: 47.617706,-111.215248,000,{},4,"Reported By: Test User\nHail\nTime: 2018-09-20 22:49:29 UTC\nSize: 0.75" (Penny)\nNotes: None""#,
+            digit
+        )
+    }
+
+    #[test]
+    fn normalize_line_should_only_zero_aging_icon_digits() {
+        // The feed only defines icons 3, 4, and 5 (see the `IconFile` header lines)
+        for digit in 0..10 {
+            let line = line_with_age_digit(digit);
+            let normalized = normalize_line(&line);
+
+            if (3..=5).contains(&digit) {
+                assert_eq!(normalized, line_with_age_digit(0), "digit {}", digit);
+            } else {
+                assert_eq!(normalized, line, "digit {}", digit);
+            }
+        }
+    }
+
+    #[test]
+    fn lines_differing_only_by_age_digit_should_be_deduped() {
+        let first = line_with_age_digit(4);
+        let second = line_with_age_digit(5);
+        assert_eq!(normalize_line(&first), normalize_line(&second));
+
+        let comparison = get_comparison(&first, SeenSet::default());
+        assert_eq!(comparison.new.len(), 1);
+
+        let comparison = get_comparison(&second, comparison.latest_hashes);
+        assert_eq!(comparison.latest_set.len(), 1);
+        assert_eq!(comparison.new.len(), 0);
+    }
+
+    #[test]
+    fn line_hash_should_ignore_age_digit_only() {
+        let line = line_with_age_digit(3);
+        assert_eq!(line_hash(&line), line_hash(&line_with_age_digit(5)));
+
+        let edited = line.replace("Notes: None", "Notes: Trees down");
+        assert_ne!(line_hash(&line), line_hash(&edited));
+    }
+
+    #[test]
+    fn content_hash_should_ignore_age_digit_and_notes() {
+        let line = line_with_age_digit(3);
+        let edited = line_with_age_digit(5).replace("Notes: None", "Notes: Trees down");
+        assert_eq!(content_hash(&line), content_hash(&edited));
+
+        let later = line.replace("22:49:29", "22:49:30");
+        assert_ne!(content_hash(&line), content_hash(&later));
+        let moved = line.replace("47.617706", "47.617707");
+        assert_ne!(content_hash(&line), content_hash(&moved));
+    }
+
+    #[test]
+    fn content_hash_should_hash_malformed_lines_whole() {
+        assert_eq!(get_report_identity("Icon: 47.617706,-111.215248"), None);
+        assert_ne!(content_hash("Icon: 1,2"), content_hash("Icon: 1,3"));
+        assert_eq!(content_hash("Icon: 1,2"), line_hash("Icon: 1,2"));
+        assert_eq!(
+            content_hash("Icon: 1,2,000,3,4"),
+            content_hash("Icon: 1,2,000,0,4")
+        );
+    }
+
+    #[test]
+    fn get_comparison_should_preserve_unicode_notes() {
+        let degrees = r#"Icon: 41.338715,-96.059563,000,4,8,"Reported By: Test User\nOther - See Note\nTime: 2018-09-21 00:34:00 UTC\nNotes: Temp dropped to 32°F"#;
+        let dash = r#"Icon: 41.230400,-95.850403,000,3,8,"Reported By: Test User\nOther - See Note\nTime: 2018-09-21 00:35:00 UTC\nNotes: Power out — lines down"#;
+        let snow = r#"Icon: 44.174999,-93.682098,000,5,8,"Reported By: Test User\nOther - See Note\nTime: 2018-09-21 00:36:00 UTC\nNotes: 雪 falling heavily"#;
+        let body = format!("{}\n{}\n{}\n{}", degrees, dash, snow, degrees);
+
+        let comparison = get_comparison(&body, SeenSet::default());
+        assert_eq!(comparison.latest_set.len(), 3);
+        assert_eq!(comparison.new.len(), 3);
+
+        for (line, note) in &[(degrees, "32°F"), (dash, "—"), (snow, "雪")] {
+            let normalized = normalize_line(line);
+            assert!(normalized.ends_with(&line[line.find("Notes:").unwrap()..]));
+            assert!(normalized.contains(note));
+            assert!(comparison.latest_set.contains(normalized.as_ref()));
+        }
+
+        let comparison = get_comparison(&body, comparison.latest_hashes);
+        assert_eq!(comparison.latest_set.len(), 3);
+        assert_eq!(comparison.new.len(), 0);
+    }
+
+    #[test]
+    fn split_partial_line_should_drop_truncated_record() {
+        let complete = WIND_REPORT;
+        let partial = r#"Icon: 47.617706,-111.215248,000,4,4,"Reported By: Test Us"#;
+        let body = format!("{}\n{}", complete, partial);
+
+        let (body, dropped) = split_partial_line(&body);
+        assert_eq!(body, format!("{}\n", complete));
+        assert_eq!(dropped, Some(partial));
+    }
+
+    #[test]
+    fn split_partial_line_should_keep_complete_bodies() {
+        let complete = WIND_REPORT;
+        let with_newline = format!("{}\n", complete);
+
+        assert_eq!(
+            split_partial_line(&with_newline),
+            (with_newline.as_str(), None)
+        );
+        // Cut off after the closing quote, so the record itself is whole
+        assert_eq!(split_partial_line(complete), (complete, None));
+        assert_eq!(split_partial_line(""), ("", None));
+    }
+
+    #[test]
+    fn get_feed_timestamp_should_parse_header_timestamp() {
+        let body = "Refresh: 1\nTitle: Spotter Network - Reports Only\nGenerated: 2018-09-20 23:00:00 UTC\n\nIcon: 47.617706,-111.215248,000,4,4,\"Reported By: Test User\\nHail\\nTime: 2018-09-20 22:39:00 UTC\\nNotes: None\"";
+        assert_eq!(get_feed_timestamp(body), Some(1_537_484_400_000_000));
+    }
+
+    #[test]
+    fn get_feed_timestamp_should_ignore_report_timestamps() {
+        let mut file = File::open("data/reports").expect("unable to open file");
+        let mut body = String::new();
+        file.read_to_string(&mut body).expect("unable to read file");
+        assert_eq!(get_feed_timestamp(&body), None);
+    }
+
+    #[test]
+    fn empty_report_should_return_no_seen_or_unseen() {
+        let mut file = File::open("data/reports-empty").expect("unable to open file");
+        let mut body = String::new();
+        file.read_to_string(&mut body).expect("unable to read file");
+        let comparison = get_comparison(&body, SeenSet::default());
+        assert_eq!(comparison.latest_set.len(), 0);
+        assert_eq!(comparison.new.len(), 0);
+    }
+
+    #[test]
+    fn no_current_seen_should_return_all_reports() {
+        let mut file = File::open("data/reports").expect("unable to open file");
+        let mut body = String::new();
+        file.read_to_string(&mut body).expect("unable to read file");
+        let comparison = get_comparison(&body, SeenSet::default());
+        assert_eq!(comparison.latest_set.len(), 23);
+        assert_eq!(comparison.new.len(), 23);
+    }
+
+    #[test]
+    fn same_report_different_age_digit_should_be_deduped() {
+        let body = r#"Icon: 47.617706,-111.215248,000,4,4,"Reported By: Test User\nHail\nTime: 2018-09-20 22:39:00 UTC\nSize: 0.75" (Penny)\nNotes: None""#;
+        let comparison = get_comparison(&body, SeenSet::default());
+        assert_eq!(comparison.latest_set.len(), 1);
+        assert_eq!(comparison.new.len(), 1);
+
+        let body = r#"Icon: 47.617706,-111.215248,000,5,4,"Reported By: Test User\nHail\nTime: 2018-09-20 22:39:00 UTC\nSize: 0.75" (Penny)\nNotes: None"
+            Icon: 47.617706,-111.215248,000,6,4,"Reported By: Test User\nHail\nTime: 2018-09-20 22:39:00 UTC\nSize: 0.75" (Penny)\nNotes: None""#;
+        let comparison = get_comparison(&body, comparison.latest_hashes);
+        assert_eq!(comparison.latest_set.len(), 1);
+        assert_eq!(comparison.new.len(), 0);
+    }
+
+    #[test]
+    fn get_comparison_should_return_new_reports_in_feed_order() {
+        let mut file = File::open("data/reports").expect("unable to open file");
+        let mut body = String::new();
+        file.read_to_string(&mut body).expect("unable to read file");
+
+        let first = body.lines().find(|x| x.starts_with("Icon:")).unwrap();
+        let comparison = get_comparison(&body, SeenSet::default());
+        assert_eq!(comparison.new[0], normalize_line(first));
+        assert!(comparison.new[0].contains("Time: 2018-09-20 22:49:29 UTC"));
+
+        let seen: SeenSet = vec![content_hash(first)].into_iter().collect();
+        let second = body
+            .lines()
+            .filter(|x| x.starts_with("Icon:"))
+            .nth(1)
+            .unwrap();
+        let comparison = get_comparison(&body, seen);
+        assert_eq!(comparison.new[0], normalize_line(second));
+        assert!(comparison.new[0].contains("Time: 2018-09-20 21:43:37 UTC"));
+    }
+
+    #[test]
+    fn get_comparison_stats_should_match_get_comparison() {
+        let mut file = File::open("data/reports").expect("unable to open file");
+        let mut body = String::new();
+        file.read_to_string(&mut body).expect("unable to read file");
+        let mut file = File::open("data/reports-updates").expect("unable to open file");
+        let mut update = String::new();
+        file.read_to_string(&mut update)
+            .expect("unable to read file");
+        let line = line_with_age_digit(3);
+        let notes = line.replace("Notes: None", "Notes: Trees down");
+        let edited = format!("{}\n{}", line, notes);
+
+        for &key in &[DedupKey::Identity, DedupKey::Line] {
+            let stats = get_comparison_stats(&body, &SeenSet::default(), key);
+            assert_eq!(
+                stats,
+                ComparisonStats {
+                    total: 23,
+                    new: 23,
+                    unchanged: 0
+                }
+            );
+
+            let comparison = get_comparison_filtered(&body, &SeenSet::default(), None, 0, key);
+            let stats = get_comparison_stats(&body, &comparison.latest_hashes, key);
+            assert_eq!(
+                stats,
+                ComparisonStats {
+                    total: 23,
+                    new: 0,
+                    unchanged: 23
+                }
+            );
+
+            let seen = get_comparison_filtered(&update, &SeenSet::default(), None, 0, key);
+            let stats = get_comparison_stats(&body, &seen.latest_hashes, key);
+            let comparison = get_comparison_filtered(&body, &seen.latest_hashes, None, 0, key);
+            assert_eq!(stats.total, comparison.latest_set.len());
+            assert_eq!(stats.new, comparison.new.len());
+
+            let stats = get_comparison_stats(&edited, &SeenSet::default(), key);
+            let comparison = get_comparison_filtered(&edited, &SeenSet::default(), None, 0, key);
+            assert_eq!(stats.total, comparison.latest_set.len());
+        }
+
+        let stats = get_comparison_stats(&edited, &SeenSet::default(), DedupKey::Identity);
+        assert_eq!(stats.total, 1);
+    }
+
+    #[test]
+    fn get_comparison_with_capacity_should_match_get_comparison() {
+        let mut file = File::open("data/reports").expect("unable to open file");
+        let mut body = String::new();
+        file.read_to_string(&mut body).expect("unable to read file");
+
+        let comparison = get_comparison_with_capacity(&body, &SeenSet::default(), 128);
+        assert!(comparison.latest_set.capacity() >= 128);
+
+        let expected = get_comparison(&body, SeenSet::default());
+        assert_eq!(comparison.icon_lines, expected.icon_lines);
+        assert_eq!(comparison.latest_set, expected.latest_set);
+        assert_eq!(comparison.new, expected.new);
+    }
+
+    #[test]
+    fn get_comparison_should_dedupe_lines_within_body() {
+        let line = line_with_age_digit(3);
+        let body = format!("{}\n{}", line, line);
+        let comparison = get_comparison(&body, SeenSet::default());
+
+        assert_eq!(comparison.icon_lines, 2);
+        assert_eq!(comparison.latest_set.len(), 1);
+        assert_eq!(comparison.new.len(), 1);
+    }
+
+    #[test]
+    fn get_comparison_should_handle_previously_seen_reports() {
+        let mut file = File::open("data/reports").expect("unable to open file");
+        let mut body = String::new();
+        file.read_to_string(&mut body).expect("unable to read file");
+
+        let seen: SeenSet = [
+            "Icon: 41.338901,-96.059708,000,0,5,\"Reported By: Will Dupe\\nHigh Wind\\nTime: 2018-09-21 00:26:06 UTC\\n50 mphNotes: None\"".to_string(),
+            "Icon: 47.617706,-111.215248,000,0,4,\"Reported By: Will Dupe\\nHail\\nTime: 2018-09-20 22:49:29 UTC\\nSize: 0.75\" (Penny)\\nNotes: None\"".to_string(),
+            "Icon: 43.112000,-94.610001,000,0,6,\"Reported By: Will Dupe\\nFlooding\\nTime: 2018-09-20 22:58:00 UTC\\nNotes: Water over road on US 18\"".to_string(),
+            "Icon: 41.338715,-96.059563,000,0,5,\"Reported By: Will Dupe\\nHigh Wind\\nTime: 2018-09-21 00:34:00 UTC\\n60 mphNotes: Wind gusting to 63mph\"".to_string(),
+            "Icon: 35.851399,-90.708198,000,0,8,\"Reported By: Will Dupe\\nOther - See Note\\nTime: 2018-11-14 20:22:00 UTC\\nNotes: i got snow and a little of sleet\"".to_string(),
+            "Icon: 41.230400,-95.850403,000,0,3,\"Reported By: Will Dupe\\nNot Rotating Wall Cloud\\nTime: 2018-09-21 00:34:00 UTC\\nNotes: None\"".to_string(),
+        ]
+        .iter()
+        .map(|x| content_hash(x))
+        .collect();
+
+        let seen_length = seen.len();
+        let comparison = get_comparison(&body, seen);
+
+        assert_eq!(comparison.latest_set.len(), 23);
+        assert_eq!(
+            comparison.new.len(),
+            comparison.latest_set.len() - seen_length
+        );
+    }
+}
@@ -1,217 +1,209 @@
 #[macro_use]
-extern crate serde_derive;
-#[macro_use]
 extern crate slog;
-extern crate serde_json;
-
-mod domain;
-mod parser;
 
-use self::parser::ReportParser;
-use fnv::FnvHashSet;
-use reqwest::{header, Client, StatusCode};
-use std::thread;
-use std::time::Duration;
-use wx::error::{Error, WxError};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::process;
 use wx::util::Logger;
-
-#[derive(Debug)]
-pub struct Comparison {
-    latest_set: FnvHashSet<String>,
-    new: Vec<String>,
-}
-
-#[derive(Serialize)]
-pub struct Config {
-    pub app_name: &'static str,
-    pub api_url: &'static str,
-    pub poll_interval_ms: u64,
-    pub user_agent: &'static str,
-}
-
-impl Default for Config {
-    fn default() -> Self {
-        Config {
-            app_name: "sn_loader",
-            api_url: "http://www.spotternetwork.org/feeds/reports.txt",
-            poll_interval_ms: 60_000,
-            user_agent: "sigtor.org",
-        }
-    }
-}
+use wx_sn_loader::channel::ChannelSink;
+use wx_sn_loader::diff;
+use wx_sn_loader::export;
+#[cfg(feature = "nats")]
+use wx_sn_loader::nats::NatsSink;
+use wx_sn_loader::parser::ReportParser;
+use wx_sn_loader::producer::{preflight, Producer};
+use wx_sn_loader::schema;
+#[cfg(unix)]
+use wx_sn_loader::sink::UnixSocketSink;
+use wx_sn_loader::sink::{Encoding, EventSink, IdempotentSink, WriterSink};
+use wx_sn_loader::wakeup::{self, PollTimer, Wakeup};
+use wx_sn_loader::{pools_connections, Config, HttpFetcher, Output};
 
 fn main() {
-    let config = Config::default();
-    let logger = Logger::new(&config.app_name);
-    let http_client = Client::new();
-    let store_client = wx::store::Client::new();
-    let parser = ReportParser::new();
-    let mut seen: FnvHashSet<String> = FnvHashSet::default();
-
-    info!(logger, "initializing"; "config" => serde_json::to_string(&config).unwrap());
-    // TODO test loading non-utf8 file and figure out where it breaks in this module
+    let args: Vec<String> = env::args().collect();
+    if args.iter().any(|x| x == "--schema") {
+        println!("{:#}", schema::event_schema());
+        return;
+    }
 
-    loop {
-        let response = fetch_reports(&http_client, &config.api_url, &config.user_agent);
-
-        match response {
-            Ok(body) => {
-                let comparison = get_comparison(&body, seen);
-                seen = comparison.latest_set;
-
-                comparison
-                    .new
-                    .iter()
-                    .map(|report| parser.parse(report))
-                    .for_each(|event| match event {
-                        Ok(event) => {
-                            if event.is_some() {
-                                match store_client.put_event(&event.unwrap()) {
-                                    Ok(_) => info!(logger, "stored event";),
-                                    Err(e) => {
-                                        let reason = format!("unable to store event: {}", e);
-                                        error!(logger, "processing"; "reason" => reason);
-                                    }
-                                }
-                            }
-                        }
-                        Err(e) => warn!(logger, "parse"; "reason" => e.to_string()),
-                    });
+    if let Some(i) = args.iter().position(|x| x == "--trace") {
+        let line = match args.get(i + 1) {
+            Some(line) => line,
+            None => {
+                eprintln!("usage: --trace <report line>");
+                process::exit(1);
+            }
+        };
+        match ReportParser::new().trace(line) {
+            Some(groups) => {
+                for (name, value) in groups {
+                    println!("{}: {}", name, value.as_deref().unwrap_or("(no match)"));
+                }
             }
-            Err(e) => {
-                warn!(logger, "fetch_reports"; "error" => e.to_string());
+            None => {
+                eprintln!("line doesn't match the report pattern");
+                process::exit(1);
             }
         }
-
-        thread::sleep(Duration::from_millis(config.poll_interval_ms));
+        return;
     }
-}
 
-fn get_comparison(body: &str, seen: FnvHashSet<String>) -> Comparison {
-    let latest_set: FnvHashSet<String> = body
-        .lines()
-        .filter(|x| x.starts_with("Icon:"))
-        .map(|x| normalize_line(x).to_string())
-        .collect();
-
-    let new: Vec<String> = latest_set
-        .iter()
-        .filter_map(|x| {
-            if !seen.contains(x) {
-                Some(x.to_string())
-            } else {
-                None
+    if let Some(i) = args.iter().position(|x| x == "--diff") {
+        let (old, new) = match (args.get(i + 1), args.get(i + 2)) {
+            (Some(old), Some(new)) => (read_snapshot(old), read_snapshot(new)),
+            _ => {
+                eprintln!("usage: --diff <old snapshot> <new snapshot>");
+                process::exit(1);
             }
-        })
-        .collect();
+        };
+        let diff = diff::diff_feeds(&old, &new);
 
-    Comparison { latest_set, new }
-}
+        for line in &diff.added {
+            println!("+ {}", line);
+        }
+        for line in &diff.removed {
+            println!("- {}", line);
+        }
+        for (old_line, new_line) in &diff.changed {
+            println!("~ {}\n  {}", old_line, new_line);
+        }
+        return;
+    }
 
-fn fetch_reports(client: &Client, url: &str, user_agent: &str) -> Result<String, Error> {
-    let mut response = client
-        .get(url)
-        .header(header::USER_AGENT, user_agent)
-        .send()?;
-
-    match response.status() {
-        StatusCode::OK => {} // don't exit early
-        _ => {
-            let reason = format!("Unexpected status code: {}", response.status());
-            return Err(Error::Wx(<WxError>::new(&reason)));
+    let config = match args.iter().position(|x| x == "--config") {
+        Some(i) => {
+            let path = match args.get(i + 1) {
+                Some(path) => path,
+                None => {
+                    eprintln!("usage: --config <path>");
+                    process::exit(1);
+                }
+            };
+            match Config::from_file(path) {
+                Ok(config) => config,
+                Err(e) => {
+                    eprintln!("unable to load config: {}", e);
+                    process::exit(1);
+                }
+            }
         }
+        None => Config::from_env(),
+    };
+    if let Err(e) = config.validate() {
+        eprintln!("invalid config: {}", e);
+        process::exit(1);
+    }
+    let logger = Logger::new(&config.app_name);
+    let fetcher = HttpFetcher::new(&config).expect("unable to build http client");
+
+    if args.iter().any(|x| x == "--geojson") {
+        // Nothing is stored, so the sink is never written to
+        let sink = WriterSink::new(io::sink(), config.output_encoding);
+        let mut producer = Producer::new(config, fetcher, sink, logger);
+        let events = producer.dry_run().expect("unable to fetch reports");
+        println!("{}", export::feature_collection(events));
+        return;
     }
-    
-    match response.text() {
-        Ok(text) => Ok(text),
-        Err(_) => {
-            let reason = format!("Unable to read text");
-            return Err(Error::Wx(<WxError>::new(&reason)));
+
+    let sink = open_sink(&config.output, config.output_encoding, &logger);
+    let sink: Box<dyn EventSink + Send + Sync> = match config.idempotent_write_window {
+        Some(window) => Box::new(IdempotentSink::new(sink, window)),
+        None => sink,
+    };
+    // Checked ahead of the channel, whose writer threads own the sink
+    if config.preflight {
+        if let Err(e) = preflight(&fetcher, &sink, &logger) {
+            if config.exit_on_store_unavailable {
+                crit!(logger, "preflight failed, exiting"; "error" => e.to_string());
+                process::exit(1);
+            }
+            warn!(logger, "preflight failed, continuing"; "error" => e.to_string());
         }
     }
-}
 
-/**
- * Normalizes raw report lines as returned by the SpotterNetwork API. Since there is no offset,
- * you will see the same report multiple times and need to de-dupe. Unfortunately, the same
- * report will have the icon image digit change as the report ages so we need to normalize.
- */
-fn normalize_line(line: &str) -> String {
-    line.replace(",000,3", ",000,0")
-        .replace(",000,4", ",000,0")
-        .replace(",000,5", ",000,0")
-}
+    let sink: Box<dyn EventSink> = match config.channel_capacity {
+        Some(capacity) => Box::new(ChannelSink::with_workers(
+            sink,
+            capacity,
+            config.channel_drop_policy,
+            config.channel_workers,
+            logger.clone(),
+        )),
+        None => sink,
+    };
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::fs::File;
-    use std::io::Read;
-
-    #[test]
-    fn normalize_line_should_zero_icon_digit() {
-        let line = r#"Icon: 47.617706,-111.215248,000,4,4,"Reported By: Test User\nHail\nTime: 2018-09-20 22:49:29 UTC\nSize: 0.75" (Penny)\nNotes: None""#;
-        let expected = r#"Icon: 47.617706,-111.215248,000,0,4,"Reported By: Test User\nHail\nTime: 2018-09-20 22:49:29 UTC\nSize: 0.75" (Penny)\nNotes: None""#;
-        let normalized = normalize_line(line);
-        assert_eq!(normalized, expected);
-    }
+    info!(logger, "initializing"; "config" => serde_json::to_string(&config).unwrap());
+    info!(logger, "http client";
+        "pool_idle_timeout_ms" => config.http_connection_pool_idle_timeout_ms,
+        "pool_connections" => pools_connections(&config));
+    // TODO test loading non-utf8 file and figure out where it breaks in this module
+
+    let diagnostic_sink = config
+        .diagnostic_output
+        .as_ref()
+        .map(|output| open_sink(output, config.output_encoding, &logger));
 
-    #[test]
-    fn empty_report_should_return_no_seen_or_unseen() {
-        let mut file = File::open("data/reports-empty").expect("unable to open file");
-        let mut body = String::new();
-        file.read_to_string(&mut body).expect("unable to read file");
-        let comparison = get_comparison(&body, FnvHashSet::default());
-        assert_eq!(comparison.latest_set.len(), 0);
-        assert_eq!(comparison.new.len(), 0);
+    let mut producer = Producer::new(config, fetcher, sink, logger.clone());
+    if let Some(diagnostic_sink) = diagnostic_sink {
+        producer = producer.with_diagnostic_sink(diagnostic_sink);
     }
+    let timer = PollTimer::new();
+    wakeup::forward_signals(timer.waker()).expect("unable to register signal handlers");
 
-    #[test]
-    fn no_current_seen_should_return_all_reports() {
-        let mut file = File::open("data/reports").expect("unable to open file");
-        let mut body = String::new();
-        file.read_to_string(&mut body).expect("unable to read file");
-        let comparison = get_comparison(&body, FnvHashSet::default());
-        assert_eq!(comparison.latest_set.len(), 23);
-        assert_eq!(comparison.new.len(), 23);
+    loop {
+        let result = producer.poll_once();
+
+        if result.store_unavailable {
+            crit!(logger, "store unavailable, exiting";);
+            process::exit(1);
+        }
+
+        match timer.wait(producer.next_wait(&result)) {
+            Some(Wakeup::ForcePoll) => info!(logger, "forced poll";),
+            Some(Wakeup::Reload) => warn!(logger, "config reload is not supported yet, ignoring";),
+            Some(Wakeup::Shutdown) => {
+                info!(logger, "shutting down";);
+                break;
+            }
+            None => {}
+        }
     }
+}
 
-    #[test]
-    fn same_report_different_age_digit_should_be_deduped() {
-        let body = r#"Icon: 47.617706,-111.215248,000,4,4,"Reported By: Test User\nHail\nTime: 2018-09-20 22:39:00 UTC\nSize: 0.75" (Penny)\nNotes: None""#;
-        let comparison = get_comparison(&body, FnvHashSet::default());
-        assert_eq!(comparison.latest_set.len(), 1);
-        assert_eq!(comparison.new.len(), 1);
-
-        let body = r#"Icon: 47.617706,-111.215248,000,5,4,"Reported By: Test User\nHail\nTime: 2018-09-20 22:39:00 UTC\nSize: 0.75" (Penny)\nNotes: None"
-            Icon: 47.617706,-111.215248,000,6,4,"Reported By: Test User\nHail\nTime: 2018-09-20 22:39:00 UTC\nSize: 0.75" (Penny)\nNotes: None""#;
-        let comparison = get_comparison(&body, comparison.latest_set);
-        assert_eq!(comparison.latest_set.len(), 1);
-        assert_eq!(comparison.new.len(), 0);
+fn read_snapshot(path: &str) -> String {
+    match fs::read_to_string(path) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            eprintln!("unable to read {}: {}", path, e);
+            process::exit(1);
+        }
     }
+}
 
-    #[test]
-    fn get_comparison_should_handle_previously_seen_reports() {
-        let mut file = File::open("data/reports").expect("unable to open file");
-        let mut body = String::new();
-        file.read_to_string(&mut body).expect("unable to read file");
-
-        let seen: FnvHashSet<String> = vec![
-            "Icon: 41.338901,-96.059708,000,0,5,\"Reported By: Will Dupe\\nHigh Wind\\nTime: 2018-09-21 00:26:06 UTC\\n50 mphNotes: None\"".to_string(),
-            "Icon: 47.617706,-111.215248,000,0,4,\"Reported By: Will Dupe\\nHail\\nTime: 2018-09-20 22:49:29 UTC\\nSize: 0.75\" (Penny)\\nNotes: None\"".to_string(),
-            "Icon: 43.112000,-94.610001,000,0,6,\"Reported By: Will Dupe\\nFlooding\\nTime: 2018-09-20 22:58:00 UTC\\nNotes: Water over road on US 18\"".to_string(),
-            "Icon: 41.338715,-96.059563,000,0,5,\"Reported By: Will Dupe\\nHigh Wind\\nTime: 2018-09-21 00:34:00 UTC\\n60 mphNotes: Wind gusting to 63mph\"".to_string(),
-            "Icon: 35.851399,-90.708198,000,0,8,\"Reported By: Will Dupe\\nOther - See Note\\nTime: 2018-11-14 20:22:00 UTC\\nNotes: i got snow and a little of sleet\"".to_string(),
-            "Icon: 41.230400,-95.850403,000,0,3,\"Reported By: Will Dupe\\nNot Rotating Wall Cloud\\nTime: 2018-09-21 00:34:00 UTC\\nNotes: None\"".to_string(),
-        ].into_iter().collect();
-
-        let seen_length = seen.len();
-        let comparison = get_comparison(&body, seen);
-
-        assert_eq!(comparison.latest_set.len(), 23);
-        assert_eq!(
-            comparison.new.len(),
-            comparison.latest_set.len() - seen_length
-        );
+// Only NATS logs from its sink
+#[cfg_attr(not(feature = "nats"), allow(unused_variables))]
+fn open_sink(
+    output: &Output,
+    encoding: Encoding,
+    logger: &slog::Logger,
+) -> Box<dyn EventSink + Send + Sync> {
+    match output {
+        Output::Store => Box::new(wx::store::Client::new()),
+        Output::Stdout => Box::new(WriterSink::new(io::stdout(), encoding)),
+        Output::File(path) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .expect("unable to open output file");
+            Box::new(WriterSink::new(file, encoding))
+        }
+        #[cfg(unix)]
+        Output::UnixSocket(path) => Box::new(UnixSocketSink::new(path, encoding)),
+        #[cfg(feature = "nats")]
+        Output::Nats { address, subject } => {
+            Box::new(NatsSink::new(address, subject, encoding, logger.clone()))
+        }
     }
 }
@@ -0,0 +1,105 @@
+/// Store write latencies are sampled up to this many per summary window.
+const MAX_SAMPLES: usize = 4096;
+
+#[derive(Debug, PartialEq)]
+pub struct LatencySummary {
+    pub count: usize,
+    pub p50_us: u64,
+    pub p95_us: u64,
+    pub max_us: u64,
+}
+
+/**
+ * Tracks latencies over a summary window. Once the window holds `MAX_SAMPLES` samples the
+ * oldest are overwritten, but the max is tracked separately so a spike is never lost.
+ */
+#[derive(Default)]
+pub struct LatencyTracker {
+    samples: Vec<u64>,
+    next: usize,
+    count: usize,
+    max_us: u64,
+}
+
+impl LatencyTracker {
+    pub fn record(&mut self, latency_us: u64) {
+        if self.samples.len() < MAX_SAMPLES {
+            self.samples.push(latency_us);
+        } else {
+            self.samples[self.next] = latency_us;
+            self.next = (self.next + 1) % MAX_SAMPLES;
+        }
+
+        self.count += 1;
+        self.max_us = self.max_us.max(latency_us);
+    }
+
+    /// Returns the summary of the current window and starts a new one.
+    pub fn take_summary(&mut self) -> Option<LatencySummary> {
+        if self.samples.is_empty() {
+            return None;
+        }
+
+        let mut sorted = std::mem::take(&mut self.samples);
+        sorted.sort_unstable();
+
+        let summary = LatencySummary {
+            count: self.count,
+            p50_us: percentile(&sorted, 50),
+            p95_us: percentile(&sorted, 95),
+            max_us: self.max_us,
+        };
+
+        *self = LatencyTracker::default();
+        Some(summary)
+    }
+}
+
+/// Nearest-rank percentile of an already sorted, non-empty slice.
+fn percentile(sorted: &[u64], pct: usize) -> u64 {
+    let rank = (pct * sorted.len() + 99) / 100;
+    sorted[rank.max(1) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_summary_should_return_none_without_samples() {
+        let mut tracker = LatencyTracker::default();
+        assert!(tracker.take_summary().is_none());
+    }
+
+    #[test]
+    fn take_summary_should_compute_percentiles() {
+        let mut tracker = LatencyTracker::default();
+
+        // Record out of order to make sure samples are sorted
+        for ms in (1..=100).rev() {
+            tracker.record(ms * 1_000);
+        }
+
+        let summary = tracker.take_summary().unwrap();
+        assert_eq!(summary.count, 100);
+        assert_eq!(summary.p50_us, 50_000);
+        assert_eq!(summary.p95_us, 95_000);
+        assert_eq!(summary.max_us, 100_000);
+        assert!(tracker.take_summary().is_none());
+    }
+
+    #[test]
+    fn max_should_survive_sample_overwrites() {
+        let mut tracker = LatencyTracker::default();
+        tracker.record(500_000);
+
+        for _ in 0..MAX_SAMPLES {
+            tracker.record(1_000);
+        }
+
+        let summary = tracker.take_summary().unwrap();
+        assert_eq!(summary.count, MAX_SAMPLES + 1);
+        assert_eq!(summary.p95_us, 1_000);
+        assert_eq!(summary.max_us, 500_000);
+    }
+}
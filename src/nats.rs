@@ -0,0 +1,439 @@
+use crate::sink::{encode_event, Encoding, EventSink, StoreError};
+use slog::Logger;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use wx::domain::Event;
+use wx::error::{Error, WxError};
+
+/// Events held while the broker is unreachable, beyond which new events are rejected.
+const BUFFER_CAPACITY: usize = 1000;
+
+/// How long to wait on the broker's acknowledgement before treating the connection as dead.
+const ACK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait on a TCP connection, so an unreachable broker can't stall a poll for long.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+enum SendError {
+    /// The broker answered with `-ERR`, refusing the command itself.
+    Rejected(String),
+    Io(io::Error),
+}
+
+impl From<io::Error> for SendError {
+    fn from(error: io::Error) -> SendError {
+        SendError::Io(error)
+    }
+}
+
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Connection {
+    fn open(address: &str) -> io::Result<Connection> {
+        let mut last_error = None;
+        let mut writer = None;
+
+        for address in address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+                Ok(stream) => {
+                    writer = Some(stream);
+                    break;
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        let writer = match (writer, last_error) {
+            (Some(writer), _) => writer,
+            (None, Some(e)) => return Err(e),
+            (None, None) => {
+                let reason = format!("{} didn't resolve to any addresses", address);
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, reason));
+            }
+        };
+        writer.set_read_timeout(Some(ACK_TIMEOUT))?;
+        let mut connection = Connection {
+            reader: BufReader::new(writer.try_clone()?),
+            writer,
+        };
+
+        // The broker opens with an INFO line, and verbose mode acks every command with +OK
+        connection.read_line()?;
+        match connection.send(b"CONNECT {\"verbose\":true,\"pedantic\":false}\r\n") {
+            Ok(_) => Ok(connection),
+            Err(SendError::Rejected(line)) => Err(io::Error::new(io::ErrorKind::Other, line)),
+            Err(SendError::Io(e)) => Err(e),
+        }
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "broker closed",
+            ));
+        }
+
+        Ok(line.trim_end().to_string())
+    }
+
+    /// Writes a command and waits for the broker's +OK, answering any PINGs in between.
+    fn send(&mut self, command: &[u8]) -> Result<(), SendError> {
+        self.writer.write_all(command)?;
+        self.writer.flush()?;
+
+        loop {
+            let line = self.read_line()?;
+            match line.as_str() {
+                "+OK" => return Ok(()),
+                "PING" => self.writer.write_all(b"PONG\r\n")?,
+                _ if line.starts_with("-ERR") => return Err(SendError::Rejected(line)),
+                _ => {}
+            }
+        }
+    }
+
+    fn publish(&mut self, subject: &str, payload: &[u8]) -> Result<(), SendError> {
+        let mut command = format!("PUB {} {}\r\n", subject, payload.len()).into_bytes();
+        command.extend_from_slice(payload);
+        command.extend_from_slice(b"\r\n");
+        self.send(&command)
+    }
+}
+
+struct NatsState {
+    connection: Option<Connection>,
+    pending: VecDeque<Vec<u8>>,
+    /// Delay after the latest failed connection attempt, zero once connected.
+    backoff: Duration,
+    /// No connection is attempted before this, so each event doesn't wait on a dead broker.
+    next_attempt: Option<Instant>,
+    rejected: u64,
+}
+
+/**
+ * Publishes encoded events to a NATS subject, speaking the core text protocol directly rather
+ * than pulling in an async client. Each publish waits for the broker's acknowledgement, so a
+ * dropped connection is noticed right away. Events that can't be published are buffered, in
+ * order, and sent ahead of the next event once the broker is reachable again. Failed connection
+ * attempts back off exponentially, see `with_reconnect_backoff`. An event the broker answers with
+ * `-ERR`, e.g. for exceeding its maximum payload, would fail the same way every time, so it's
+ * logged and dropped rather than buffered.
+ *
+ * Delivery is at least once: if the acknowledgement for a publish the broker did receive times
+ * out, the event stays buffered and is published again, so consumers should dedup, e.g. on
+ * `event_ts` and the report's coordinates.
+ */
+pub struct NatsSink {
+    address: String,
+    subject: String,
+    encoding: Encoding,
+    min_backoff: Duration,
+    max_backoff: Duration,
+    state: Mutex<NatsState>,
+    logger: Logger,
+}
+
+impl NatsSink {
+    pub fn new(address: &str, subject: &str, encoding: Encoding, logger: Logger) -> NatsSink {
+        NatsSink {
+            address: address.to_string(),
+            subject: subject.to_string(),
+            encoding,
+            min_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            state: Mutex::new(NatsState {
+                connection: None,
+                pending: VecDeque::new(),
+                backoff: Duration::from_secs(0),
+                next_attempt: None,
+                rejected: 0,
+            }),
+            logger,
+        }
+    }
+
+    /**
+     * Waits `min` after the first failed connection attempt, doubling up to `max` for each one
+     * after it. Defaults to 1s and 60s.
+     */
+    pub fn with_reconnect_backoff(mut self, min: Duration, max: Duration) -> NatsSink {
+        self.min_backoff = min;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Events waiting for the broker to come back.
+    pub fn buffered(&self) -> usize {
+        self.state.lock().unwrap().pending.len()
+    }
+
+    /// Events dropped so far because the broker rejected them.
+    pub fn rejected(&self) -> u64 {
+        self.state.lock().unwrap().rejected
+    }
+
+    fn connect(&self, state: &mut NatsState) -> io::Result<()> {
+        if let Some(next_attempt) = state.next_attempt {
+            if Instant::now() < next_attempt {
+                let reason = "waiting to reconnect to the broker";
+                return Err(io::Error::new(io::ErrorKind::NotConnected, reason));
+            }
+        }
+
+        match Connection::open(&self.address) {
+            Ok(connection) => {
+                state.connection = Some(connection);
+                state.backoff = Duration::from_secs(0);
+                state.next_attempt = None;
+                Ok(())
+            }
+            Err(e) => {
+                state.backoff = (state.backoff * 2).clamp(self.min_backoff, self.max_backoff);
+                state.next_attempt = Some(Instant::now() + state.backoff);
+                Err(e)
+            }
+        }
+    }
+
+    /**
+     * Publishes buffered events in order until none are left or the broker can't be reached.
+     * Returns the broker's error if it rejected the last event published.
+     */
+    fn flush(&self, state: &mut NatsState) -> io::Result<Option<String>> {
+        let mut last_rejected = None;
+
+        while !state.pending.is_empty() {
+            if state.connection.is_none() {
+                self.connect(state)?;
+            }
+
+            let payload = state.pending.front().unwrap();
+            let result = state
+                .connection
+                .as_mut()
+                .unwrap()
+                .publish(&self.subject, payload);
+
+            last_rejected = match result {
+                Ok(_) => None,
+                Err(SendError::Rejected(line)) => {
+                    // The broker may close the connection after an error, so start a new one
+                    state.connection = None;
+                    state.rejected += 1;
+                    error!(self.logger, "nats broker rejected event, dropping it";
+                        "error" => &line, "rejected" => state.rejected);
+                    Some(line)
+                }
+                Err(SendError::Io(e)) => {
+                    state.connection = None;
+                    return Err(e);
+                }
+            };
+
+            state.pending.pop_front();
+        }
+
+        Ok(last_rejected)
+    }
+}
+
+impl EventSink for NatsSink {
+    fn put_event(&self, event: &Event) -> Result<(), StoreError> {
+        let payload = encode_event(event, self.encoding)?;
+        let mut state = self.state.lock().unwrap();
+
+        if state.pending.len() >= BUFFER_CAPACITY {
+            // Try to make room before turning the event away
+            let _ = self.flush(&mut state);
+            if state.pending.len() >= BUFFER_CAPACITY {
+                let reason = format!("nats buffer full, unable to reach {}", self.address);
+                return Err(StoreError::Store(Error::Wx(<WxError>::new(&reason))));
+            }
+        }
+
+        state.pending.push_back(payload);
+        // Anything unpublished stays buffered for the next event, and with nothing left buffered
+        // the last event published was this one
+        match self.flush(&mut state) {
+            Ok(Some(line)) => {
+                let reason = format!("nats broker rejected event: {}", line);
+                Err(StoreError::Rejected(Error::Wx(<WxError>::new(&reason))))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check_health(&self) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        if state.connection.is_none() {
+            state.connection = Some(Connection::open(&self.address)?);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{test_logger, wind_event};
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::sync::mpsc::{self, Receiver};
+    use std::thread;
+
+    fn test_event(ts_offset: u64) -> Event {
+        let mut event = wind_event();
+        event.event_ts += ts_offset;
+        event
+    }
+
+    /**
+     * A single connection mock broker that acks each command and sends each published subject
+     * and payload back. It hangs up after `max_publishes` publishes.
+     */
+    fn mock_broker(listener: TcpListener, max_publishes: usize) -> Receiver<(String, Vec<u8>)> {
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream
+                .write_all(b"INFO {\"server_id\":\"mock\"}\r\n")
+                .unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut published = 0;
+
+            while published < max_publishes {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    return;
+                }
+
+                let parts: Vec<&str> = line.split_whitespace().collect();
+                if parts[0] == "PUB" {
+                    let mut payload = vec![0; parts[2].parse::<usize>().unwrap() + 2];
+                    reader.read_exact(&mut payload).unwrap();
+                    payload.truncate(payload.len() - 2);
+                    sender.send((parts[1].to_string(), payload)).unwrap();
+                    published += 1;
+                }
+                stream.write_all(b"+OK\r\n").unwrap();
+            }
+        });
+
+        receiver
+    }
+
+    #[test]
+    fn nats_sink_should_publish_encoded_events() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let published = mock_broker(listener, 2);
+        let sink = NatsSink::new(&address, "sn.reports", Encoding::Json, test_logger());
+
+        sink.put_event(&test_event(0)).unwrap();
+        sink.put_event(&test_event(1)).unwrap();
+
+        for ts_offset in 0..2 {
+            let (subject, payload) = published.recv().unwrap();
+            let event: Event = serde_json::from_slice(&payload).unwrap();
+            assert_eq!(subject, "sn.reports");
+            assert!(event == test_event(ts_offset));
+        }
+        assert_eq!(sink.buffered(), 0);
+    }
+
+    #[test]
+    fn nats_sink_should_buffer_until_broker_returns() {
+        // Reserve a port with nothing listening on it yet
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        let no_backoff = Duration::from_millis(0);
+        let sink = NatsSink::new(&address, "sn.reports", Encoding::Json, test_logger())
+            .with_reconnect_backoff(no_backoff, no_backoff);
+
+        sink.put_event(&test_event(0)).unwrap();
+        sink.put_event(&test_event(1)).unwrap();
+        assert_eq!(sink.buffered(), 2);
+
+        let published = mock_broker(TcpListener::bind(&address).unwrap(), 3);
+        sink.put_event(&test_event(2)).unwrap();
+
+        for ts_offset in 0..3 {
+            let (_, payload) = published.recv().unwrap();
+            let event: Event = serde_json::from_slice(&payload).unwrap();
+            assert!(event == test_event(ts_offset));
+        }
+        assert_eq!(sink.buffered(), 0);
+    }
+
+    #[test]
+    fn nats_sink_should_back_off_reconnecting() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        let sink = NatsSink::new(&address, "sn.reports", Encoding::Json, test_logger());
+
+        sink.put_event(&test_event(0)).unwrap();
+        // The broker is back, but the next attempt isn't due for another second
+        let published = mock_broker(TcpListener::bind(&address).unwrap(), 2);
+        sink.put_event(&test_event(1)).unwrap();
+        assert_eq!(sink.buffered(), 2);
+        assert!(published.try_recv().is_err());
+    }
+
+    #[test]
+    fn nats_sink_should_drop_events_the_broker_rejects() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        let broker = thread::spawn(move || {
+            {
+                let (mut stream, _) = listener.accept().unwrap();
+                stream.write_all(b"INFO {}\r\n").unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                assert!(line.starts_with("CONNECT"));
+                stream.write_all(b"+OK\r\n").unwrap();
+
+                line.clear();
+                reader.read_line(&mut line).unwrap();
+                assert!(line.starts_with("PUB sn.reports"));
+                line.clear();
+                reader.read_line(&mut line).unwrap();
+                stream
+                    .write_all(b"-ERR 'Maximum Payload Violation'\r\n")
+                    .unwrap();
+            }
+            listener
+        });
+
+        let sink = NatsSink::new(&address, "sn.reports", Encoding::Json, test_logger());
+        match sink.put_event(&test_event(0)) {
+            Err(StoreError::Rejected(_)) => {}
+            other => panic!("expected a rejection, got {:?}", other),
+        }
+        assert_eq!(sink.buffered(), 0);
+        assert_eq!(sink.rejected(), 1);
+
+        // Later events aren't stuck behind the rejected one
+        let published = mock_broker(broker.join().unwrap(), 1);
+        sink.put_event(&test_event(1)).unwrap();
+
+        let (_, payload) = published.recv().unwrap();
+        let event: Event = serde_json::from_slice(&payload).unwrap();
+        assert!(event == test_event(1));
+        assert_eq!(sink.buffered(), 0);
+    }
+}
@@ -1,23 +1,199 @@
-use crate::domain::Hazard;
+use crate::domain::{Hazard, Magnitude};
+use crate::normalize_line;
 use chrono::prelude::*;
 use regex::Regex;
+use slog::Logger;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use wx::domain::{Coordinates, Event, EventType, Location, Report, Units};
 use wx::error::{Error, WxError};
 
-const REPORT_PATTERN: &str = r"Icon: (?P<lat>\d{2}\.\d{6}),(?P<lon>-\d{2,3}\.\d{6}),000,\d,(?P<hazard_code>\d),.Reported By: (?P<reporter>.+)\\n.+\\nTime: (?P<ts>.+) UTC(?:\\nSize: (?P<size>\d{1,2}\.\d{2}).+?)*(?:\\n(?P<mph>\d{1,3}) mph)*(?P<measured> \[Measured\])*.+otes: (?P<notes>.+).$";
+/// Largest hail on record in the US is 8", anything bigger is almost certainly a typo.
+const MAX_PLAUSIBLE_HAIL_INCHES: f64 = 8.0;
+/// Well above any measured non-tornadic gust.
+const MAX_PLAUSIBLE_WIND_MPH: f64 = 250.0;
 
+/// USPS abbreviations for the states, DC and Puerto Rico, matched by `find_state`.
+const STATE_ABBREVIATIONS: [&str; 52] = [
+    "AK", "AL", "AR", "AZ", "CA", "CO", "CT", "DC", "DE", "FL", "GA", "HI", "IA", "ID", "IL", "IN",
+    "KS", "KY", "LA", "MA", "MD", "ME", "MI", "MN", "MO", "MS", "MT", "NC", "ND", "NE", "NH", "NJ",
+    "NM", "NV", "NY", "OH", "OK", "OR", "PA", "PR", "RI", "SC", "SD", "TN", "TX", "UT", "VA", "VT",
+    "WA", "WI", "WV", "WY",
+];
+
+/// Appended to the text of events whose `event_ts` is the ingest time, see `estimate_bad_timestamps`.
+pub const ESTIMATED_TIME_MARKER: &str = "[estimated time]";
+
+const REPORT_PATTERN: &str = r"Icon: (?P<lat>\d{2}\.\d{6}),(?P<lon>-\d{2,3}\.\d{6}),000,\d,(?P<hazard_code>\d{1,2}),.Reported By: (?P<reporter>.+)\\n(?P<label>.+)\\nTime: (?P<ts>.+?) UTC(?:\\nPosted: (?P<posted>.+?) UTC)?(?:\\nSize: (?P<size>\d{1,2}(?:[.,]\d{1,2})?)(?: ?(?P<size_units>cm|inch(?:es)?|in))?.+?)*(?:\\n(?P<mph>\d{1,3}(?:[.,]\d{1,2})?) mph)*(?P<measured> \[Measured\])*.+otes: (?P<notes>.+).$";
+
+/// Settings that only affect how a single report is parsed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseOptions {
+    /// Skip Other reports that have no notes.
+    pub skip_other_none: bool,
+    /// Reject reports older than this many hours. `0` disables the check.
+    pub max_age_hours: u64,
+    /// Reject reports more than this many hours in the future. `0` disables the check.
+    pub future_tolerance_hours: u64,
+    /// Notes longer than this many bytes are truncated with a trailing `… [truncated]` marker.
+    pub max_notes_bytes: usize,
+    /// Reject hail and wind magnitudes that can't be real, e.g. a 50" hailstone.
+    pub reject_implausible_magnitudes: bool,
+    /// Hazards for report labels like `Dense Fog`, checked before the numeric hazard code.
+    pub label_overrides: HashMap<String, Hazard>,
+    /// Unescape notes and collapse their whitespace, treating blank notes like `None`.
+    pub normalize_notes: bool,
+    /// Use the ingest time for reports with an unparseable timestamp, marking their text with
+    /// `ESTIMATED_TIME_MARKER`, instead of rejecting them.
+    pub estimate_bad_timestamps: bool,
+    /// Keep each matched capture group on `SnReport::raw_captures`.
+    pub include_raw_captures: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            skip_other_none: true,
+            max_age_hours: 0,
+            future_tolerance_hours: 0,
+            max_notes_bytes: usize::MAX,
+            reject_implausible_magnitudes: false,
+            label_overrides: HashMap::new(),
+            normalize_notes: false,
+            estimate_bad_timestamps: false,
+            include_raw_captures: false,
+        }
+    }
+}
+
+/**
+ * Parses SpotterNetwork report lines into events. Every event is an `EventType::SnReport`, since
+ * the shared domain has no per-hazard report types. Consumers that need to route by hazard should
+ * key on `report.hazard`, which is always set.
+ */
 pub struct ReportParser {
     pub compiled_regex: Regex,
+    /// Used by `parse`, see `parse_with_options` to override per call.
+    pub options: ParseOptions,
+    /// Overrides the default `text`, see `render_event_template` for placeholders.
+    pub text_template: Option<String>,
+    /// Overrides the default `title`, see `render_event_template` for placeholders.
+    pub title_template: Option<String>,
+    /// Set as each event's `ext_uri` to record which feed it came from.
+    pub source_url: Option<String>,
+    /// Discards everything unless replaced, e.g. with the producer's logger.
+    pub logger: Logger,
 }
 
 impl ReportParser {
     pub fn new() -> ReportParser {
         let compiled_regex = regex::Regex::new(REPORT_PATTERN).unwrap();
 
-        ReportParser { compiled_regex }
+        ReportParser {
+            compiled_regex,
+            options: ParseOptions::default(),
+            text_template: None,
+            title_template: None,
+            source_url: None,
+            logger: Logger::root(slog::Discard, o!()),
+        }
+    }
+
+    /**
+     * Lazily parses every report line in a raw feed body, normalizing each line first. Lines are
+     * parsed as the iterator is advanced, so nothing is collected up front.
+     */
+    pub fn parse_iter<'a>(
+        &'a self,
+        body: &'a str,
+    ) -> impl Iterator<Item = Result<Option<Event>, Error>> + 'a {
+        body.lines()
+            .filter(|l| l.trim_start().starts_with("Icon:"))
+            .map(normalize_line)
+            .map(move |l| self.parse(&l))
     }
 
+    /**
+     * Lists every named group in the report pattern, in pattern order, with what it matched or
+     * `None` for optional groups that didn't. Returns `None` if the line doesn't match at all.
+     * Meant for debugging near-miss reports, see `--trace`.
+     */
+    pub fn trace(&self, report: &str) -> Option<Vec<(String, Option<String>)>> {
+        let captures = self.compiled_regex.captures(report)?;
+        let groups = self
+            .compiled_regex
+            .capture_names()
+            .flatten()
+            .map(|name| {
+                let value = captures.name(name).map(|x| x.as_str().to_string());
+                (name.to_string(), value)
+            })
+            .collect();
+
+        Some(groups)
+    }
+
+    /// Parses with the parser's own `options`.
     pub fn parse(&self, report: &str) -> Result<Option<Event>, Error> {
+        self.parse_with_options(report, &self.options)
+    }
+
+    pub fn parse_with_options(
+        &self,
+        report: &str,
+        options: &ParseOptions,
+    ) -> Result<Option<Event>, Error> {
+        self.parse_at(report, options, Utc::now().timestamp() as u64 * 1_000_000)
+    }
+
+    /**
+     * Like `parse_with_options`, but checks report age, and estimates bad timestamps, against
+     * `now` in microseconds instead of the system clock, e.g. a producer's own clock.
+     */
+    pub fn parse_at(
+        &self,
+        report: &str,
+        options: &ParseOptions,
+        now: u64,
+    ) -> Result<Option<Event>, Error> {
+        let report = match self.parse_report_at(report, options, now)? {
+            Some(report) => report,
+            None => return Ok(None),
+        };
+
+        Ok(Some(self.build_event(&report)))
+    }
+
+    /// Maps a parsed report onto an `Event` with this parser's templates and source URL.
+    #[must_use]
+    pub fn build_event(&self, report: &SnReport) -> Event {
+        let mut event = report.to_event_with_templates(
+            self.text_template.as_deref(),
+            self.title_template.as_deref(),
+        );
+        event.ext_uri = self.source_url.clone();
+        event
+    }
+
+    /**
+     * Parses and validates a report line into an `SnReport` without building an `Event`, applying
+     * the same options and checks as `parse_with_options`.
+     */
+    pub fn parse_report_with_options(
+        &self,
+        report: &str,
+        options: &ParseOptions,
+    ) -> Result<Option<SnReport>, Error> {
+        self.parse_report_at(report, options, Utc::now().timestamp() as u64 * 1_000_000)
+    }
+
+    /// Like `parse_report_with_options`, but against `now` instead of the system clock.
+    pub fn parse_report_at(
+        &self,
+        report: &str,
+        options: &ParseOptions,
+        now: u64,
+    ) -> Result<Option<SnReport>, Error> {
         let captures = self.compiled_regex.captures(report);
 
         if captures.is_none() {
@@ -27,72 +203,222 @@ impl ReportParser {
 
         let captures = captures.unwrap();
 
-        let hazard = Hazard::get_by_code(captures.name("hazard_code").unwrap().as_str())?;
-        let notes = captures.name("notes").unwrap().as_str();
-        let reporter = captures.name("reporter").unwrap().as_str();
+        let label = captures.name("label").unwrap().as_str();
+        let hazard = match options.label_overrides.get(label) {
+            Some(hazard) => hazard.clone(),
+            None => Hazard::get_by_code(captures.name("hazard_code").unwrap().as_str())?,
+        };
+        let notes = match captures.name("notes").unwrap().as_str() {
+            notes if options.normalize_notes => clean_text(notes),
+            notes => notes.to_string(),
+        };
+        let has_notes = notes != "None" && !notes.is_empty();
+        let reporter = clean_text(captures.name("reporter").unwrap().as_str());
 
         // Skip Other/None reports since they're essentially worthless
-        if hazard == Hazard::Other && notes == "None" {
+        if hazard == Hazard::Other && !has_notes && options.skip_other_none {
             return Ok(None);
         }
 
+        let mph_cap = captures.name("mph");
+        let size_cap = captures.name("size");
+
+        // The feed shouldn't include both, but if it does the wind speed wins
+        if let (Some(size), Some(mph)) = (size_cap, mph_cap) {
+            warn!(self.logger, "ambiguous magnitude"; "hazard" => hazard.to_string(),
+                "size" => size.as_str(), "mph" => mph.as_str());
+        }
+
+        let magnitude = if mph_cap.is_some() {
+            Some(Magnitude::Mph(parse_decimal(mph_cap.unwrap().as_str())?))
+        } else if size_cap.is_some() {
+            let size = parse_decimal(size_cap.unwrap().as_str())?;
+            let size = match captures.name("size_units").map(|x| x.as_str()) {
+                Some("cm") => cm_to_inches(size),
+                _ => size,
+            };
+            Some(Magnitude::Inches(size))
+        } else {
+            None
+        };
+
+        if let Some(magnitude) = magnitude {
+            if options.reject_implausible_magnitudes {
+                check_magnitude(magnitude)?;
+            }
+        }
+
+        let point = validate_coordinates(
+            captures.name("lat").unwrap().as_str().parse()?,
+            captures.name("lon").unwrap().as_str().parse()?,
+        )?;
+
+        let raw_ts = captures.name("ts").unwrap().as_str();
+        let (event_ts, is_estimated) = match parse_sn_timestamp(raw_ts) {
+            Ok(ts) => (ts.timestamp() as u64 * 1_000_000, false),
+            Err(e) if options.estimate_bad_timestamps => {
+                warn!(self.logger, "estimating timestamp"; "error" => e.to_string());
+                (now, true)
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        if !is_estimated {
+            check_report_age(event_ts, now, options)?;
+        }
+
+        // When SpotterNetwork posted the report, only in feeds that include it
+        let posted_ts = match captures
+            .name("posted")
+            .map(|x| parse_sn_timestamp(x.as_str()))
+        {
+            Some(Ok(ts)) => Some(ts.timestamp() as u64 * 1_000_000),
+            Some(Err(e)) => {
+                warn!(self.logger, "ignoring posted time"; "error" => e.to_string());
+                None
+            }
+            None => None,
+        };
+
+        let notes = if has_notes {
+            Some(truncate_notes(&notes, options.max_notes_bytes))
+        } else {
+            None
+        };
+
+        let state = notes
+            .as_deref()
+            .and_then(find_state)
+            .or_else(|| find_state(&reporter));
+
+        let raw_captures = if options.include_raw_captures {
+            let groups = self.compiled_regex.capture_names().flatten();
+            let matched = groups.filter_map(|name| {
+                let value = captures.name(name)?;
+                Some((name.to_string(), value.as_str().to_string()))
+            });
+            Some(matched.collect())
+        } else {
+            None
+        };
+
+        Ok(Some(SnReport {
+            lat: point.lat,
+            lon: point.lon,
+            hazard,
+            label: label.to_string(),
+            reporter,
+            event_ts,
+            is_estimated,
+            posted_ts,
+            magnitude,
+            was_measured: captures.name("measured").is_some(),
+            notes,
+            state,
+            raw_captures,
+        }))
+    }
+}
+
+/**
+ * The fields of a parsed and validated report line, before they're mapped onto the shared
+ * `wx::domain::Event` by `to_event`. Useful for output that doesn't need a full event.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct SnReport {
+    pub lat: f32,
+    pub lon: f32,
+    pub hazard: Hazard,
+    /// The report's label as written, e.g. `High Wind`.
+    pub label: String,
+    pub reporter: String,
+    /// Report time in microseconds since epoch, or the ingest time if `is_estimated`.
+    pub event_ts: u64,
+    /// The timestamp couldn't be parsed and `estimate_bad_timestamps` was set.
+    pub is_estimated: bool,
+    /// When SpotterNetwork posted the report, if the feed says.
+    pub posted_ts: Option<u64>,
+    pub magnitude: Option<Magnitude>,
+    pub was_measured: bool,
+    /// Cleaned and truncated per the parse options, `None` if the reporter left none.
+    pub notes: Option<String>,
+    /// State abbreviation found in the notes or else the reporter, see `find_state`.
+    pub state: Option<&'static str>,
+    /// Every capture group that matched, by name, if `include_raw_captures` was set.
+    pub raw_captures: Option<BTreeMap<String, String>>,
+}
+
+impl SnReport {
+    /// Maps onto an `Event` with the default text and title.
+    #[must_use]
+    pub fn to_event(&self) -> Event {
+        self.to_event_with_templates(None, None)
+    }
+
+    /// Like `to_event`, with templates overriding the default text or title.
+    #[must_use]
+    pub fn to_event_with_templates(
+        &self,
+        text_template: Option<&str>,
+        title_template: Option<&str>,
+    ) -> Event {
         let mut report = Report {
-            hazard: hazard.to_hazard_type(),
+            hazard: self.hazard.to_hazard_type(),
             magnitude: None,
-            report_ts: None, // not set for SN reports
-            reporter: reporter.to_string(),
+            report_ts: self.posted_ts,
+            reporter: self.reporter.clone(),
             units: None,
-            was_measured: None,
+            was_measured: if self.was_measured { Some(true) } else { None },
         };
 
-        if captures.name("measured").is_some() {
-            report.was_measured = Some(true);
+        if let Some(magnitude) = self.magnitude {
+            magnitude.apply(&mut report);
         }
 
-        let mph_cap = captures.name("mph");
-        let size_cap = captures.name("size");
-        let mut title = format!("Report: {}", hazard.to_string());
+        let notes = self.notes.as_deref().unwrap_or_default();
+        let build = |template: &str| {
+            let units = report.units.as_ref();
+            let magnitude = report.magnitude;
+            render_event_template(
+                &self.hazard,
+                &self.reporter,
+                notes,
+                template,
+                magnitude,
+                units,
+            )
+        };
 
-        if mph_cap.is_some() {
-            let mph = mph_cap.unwrap().as_str().parse()?;
-            title = format!("Report: {}mph {}", mph, hazard.to_string());
-            report.magnitude = Some(mph);
-            report.units = Some(Units::Mph);
-        } else if size_cap.is_some() {
-            let size = size_cap.unwrap().as_str().parse()?;
-            title = format!("Report: {}\" {}", size, hazard.to_string());
-            report.magnitude = Some(size);
-            report.units = Some(Units::Inches);
-        }
-
-        let location = Some(Location {
-            county: None,
-            wfo: None,
-            point: Some(Coordinates {
-                lat: captures.name("lat").unwrap().as_str().parse()?,
-                lon: captures.name("lon").unwrap().as_str().parse()?,
-            }),
-            poly: None,
-        });
+        let mut text = match text_template {
+            Some(template) => build(template),
+            None => build_event_text(&self.hazard, &self.reporter, notes),
+        };
 
-        let event_ts = Utc
-            .datetime_from_str(captures.name("ts").unwrap().as_str(), "%Y-%m-%d %H:%M:%S")?
-            .timestamp() as u64
-            * 1_000_000;
+        if self.is_estimated {
+            text = format!("{} {}", text, ESTIMATED_TIME_MARKER);
+        }
 
-        let text = if notes == "None" {
-            format!("{} reported by {}", hazard.to_string(), reporter)
-        } else {
-            format!("{} reported by {}. {}", hazard.to_string(), reporter, notes)
+        let title = match (title_template, self.magnitude) {
+            (Some(template), _) => build(template),
+            (None, Some(magnitude)) => format!("Report: {} {}", magnitude, self.hazard.to_string()),
+            (None, None) => build_event_title(&self.hazard),
         };
 
-        let event = Event {
-            event_ts,
+        Event {
+            event_ts: self.event_ts,
             event_type: EventType::SnReport,
             expires_ts: None,
             ext_uri: None,
             ingest_ts: 0, // set when storing
-            location,
+            location: Some(Location {
+                county: None,
+                wfo: None,
+                point: Some(Coordinates {
+                    lat: self.lat,
+                    lon: self.lon,
+                }),
+                poly: None,
+            }),
             md: None,
             outlook: None,
             report: Some(report),
@@ -101,15 +427,302 @@ impl ReportParser {
             valid_ts: None,
             warning: None,
             watch: None,
-        };
+        }
+    }
+}
 
-        Ok(Some(event))
+/// The default event text, e.g. `Hail reported by Jane Doe. Quarter sized`.
+pub fn build_event_text(hazard: &Hazard, reporter: &str, notes: &str) -> String {
+    match notes {
+        "" | "None" => format!("{} reported by {}", hazard.to_string(), reporter),
+        notes => format!("{} reported by {}. {}", hazard.to_string(), reporter, notes),
+    }
+}
+
+/// The default event title, before any magnitude is added.
+pub fn build_event_title(hazard: &Hazard) -> String {
+    format!("Report: {}", hazard.to_string())
+}
+
+/**
+ * Fills in `{hazard}`, `{reporter}`, `{notes}`, `{magnitude}`, `{units}`, `{reporter_org}` and
+ * `{state}` in a template, with the state found in the notes or else the reporter.
+ * `{magnitude_text}` is the magnitude formatted like titles, e.g. `0.75"` or `60 mph`, while
+ * `{magnitude}` keeps the value as reported.
+ * Placeholders without a value become empty, e.g. `{magnitude}` on a tornado report, and anything
+ * else in braces is left as is.
+ */
+pub fn render_event_template(
+    hazard: &Hazard,
+    reporter: &str,
+    notes: &str,
+    template: &str,
+    magnitude: Option<f64>,
+    units: Option<&Units>,
+) -> String {
+    let magnitude_text = match (magnitude, units) {
+        (Some(value), Some(Units::Mph)) => Magnitude::Mph(value).to_string(),
+        (Some(value), Some(Units::Knots)) => Magnitude::Knots(value).to_string(),
+        (Some(value), Some(Units::Inches)) => Magnitude::Inches(value).to_string(),
+        _ => String::new(),
+    };
+    let magnitude = magnitude.map(|x| x.to_string()).unwrap_or_default();
+    let units = match units {
+        Some(Units::Inches) => "in",
+        Some(Units::Mph) => "mph",
+        Some(Units::Knots) => "kt",
+        None => "",
+    };
+    let state = find_state(notes).or_else(|| find_state(reporter));
+
+    template
+        .replace("{hazard}", &hazard.to_string())
+        .replace("{reporter_org}", get_reporter_org(reporter))
+        .replace("{reporter}", reporter)
+        .replace("{notes}", notes)
+        .replace("{magnitude_text}", &magnitude_text)
+        .replace("{magnitude}", &magnitude)
+        .replace("{units}", units)
+        .replace("{state}", state.unwrap_or_default())
+}
+
+/**
+ * Finds a state abbreviation written after a comma, like `3 mi N of Ames, IA`, taking the last one
+ * if there are several. Requiring the comma keeps words like `OK` or `IN` in notes from matching.
+ */
+pub fn find_state(text: &str) -> Option<&'static str> {
+    let (_, after_comma) = text.split_once(',')?;
+    after_comma.rsplit(',').find_map(|segment| {
+        let segment = segment.trim_start();
+        let candidate = segment.get(..2)?;
+        let is_whole_word = !segment[2..].starts_with(|c: char| c.is_alphanumeric());
+
+        match STATE_ABBREVIATIONS.iter().find(|x| **x == candidate) {
+            Some(state) if is_whole_word => Some(*state),
+            _ => None,
+        }
+    })
+}
+
+/**
+ * Undoes the feed's escaping in reporter names and notes. Escaped line breaks and tabs become
+ * spaces, `\"` and `\\` keep just the character, and whitespace is collapsed and trimmed. Anything
+ * else that looks like an escape is left as written, since it's more likely a literal backslash.
+ */
+fn clean_text(raw: &str) -> String {
+    let mut cleaned = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            cleaned.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') | Some('r') | Some('t') => cleaned.push(' '),
+            Some(escaped @ '"') | Some(escaped @ '\\') => cleaned.push(escaped),
+            Some(other) => {
+                cleaned.push(c);
+                cleaned.push(other);
+            }
+            None => {}
+        }
+    }
+
+    cleaned.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// Reporters sometimes append their organization in parens, e.g. `Jane Doe (KS Storm Chasers)`.
+fn get_reporter_org(reporter: &str) -> &str {
+    match get_trailing_parens(reporter) {
+        Some((_, inner)) if !is_callsign(inner) => inner,
+        _ => "",
+    }
+}
+
+/**
+ * Splits an amateur radio callsign off a reporter name, e.g. `John Doe (W0XYZ)` becomes
+ * `("John Doe", Some("W0XYZ"))`. Only a callsign in trailing parens counts, so
+ * `Bob (K4ABC) Jones` comes back whole with no callsign, as does an organization like
+ * `Jane Doe (KS Storm Chasers)`.
+ */
+pub fn parse_reporter_name(reporter: &str) -> (&str, Option<&str>) {
+    match get_trailing_parens(reporter) {
+        Some((name, inner)) if is_callsign(inner) => (name, Some(inner)),
+        _ => (reporter.trim(), None),
+    }
+}
+
+/// Splits `name (inner)` into its trimmed name and the text inside the trailing parens.
+fn get_trailing_parens(reporter: &str) -> Option<(&str, &str)> {
+    let reporter = reporter.trim_end();
+
+    match (reporter.rfind('('), reporter.ends_with(')')) {
+        (Some(start), true) => Some((
+            reporter[..start].trim(),
+            &reporter[start + 1..reporter.len() - 1],
+        )),
+        _ => None,
+    }
+}
+
+/// Loosely matches a callsign: 3 to 7 uppercase letters and digits, with at least one of each.
+fn is_callsign(text: &str) -> bool {
+    (3..=7).contains(&text.len())
+        && text
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+        && text.chars().any(|c| c.is_ascii_digit())
+        && text.chars().any(|c| c.is_ascii_uppercase())
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    InvalidCoordinates {
+        lat: f64,
+        lon: f64,
+        reason: &'static str,
+    },
+    InvalidTimestamp(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::InvalidCoordinates { lat, lon, reason } => {
+                write!(f, "invalid coordinates {},{}: {}", lat, lon, reason)
+            }
+            ParseError::InvalidTimestamp(raw) => write!(f, "invalid timestamp: {:?}", raw),
+        }
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(error: ParseError) -> Error {
+        Error::Wx(<WxError>::new(&error.to_string()))
+    }
+}
+
+/// Naive formats, read as UTC. The first is what the feed sends today.
+const NAIVE_TIMESTAMP_FORMATS: [&str; 2] = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S"];
+
+/**
+ * Parses a report timestamp as the feed writes it, `2018-09-20 22:52:00` with an optional trailing
+ * `UTC`, falling back to naive ISO 8601 and then to RFC 3339 or a numeric offset such as
+ * `2018-09-20 17:52:00 -0500`, which are converted to UTC.
+ */
+pub fn parse_sn_timestamp(raw: &str) -> Result<DateTime<Utc>, ParseError> {
+    let trimmed = raw.trim();
+    let naive = trimmed.trim_end_matches(" UTC");
+
+    let parsed = NAIVE_TIMESTAMP_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(naive, format).ok())
+        .map(|ts| Utc.from_utc_datetime(&ts))
+        .or_else(|| {
+            DateTime::parse_from_rfc3339(trimmed)
+                .ok()
+                .map(|ts| ts.with_timezone(&Utc))
+        })
+        .or_else(|| {
+            DateTime::parse_from_str(trimmed, "%Y-%m-%d %H:%M:%S %z")
+                .ok()
+                .map(|ts| ts.with_timezone(&Utc))
+        });
+
+    parsed.ok_or_else(|| ParseError::InvalidTimestamp(raw.to_string()))
+}
+
+/// Checks that a lat/lon pair is finite and on the globe, with inclusive bounds.
+pub fn validate_coordinates(lat: f64, lon: f64) -> Result<Coordinates, ParseError> {
+    let reason = if !lat.is_finite() || !lon.is_finite() {
+        Some("not a finite number")
+    } else if !(-90.0..=90.0).contains(&lat) {
+        Some("latitude out of range")
+    } else if !(-180.0..=180.0).contains(&lon) {
+        Some("longitude out of range")
+    } else {
+        None
+    };
+
+    match reason {
+        Some(reason) => Err(ParseError::InvalidCoordinates { lat, lon, reason }),
+        None => Ok(Coordinates {
+            lat: lat as f32,
+            lon: lon as f32,
+        }),
+    }
+}
+
+fn check_magnitude(magnitude: Magnitude) -> Result<(), Error> {
+    let max = match magnitude {
+        Magnitude::Inches(_) => MAX_PLAUSIBLE_HAIL_INCHES,
+        Magnitude::Mph(_) => MAX_PLAUSIBLE_WIND_MPH,
+        Magnitude::Knots(_) => return Ok(()),
+    };
+
+    if magnitude.value() > max {
+        let reason = format!("implausible magnitude: {}", magnitude.value());
+        Err(Error::Wx(<WxError>::new(&reason)))
+    } else {
+        Ok(())
+    }
+}
+
+/// Checks `event_ts` against the age window in `options`, all in microseconds since epoch.
+fn check_report_age(event_ts: u64, now: u64, options: &ParseOptions) -> Result<(), Error> {
+    let hour_us = 3_600 * 1_000_000;
+    let reason = if options.max_age_hours > 0 && event_ts + options.max_age_hours * hour_us < now {
+        Some("report too old")
+    } else if options.future_tolerance_hours > 0
+        && event_ts > now + options.future_tolerance_hours * hour_us
+    {
+        Some("report in the future")
+    } else {
+        None
+    };
+
+    match reason {
+        Some(reason) => Err(Error::Wx(<WxError>::new(reason))),
+        None => Ok(()),
+    }
+}
+
+/// Parses a magnitude, accepting a comma decimal separator as written in some locales.
+fn parse_decimal(text: &str) -> Result<f64, std::num::ParseFloatError> {
+    text.replace(',', ".").parse()
+}
+
+/// Converts a metric hail size to inches, rounded to the hundredths the feed normally uses.
+fn cm_to_inches(cm: f64) -> f64 {
+    (cm / 2.54 * 100.0).round() / 100.0
+}
+
+/// Truncates to at most `max_len` bytes without splitting a multibyte character.
+fn truncate_notes(notes: &str, max_len: usize) -> String {
+    if notes.len() <= max_len {
+        return notes.to_string();
+    }
+
+    let mut end = max_len;
+    while !notes.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    format!("{}… [truncated]", &notes[..end])
+}
+
+impl Default for ReportParser {
+    fn default() -> Self {
+        ReportParser::new()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util::{wind_report, CapturingDrain, WIND_REPORT};
     use std::fs::File;
     use std::io::{BufRead, BufReader};
     use wx::domain::HazardType;
@@ -130,11 +743,30 @@ mod tests {
             });
     }
 
+    #[test]
+    fn parse_should_emit_empty_other_reports_when_enabled() {
+        let mut parser = ReportParser::new();
+        parser.options.skip_other_none = false;
+        let reports_file = File::open("data/reports-other-none").unwrap();
+        let reader = BufReader::new(reports_file);
+
+        reader
+            .lines()
+            .map(|x| x.unwrap())
+            .filter(|x| x.starts_with("Icon:"))
+            .for_each(|x| {
+                let event = parser.parse(&x).unwrap().unwrap();
+                assert!(event.report.unwrap().hazard == HazardType::Other);
+                assert_eq!(event.title, "Report: Other");
+                assert_eq!(event.text.unwrap(), "Other reported by Test User");
+            });
+    }
+
     #[test]
     fn parse_should_return_an_event_with_all_required_fields() {
         let parser = ReportParser::new();
-        let report = r#"Icon: 43.112000,-94.639999,000,3,5,"Reported By: Test Human\nHigh Wind\nTime: 2018-09-20 22:52:00 UTC\n60 mph [Measured]\nNotes: Strong winds measured at 60mph with anemometer""#;
-        let event = parser.parse(report).unwrap().unwrap();
+        let report = wind_report("Strong winds measured at 60mph with anemometer");
+        let event = parser.parse(&report).unwrap().unwrap();
 
         assert!(
             event == Event {
@@ -166,7 +798,7 @@ mod tests {
                     "Wind reported by Test Human. Strong winds measured at 60mph with anemometer"
                         .to_string()
                 ),
-                title: "Report: 60mph Wind".to_string(),
+                title: "Report: 60 mph Wind".to_string(),
                 valid_ts: None,
                 warning: None,
                 watch: None
@@ -174,31 +806,1026 @@ mod tests {
         );
     }
 
+    /**
+     * Names the fields of an event that are set. The patterns are exhaustive, so a field added to
+     * `Event`, `Location` or `Report` won't compile until it's listed here.
+     */
+    fn populated_fields(event: &Event) -> Vec<&'static str> {
+        let Event {
+            event_ts: _,
+            event_type: _,
+            expires_ts,
+            ext_uri,
+            ingest_ts: _,
+            location,
+            md,
+            outlook,
+            report,
+            text,
+            title: _,
+            valid_ts,
+            warning,
+            watch,
+        } = event;
+        let mut fields = vec![];
+        let mut check = |name, is_set: bool| {
+            if is_set {
+                fields.push(name);
+            }
+        };
+
+        check("expires_ts", expires_ts.is_some());
+        check("ext_uri", ext_uri.is_some());
+        check("md", md.is_some());
+        check("outlook", outlook.is_some());
+        check("text", text.is_some());
+        check("valid_ts", valid_ts.is_some());
+        check("warning", warning.is_some());
+        check("watch", watch.is_some());
+
+        if let Some(Location {
+            county,
+            wfo,
+            point,
+            poly,
+        }) = location
+        {
+            check("location", true);
+            check("location.county", county.is_some());
+            check("location.wfo", wfo.is_some());
+            check("location.point", point.is_some());
+            check("location.poly", poly.is_some());
+        }
+
+        if let Some(Report {
+            hazard: _,
+            magnitude,
+            report_ts,
+            reporter: _,
+            units,
+            was_measured,
+        }) = report
+        {
+            check("report", true);
+            check("report.magnitude", magnitude.is_some());
+            check("report.report_ts", report_ts.is_some());
+            check("report.units", units.is_some());
+            check("report.was_measured", was_measured.is_some());
+        }
+
+        fields
+    }
+
+    #[test]
+    fn parse_should_populate_expected_fields_per_hazard() {
+        let parser = ReportParser::new();
+        let always = ["text", "location", "location.point", "report"];
+        let cases = [
+            (
+                WIND_REPORT,
+                vec!["report.magnitude", "report.units", "report.was_measured"],
+            ),
+            (
+                r#"Icon: 47.617706,-111.215248,000,0,4,"Reported By: Test User\nHail\nTime: 2018-09-20 22:49:29 UTC\nSize: 0.75" (Penny)\nNotes: None""#,
+                vec!["report.magnitude", "report.units"],
+            ),
+            (
+                r#"Icon: 35.339300,-97.486700,000,0,1,"Reported By: Test User\nTornado\nTime: 2018-09-20 22:49:29 UTC\nNotes: Rope tornado""#,
+                vec![],
+            ),
+            (
+                r#"Icon: 41.586800,-93.625000,000,0,6,"Reported By: Test User\nFlooding\nTime: 2018-09-20 22:49:29 UTC\nNotes: Water over road""#,
+                vec![],
+            ),
+        ];
+
+        for (report, extra) in cases.iter() {
+            let event = parser.parse(report).unwrap().unwrap();
+            let mut expected: Vec<&str> = always.iter().chain(extra).cloned().collect();
+            let mut actual = populated_fields(&event);
+            expected.sort_unstable();
+            actual.sort_unstable();
+
+            assert_eq!(actual, expected, "{}", event.title);
+        }
+    }
+
+    fn hail_sn_report() -> SnReport {
+        SnReport {
+            lat: 47.617706,
+            lon: -111.215_25,
+            hazard: Hazard::Hail,
+            label: "Hail".to_string(),
+            reporter: "Test User".to_string(),
+            event_ts: 1_537_483_769_000_000,
+            is_estimated: false,
+            posted_ts: None,
+            magnitude: Some(Magnitude::Inches(0.75)),
+            was_measured: false,
+            notes: Some("Penny sized".to_string()),
+            state: None,
+            raw_captures: None,
+        }
+    }
+
+    #[test]
+    fn parse_report_should_capture_raw_fields() {
+        let parser = ReportParser::new();
+        let report = r#"Icon: 47.617706,-111.215248,000,0,4,"Reported By: Test User\nHail\nTime: 2018-09-20 22:49:29 UTC\nSize: 0.75" (Penny)\nNotes: Penny sized""#;
+        let parsed = parser
+            .parse_report_with_options(report, &ParseOptions::default())
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(parsed, hail_sn_report());
+    }
+
+    #[test]
+    fn sn_report_to_event_should_map_every_field() {
+        let event = hail_sn_report().to_event();
+        let report = event.report.as_ref().unwrap();
+        let point = event.location.as_ref().unwrap().point.as_ref().unwrap();
+
+        assert_eq!(event.event_ts, 1_537_483_769_000_000);
+        assert_eq!(event.title, "Report: 0.75\" Hail");
+        assert_eq!(
+            event.text.as_deref(),
+            Some("Hail reported by Test User. Penny sized")
+        );
+        assert!(event.ext_uri.is_none());
+        assert_eq!((point.lat, point.lon), (47.617706, -111.215_25));
+        assert!(report.units == Some(Units::Inches));
+        assert_eq!(report.magnitude, Some(0.75));
+        assert_eq!(report.reporter, "Test User");
+        assert_eq!(report.was_measured, None);
+    }
+
+    #[test]
+    fn sn_report_to_event_should_apply_templates_and_estimated_marker() {
+        let sn_report = SnReport {
+            is_estimated: true,
+            ..hail_sn_report()
+        };
+        let event = sn_report.to_event_with_templates(Some("{notes}"), Some("{magnitude}{units}"));
+
+        assert_eq!(event.title, "0.75in");
+        assert_eq!(
+            event.text.unwrap(),
+            format!("Penny sized {}", ESTIMATED_TIME_MARKER)
+        );
+    }
+
+    #[test]
+    fn parse_iter_should_match_manual_pipeline() {
+        let parser = ReportParser::new();
+        let body = std::fs::read_to_string("data/reports").unwrap();
+        let expected: Vec<_> = body
+            .lines()
+            .filter(|x| x.starts_with("Icon:"))
+            .map(normalize_line)
+            .map(|x| parser.parse(&x))
+            .collect();
+        let actual: Vec<_> = parser.parse_iter(&body).collect();
+
+        assert_eq!(actual.len(), expected.len());
+        for (actual, expected) in actual.iter().zip(expected.iter()) {
+            match (actual, expected) {
+                (Ok(a), Ok(e)) => assert!(a == e),
+                (Err(_), Err(_)) => {}
+                _ => panic!("parse_iter and the manual pipeline disagree"),
+            }
+        }
+    }
+
+    #[test]
+    fn parse_iter_should_be_lazy() {
+        // Each of these logs one "ambiguous magnitude" warning as it's parsed, counting the parses
+        let drain = CapturingDrain::default();
+        let mut parser = ReportParser::new();
+        parser.logger = Logger::root(drain.clone(), o!());
+        let report = r#"Icon: 47.617706,-111.215248,000,4,4,"Reported By: Test Human\nHail\nTime: 2018-09-20 22:49:29 UTC\nSize: 1.00"\n60 mph\nNotes: None""#;
+        let body = format!("{}\n{}\n{}\n", report, report, report);
+        let mut iter = parser.parse_iter(&body);
+
+        assert!(drain.messages.lock().unwrap().is_empty());
+        assert!(iter.next().unwrap().unwrap().is_some());
+        assert_eq!(drain.messages.lock().unwrap().len(), 1);
+
+        assert_eq!(iter.count(), 2);
+        assert_eq!(drain.messages.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_all_hazard_types() {
+        let parser = ReportParser::new();
+        let expected = vec![
+            HazardType::Tornado,
+            HazardType::Funnel,
+            HazardType::WallCloud,
+            HazardType::Hail,
+            HazardType::Wind,
+            HazardType::Flood,
+            HazardType::Flood,
+            HazardType::Other,
+            HazardType::FreezingRain,
+            HazardType::Snow,
+        ];
+
+        for (code, hazard) in (1..=10).zip(expected) {
+            let report = format!(
+                r#"Icon: 43.112000,-94.639999,000,0,{},"Reported By: Test Human\nSomething\nTime: 2018-09-20 22:52:00 UTC\nNotes: Hazard test""#,
+                code
+            );
+            let event = parser.parse(&report).unwrap().unwrap();
+            assert!(event.report.unwrap().hazard == hazard, "code: {}", code);
+        }
+    }
+
+    #[test]
+    fn parse_should_carry_hazard_for_routing() {
+        let parser = ReportParser::new();
+
+        for hazard in Hazard::all() {
+            let report = format!(
+                r#"Icon: 43.112000,-94.639999,000,0,{},"Reported By: Test Human\nSomething\nTime: 2018-09-20 22:52:00 UTC\nNotes: Routing test""#,
+                hazard.code()
+            );
+            let event = parser.parse(&report).unwrap().unwrap();
+
+            assert!(event.event_type == EventType::SnReport);
+            assert!(event.report.unwrap().hazard == hazard.to_hazard_type());
+        }
+    }
+
     #[test]
     fn report_should_not_blow_up_with_non_utf8_characters() {
         let parser = ReportParser::new();
-        let report = r#"Icon: 43.112000,-94.639999,000,3,5,"Reported By: Test Human\nHigh Wind\nTime: 2018-09-20 22:52:00 UTC\n60 mph [Measured]\nNotes: Strong �������������������������������������������������������������������� measured at 60mph with anemometer""#;
-        let event = parser.parse(report);
+        let report = wind_report("Strong �������������������������������������������������������������������� measured at 60mph with anemometer");
+        let event = parser.parse(&report);
         assert!(event.is_ok());
     }
 
+    #[test]
+    fn truncate_notes_should_respect_char_boundaries() {
+        // Each snowflake is three bytes, so a 7 byte limit has to back off to 6
+        assert_eq!(truncate_notes("❄❄❄❄", 7), "❄❄… [truncated]");
+        assert_eq!(truncate_notes("❄❄❄❄", 6), "❄❄… [truncated]");
+        assert_eq!(truncate_notes("❄❄❄❄", 12), "❄❄❄❄");
+        assert_eq!(truncate_notes("abc", 0), "… [truncated]");
+    }
+
+    #[test]
+    fn parse_should_truncate_long_notes() {
+        let mut parser = ReportParser::new();
+        parser.options.max_notes_bytes = 20;
+        let report = wind_report("Trees down — large limbs everywhere");
+        let event = parser.parse(&report).unwrap().unwrap();
+
+        // The em dash starts at byte 11 and ends at byte 14, so it fits
+        assert_eq!(
+            event.text.unwrap(),
+            "Wind reported by Test Human. Trees down — large… [truncated]"
+        );
+    }
+
+    #[test]
+    fn parse_should_handle_notes_over_4kb() {
+        let parser = ReportParser::new();
+        let notes = "x".repeat(4_097);
+        let report = wind_report(&notes);
+        let event = parser.parse(&report).unwrap().unwrap();
+        assert!(event.text.unwrap().ends_with(&notes));
+
+        let mut parser = ReportParser::new();
+        parser.options.max_notes_bytes = 1024;
+        let event = parser.parse(&report).unwrap().unwrap();
+        let expected = format!(
+            "Wind reported by Test Human. {}… [truncated]",
+            &notes[..1024]
+        );
+        assert_eq!(event.text.unwrap(), expected);
+    }
+
+    #[test]
+    fn parse_should_not_truncate_notes_by_default() {
+        let parser = ReportParser::new();
+        let notes = "x".repeat(5_000);
+        let report = wind_report(&notes);
+        let event = parser.parse(&report).unwrap().unwrap();
+        assert!(event.text.unwrap().ends_with(&notes));
+    }
+
     #[test]
     fn report_should_parse_optional_mph() {
         let parser = ReportParser::new();
-        let report = r#"Icon: 43.112000,-94.639999,000,3,5,"Reported By: Test Human\nHigh Wind\nTime: 2018-09-20 22:52:00 UTC\n60 mph [Measured]\nNotes: Strong winds measured at 60mph with anemometer""#;
-        let parsed_report = parser.parse(report).unwrap().unwrap().report.unwrap();
+        let report = wind_report("Strong winds measured at 60mph with anemometer");
+        let parsed_report = parser.parse(&report).unwrap().unwrap().report.unwrap();
         assert!(parsed_report.magnitude == Some(60.0));
         assert!(parsed_report.units == Some(Units::Mph));
     }
 
+    #[test]
+    fn report_should_parse_comma_decimal_magnitudes() {
+        let parser = ReportParser::new();
+        let wind = r#"Icon: 43.112000,-94.639999,000,3,5,"Reported By: Test Human\nHigh Wind\nTime: 2018-09-20 22:52:00 UTC\n60,5 mph [Measured]\nNotes: None""#;
+        let event = parser.parse(wind).unwrap().unwrap();
+        let report = event.report.unwrap();
+        assert!(report.magnitude == Some(60.5));
+        assert!(report.units == Some(Units::Mph));
+        assert!(report.was_measured == Some(true));
+        assert_eq!(event.title, "Report: 61 mph Wind");
+
+        let hail = r#"Icon: 47.617706,-111.215248,000,4,4,"Reported By: Test Human\nHail\nTime: 2018-09-20 22:49:29 UTC\nSize: 0,75" (Penny)\nNotes: None""#;
+        let event = parser.parse(hail).unwrap().unwrap();
+        let report = event.report.unwrap();
+        assert!(report.magnitude == Some(0.75));
+        assert!(report.units == Some(Units::Inches));
+        // The coordinates are still split on the field separators
+        let point = event.location.unwrap().point.unwrap();
+        assert!((point.lat - 47.617_706).abs() < 1e-4);
+    }
+
+    #[test]
+    fn bad_timestamp_should_be_rejected_by_default() {
+        let report = r#"Icon: 43.112000,-94.639999,000,3,5,"Reported By: Test Human\nHigh Wind\nTime: yesterday-ish UTC\n60 mph [Measured]\nNotes: None""#;
+        assert!(ReportParser::new().parse(report).is_err());
+    }
+
+    #[test]
+    fn bad_timestamp_should_be_estimated_when_enabled() {
+        let report = r#"Icon: 43.112000,-94.639999,000,3,5,"Reported By: Test Human\nHigh Wind\nTime: yesterday-ish UTC\n60 mph [Measured]\nNotes: None""#;
+        let options = ParseOptions {
+            estimate_bad_timestamps: true,
+            ..ParseOptions::default()
+        };
+        let drain = CapturingDrain::default();
+        let mut parser = ReportParser::new();
+        parser.logger = Logger::root(drain.clone(), o!());
+
+        let before = Utc::now().timestamp() as u64 * 1_000_000;
+        let event = parser
+            .parse_with_options(report, &options)
+            .unwrap()
+            .unwrap();
+
+        assert!(event.event_ts >= before);
+        assert!(event.event_ts <= Utc::now().timestamp() as u64 * 1_000_000);
+        assert!(event.text.unwrap().ends_with(ESTIMATED_TIME_MARKER));
+        assert!(event.report.unwrap().magnitude == Some(60.0));
+        assert_eq!(
+            *drain.messages.lock().unwrap(),
+            vec!["estimating timestamp"]
+        );
+    }
+
+    #[test]
+    fn valid_timestamp_should_not_be_marked_estimated() {
+        let options = ParseOptions {
+            estimate_bad_timestamps: true,
+            ..ParseOptions::default()
+        };
+        let event = ReportParser::new()
+            .parse_with_options(WIND_REPORT, &options)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(event.event_ts, 1_537_483_920_000_000);
+        assert!(!event.text.unwrap().contains(ESTIMATED_TIME_MARKER));
+    }
+
+    #[test]
+    fn trace_should_list_each_group_in_order() {
+        let parser = ReportParser::new();
+        let trace = parser.trace(WIND_REPORT).unwrap();
+        let group = |name: &str| trace.iter().find(|(x, _)| x == name).unwrap().1.clone();
+
+        let names: Vec<&str> = trace.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "lat",
+                "lon",
+                "hazard_code",
+                "reporter",
+                "label",
+                "ts",
+                "posted",
+                "size",
+                "size_units",
+                "mph",
+                "measured",
+                "notes"
+            ]
+        );
+        assert_eq!(group("lat").as_deref(), Some("43.112000"));
+        assert_eq!(group("label").as_deref(), Some("High Wind"));
+        assert_eq!(group("mph").as_deref(), Some("60"));
+        assert_eq!(group("measured").as_deref(), Some(" [Measured]"));
+        assert_eq!(group("size"), None);
+        assert_eq!(group("size_units"), None);
+    }
+
+    #[test]
+    fn parse_should_set_report_ts_from_posted_time() {
+        let parser = ReportParser::new();
+        let report = r#"Icon: 43.112000,-94.639999,000,3,5,"Reported By: Test Human\nHigh Wind\nTime: 2018-09-20 22:52:00 UTC\nPosted: 2018-09-20 22:55:30 UTC\n60 mph [Measured]\nNotes: None""#;
+        let event = parser.parse(report).unwrap().unwrap();
+        let report = event.report.unwrap();
+
+        assert_eq!(event.event_ts, 1_537_483_920_000_000);
+        assert_eq!(report.report_ts, Some(1_537_484_130_000_000));
+        assert_eq!(report.magnitude, Some(60.0));
+    }
+
+    #[test]
+    fn parse_should_leave_report_ts_unset_without_posted_time() {
+        let parser = ReportParser::new();
+        let event = parser.parse(WIND_REPORT).unwrap().unwrap();
+
+        assert_eq!(event.report.unwrap().report_ts, None);
+    }
+
+    #[test]
+    fn parse_report_should_keep_raw_captures_when_enabled() {
+        let parser = ReportParser::new();
+        let report = wind_report("Tree down");
+        let mut options = ParseOptions::default();
+        let parsed = parser.parse_report_with_options(&report, &options).unwrap();
+        assert_eq!(parsed.unwrap().raw_captures, None);
+
+        options.include_raw_captures = true;
+        let parsed = parser
+            .parse_report_with_options(&report, &options)
+            .unwrap()
+            .unwrap();
+        // Kept off the event, whose text consumers display as is
+        let text = parsed.to_event().text.unwrap();
+        assert_eq!(text, "Wind reported by Test Human. Tree down");
+
+        let raw_captures = parsed.raw_captures.unwrap();
+        assert_eq!(raw_captures["lat"], "43.112000");
+        assert_eq!(raw_captures["lon"], "-94.639999");
+        assert_eq!(raw_captures["hazard_code"], "5");
+        assert_eq!(raw_captures["label"], "High Wind");
+        assert_eq!(raw_captures["ts"], "2018-09-20 22:52:00");
+        assert_eq!(raw_captures["mph"], "60");
+        assert_eq!(raw_captures["measured"], " [Measured]");
+        assert_eq!(raw_captures["notes"], "Tree down");
+        // Optional groups that didn't match are left out
+        assert!(!raw_captures.contains_key("size"));
+    }
+
+    #[test]
+    fn trace_should_be_none_without_match() {
+        assert!(ReportParser::new().trace("Icon: garbage").is_none());
+    }
+
     #[test]
     fn report_should_parse_optional_measured() {
         let parser = ReportParser::new();
-        let report = r#"Icon: 43.112000,-94.639999,000,3,5,"Reported By: Test Human\nHigh Wind\nTime: 2018-09-20 22:52:00 UTC\n60 mph [Measured]\nNotes: Strong winds measured at 60mph with anemometer""#;
-        let parsed_report = parser.parse(report).unwrap().unwrap().report.unwrap();
+        let report = wind_report("Strong winds measured at 60mph with anemometer");
+        let parsed_report = parser.parse(&report).unwrap().unwrap().report.unwrap();
         assert!(parsed_report.was_measured == Some(true));
     }
 
+    #[test]
+    fn parse_should_warn_and_prefer_mph_when_both_magnitudes_present() {
+        let drain = CapturingDrain::default();
+        let mut parser = ReportParser::new();
+        parser.logger = Logger::root(drain.clone(), o!());
+        let report = r#"Icon: 47.617706,-111.215248,000,4,4,"Reported By: Test Human\nHail\nTime: 2018-09-20 22:49:29 UTC\nSize: 1.00"\n60 mph\nNotes: None""#;
+        let parsed_report = parser.parse(report).unwrap().unwrap().report.unwrap();
+
+        assert!(parsed_report.magnitude == Some(60.0));
+        assert!(parsed_report.units == Some(Units::Mph));
+        assert_eq!(*drain.messages.lock().unwrap(), vec!["ambiguous magnitude"]);
+    }
+
+    #[test]
+    fn parse_should_not_warn_for_a_single_magnitude() {
+        let drain = CapturingDrain::default();
+        let mut parser = ReportParser::new();
+        parser.logger = Logger::root(drain.clone(), o!());
+        let report = r#"Icon: 47.617706,-111.215248,000,4,4,"Reported By: Test Human\nHail\nTime: 2018-09-20 22:49:29 UTC\nSize: 0.75" (Penny)\nNotes: None""#;
+        parser.parse(report).unwrap().unwrap();
+
+        assert!(drain.messages.lock().unwrap().is_empty());
+    }
+
+    /*
+     * `parse` leans on `wx`'s `From` impls to turn these into an `Error` with `?`. The impls can
+     * only live in `wx` itself, so these fail to compile if one of them goes away.
+     */
+    #[test]
+    fn parse_float_error_should_convert_to_error() {
+        let convert = || -> Result<f64, Error> { Ok("60 mph".parse::<f64>()?) };
+
+        match convert() {
+            Err(Error::ParseFloat(e)) => assert_eq!(e, "60 mph".parse::<f64>().unwrap_err()),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("should not parse"),
+        }
+    }
+
+    #[test]
+    fn chrono_parse_error_should_convert_to_error() {
+        let parse = || Utc.datetime_from_str("2018-09-20 UTC", "%Y-%m-%d %H:%M:%S");
+        let convert = || -> Result<DateTime<Utc>, Error> { Ok(parse()?) };
+
+        match convert() {
+            Err(Error::Chrono(e)) => assert_eq!(e, parse().unwrap_err()),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("should not parse"),
+        }
+    }
+
+    #[test]
+    fn parse_sn_timestamp_should_accept_each_format() {
+        let expected = Utc.ymd(2018, 9, 20).and_hms(22, 52, 0);
+
+        for raw in &[
+            "2018-09-20 22:52:00",
+            "2018-09-20 22:52:00 UTC",
+            "2018-09-20T22:52:00",
+            "2018-09-20T22:52:00Z",
+            "2018-09-20T17:52:00-05:00",
+            "2018-09-20 17:52:00 -0500",
+        ] {
+            assert_eq!(parse_sn_timestamp(raw), Ok(expected), "{}", raw);
+        }
+    }
+
+    #[test]
+    fn parse_sn_timestamp_should_reject_incomplete_timestamps() {
+        for raw in &["2018-09-20", "2018-09-20 UTC", "22:52:00", ""] {
+            assert_eq!(
+                parse_sn_timestamp(raw),
+                Err(ParseError::InvalidTimestamp(raw.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn hazard_code_zero_should_be_a_parse_error() {
+        let parser = ReportParser::new();
+        let report = r#"Icon: 43.112000,-94.639999,000,3,0,"Reported By: Test Human\nTornado\nTime: 2018-09-20 22:52:00 UTC\nNotes: None""#;
+
+        match parser.parse(report) {
+            Err(e) => assert!(e.to_string().contains("feed codes start at 1")),
+            Ok(_) => panic!("code 0 should not parse"),
+        }
+        // The legacy discriminant 0 is still a tornado
+        assert!(serde_json::from_str::<Hazard>("0").unwrap() == Hazard::Tornado);
+    }
+
+    #[test]
+    fn unknown_hazard_code_should_be_a_wx_error() {
+        assert!(matches!(Hazard::get_by_code("0"), Err(Error::Wx(_))));
+    }
+
+    #[test]
+    fn build_event_text_should_only_append_real_notes() {
+        let text = build_event_text(&Hazard::Hail, "Test Human", "None");
+        assert_eq!(text, "Hail reported by Test Human");
+        assert_eq!(build_event_text(&Hazard::Hail, "Test Human", ""), text);
+
+        let text = build_event_text(&Hazard::WallCloud, "Test Human", "Rotating");
+        assert_eq!(text, "Wall Cloud reported by Test Human. Rotating");
+    }
+
+    #[test]
+    fn build_event_title_should_name_every_hazard() {
+        let hazards = [
+            Hazard::Tornado,
+            Hazard::Funnel,
+            Hazard::WallCloud,
+            Hazard::Hail,
+            Hazard::Wind,
+            Hazard::Flood,
+            Hazard::FlashFlood,
+            Hazard::Other,
+            Hazard::FreezingRain,
+            Hazard::Snow,
+        ];
+
+        for hazard in hazards.iter() {
+            let title = build_event_title(hazard);
+            assert_eq!(title, format!("Report: {}", hazard.to_string()));
+            assert!(!hazard.to_string().is_empty());
+        }
+        assert_eq!(
+            build_event_title(&Hazard::FlashFlood),
+            "Report: Flash Flood"
+        );
+    }
+
+    #[test]
+    fn clean_text_should_unescape_and_trim() {
+        assert_eq!(clean_text(r"Jane\nDoe"), "Jane Doe");
+        assert_eq!(clean_text(r#"Jane \"JD\" Doe"#), r#"Jane "JD" Doe"#);
+        assert_eq!(clean_text(r"Back\\slash\"), r"Back\slash");
+        assert_eq!(clean_text(r"Stray\q escape"), r"Stray\q escape");
+        assert_eq!(clean_text("  Test  Human \t"), "Test Human");
+    }
+
+    #[test]
+    fn parse_should_clean_reporter_names() {
+        let parser = ReportParser::new();
+        let report = r#"Icon: 43.112000,-94.639999,000,3,5,"Reported By: Test\nHuman  \nHigh Wind\nTime: 2018-09-20 22:52:00 UTC\n60 mph [Measured]\nNotes: None""#;
+        let event = parser.parse(report).unwrap().unwrap();
+
+        assert_eq!(event.report.unwrap().reporter, "Test Human");
+        assert_eq!(event.text.unwrap(), "Wind reported by Test Human");
+    }
+
+    #[test]
+    fn parse_should_normalize_escaped_notes() {
+        let parser = ReportParser::new();
+        let report = wind_report(r" Trees down\nPower   out ");
+        let event = parser.parse(&report).unwrap().unwrap();
+        assert!(event.text.unwrap().ends_with(r"Trees down\nPower   out "));
+
+        let options = ParseOptions {
+            normalize_notes: true,
+            ..ParseOptions::default()
+        };
+        let event = parser
+            .parse_with_options(&report, &options)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            event.text.unwrap(),
+            "Wind reported by Test Human. Trees down Power out"
+        );
+    }
+
+    #[test]
+    fn parse_should_treat_blank_notes_as_none() {
+        let mut parser = ReportParser::new();
+        parser.options.normalize_notes = true;
+        let report = wind_report("   ");
+        let event = parser.parse(&report).unwrap().unwrap();
+        assert_eq!(event.text.unwrap(), "Wind reported by Test Human");
+
+        let other = r#"Icon: 43.112000,-94.639999,000,3,8,"Reported By: Test Human\nOther - See Note\nTime: 2018-09-20 22:52:00 UTC\nNotes: \n ""#;
+        assert!(parser.parse(other).unwrap().is_none());
+    }
+
+    #[test]
+    fn parse_reporter_name_should_split_trailing_callsign() {
+        assert_eq!(
+            parse_reporter_name("John Doe (W0XYZ)"),
+            ("John Doe", Some("W0XYZ"))
+        );
+        assert_eq!(parse_reporter_name("Jane Smith"), ("Jane Smith", None));
+        assert_eq!(
+            parse_reporter_name("Bob (K4ABC) Jones"),
+            ("Bob (K4ABC) Jones", None)
+        );
+        assert_eq!(
+            parse_reporter_name("Jane Doe (KS Storm Chasers)"),
+            ("Jane Doe (KS Storm Chasers)", None)
+        );
+
+        for callsign in ["W1AW", "KG5ABC", "N0CALL"].iter() {
+            let reporter = format!("Test Human ({})", callsign);
+            assert_eq!(
+                parse_reporter_name(&reporter),
+                ("Test Human", Some(*callsign))
+            );
+        }
+    }
+
+    #[test]
+    fn parse_should_keep_callsign_in_reporter() {
+        let parser = ReportParser::new();
+        let report = r#"Icon: 43.112000,-94.639999,000,3,5,"Reported By: John Doe (W0XYZ)\nHigh Wind\nTime: 2018-09-20 22:52:00 UTC\n60 mph [Measured]\nNotes: None""#;
+        let reporter = parser
+            .parse(report)
+            .unwrap()
+            .unwrap()
+            .report
+            .unwrap()
+            .reporter;
+
+        assert_eq!(reporter, "John Doe (W0XYZ)");
+        assert_eq!(parse_reporter_name(&reporter), ("John Doe", Some("W0XYZ")));
+        assert_eq!(get_reporter_org(&reporter), "");
+    }
+
+    #[test]
+    fn render_event_template_should_substitute_placeholders() {
+        let template = "{hazard}|{reporter}|{notes}|{magnitude}|{units}|{reporter_org}";
+        let text = render_event_template(
+            &Hazard::Hail,
+            "Jane Doe (KS Storm Chasers)",
+            "Quarter sized",
+            template,
+            Some(1.75),
+            Some(&Units::Inches),
+        );
+
+        assert_eq!(
+            text,
+            "Hail|Jane Doe (KS Storm Chasers)|Quarter sized|1.75|in|KS Storm Chasers"
+        );
+    }
+
+    #[test]
+    fn parse_should_format_magnitude_text_consistently() {
+        let mut parser = ReportParser::new();
+        parser.text_template = Some("{magnitude_text} {hazard}".to_string());
+        let hail = r#"Icon: 47.617706,-111.215248,000,4,4,"Reported By: Test Human\nHail\nTime: 2018-09-20 22:49:29 UTC\nSize: 0.75" (Penny)\nNotes: None""#;
+        let wind = WIND_REPORT;
+
+        assert_eq!(
+            parser.parse(hail).unwrap().unwrap().text.unwrap(),
+            "0.75\" Hail"
+        );
+        assert_eq!(
+            parser.parse(wind).unwrap().unwrap().text.unwrap(),
+            "60 mph Wind"
+        );
+    }
+
+    #[test]
+    fn render_event_template_should_leave_unknown_placeholders() {
+        let template = "{hazard} {magnitude}{units} near {city}";
+        let text = render_event_template(&Hazard::Tornado, "Test Human", "", template, None, None);
+
+        assert_eq!(text, "Tornado  near {city}");
+    }
+
+    #[test]
+    fn find_state_should_match_abbreviation_after_comma() {
+        assert_eq!(find_state("3 mi N of Ames, IA"), Some("IA"));
+        assert_eq!(find_state("Near Moore, OK. Debris falling"), Some("OK"));
+        assert_eq!(find_state("Salina, KS to Abilene, KS"), Some("KS"));
+        assert_eq!(find_state("Jane Doe, W0XYZ"), None);
+        assert_eq!(find_state("Jane Doe, OKC Chasers"), None);
+        assert_eq!(find_state("Roads are OK. Heavy rain"), None);
+        assert_eq!(find_state("Quarter sized, Iowa"), None);
+        assert_eq!(find_state("None"), None);
+    }
+
+    #[test]
+    fn parse_should_fill_state_placeholder_when_present() {
+        let mut parser = ReportParser::new();
+        parser.title_template = Some("{hazard}|{state}".to_string());
+        let with_state = wind_report("Tree down 3 mi N of Ames, IA");
+        let without_state = wind_report("Tree down");
+
+        assert_eq!(parser.parse(&with_state).unwrap().unwrap().title, "Wind|IA");
+        assert_eq!(
+            parser.parse(&without_state).unwrap().unwrap().title,
+            "Wind|"
+        );
+    }
+
+    #[test]
+    fn parse_report_should_find_state_in_notes_or_reporter() {
+        let parser = ReportParser::new();
+        let options = ParseOptions::default();
+        let parse = |reporter: &str, notes: &str| {
+            let report = format!(
+                r#"Icon: 43.112000,-94.639999,000,3,5,"Reported By: {}\nHigh Wind\nTime: 2018-09-20 22:52:00 UTC\n60 mph [Measured]\nNotes: {}""#,
+                reporter, notes
+            );
+            let parsed = parser.parse_report_with_options(&report, &options);
+            parsed.unwrap().unwrap().state
+        };
+
+        assert_eq!(
+            parse("Test Human", "Tree down 3 mi N of Ames, IA"),
+            Some("IA")
+        );
+        assert_eq!(parse("Test Human, Norman, OK", "Tree down"), Some("OK"));
+        assert_eq!(parse("Test Human, Norman, OK", "Near Ames, IA"), Some("IA"));
+        assert_eq!(parse("Test Human", "Tree down"), None);
+    }
+
+    #[test]
+    fn parse_should_apply_templates() {
+        let mut parser = ReportParser::new();
+        parser.text_template = Some("{reporter}: {notes}".to_string());
+        parser.title_template = Some("SN {magnitude}{units} {hazard}".to_string());
+        let report = wind_report("Strong winds measured at 60mph with anemometer");
+        let event = parser.parse(&report).unwrap().unwrap();
+
+        assert_eq!(
+            event.text.unwrap(),
+            "Test Human: Strong winds measured at 60mph with anemometer"
+        );
+        assert_eq!(event.title, "SN 60mph Wind");
+    }
+
+    /// The next representable value further from zero.
+    fn one_ulp_out(value: f64) -> f64 {
+        f64::from_bits(value.to_bits() + 1)
+    }
+
+    fn invalid_reason(lat: f64, lon: f64) -> &'static str {
+        match validate_coordinates(lat, lon) {
+            Err(ParseError::InvalidCoordinates { reason, .. }) => reason,
+            other => panic!(
+                "{},{} should be invalid coordinates: {:?}",
+                lat,
+                lon,
+                other.err()
+            ),
+        }
+    }
+
+    #[test]
+    fn validate_coordinates_should_accept_boundaries() {
+        for &(lat, lon) in &[(90.0, 180.0), (-90.0, -180.0), (0.0, 0.0), (43.112, -94.64)] {
+            assert!(validate_coordinates(lat, lon).is_ok(), "{},{}", lat, lon);
+        }
+    }
+
+    #[test]
+    fn validate_coordinates_should_reject_one_ulp_outside_boundaries() {
+        assert_eq!(
+            invalid_reason(one_ulp_out(90.0), 0.0),
+            "latitude out of range"
+        );
+        assert_eq!(
+            invalid_reason(one_ulp_out(-90.0), 0.0),
+            "latitude out of range"
+        );
+        assert_eq!(
+            invalid_reason(0.0, one_ulp_out(180.0)),
+            "longitude out of range"
+        );
+        assert_eq!(
+            invalid_reason(0.0, one_ulp_out(-180.0)),
+            "longitude out of range"
+        );
+    }
+
+    #[test]
+    fn validate_coordinates_should_reject_non_finite_values() {
+        for &(lat, lon) in &[
+            (f64::NAN, 0.0),
+            (0.0, f64::NAN),
+            (f64::INFINITY, 0.0),
+            (0.0, f64::NEG_INFINITY),
+        ] {
+            assert_eq!(invalid_reason(lat, lon), "not a finite number");
+        }
+    }
+
+    #[test]
+    fn validate_coordinates_should_return_typed_error() {
+        let err = match validate_coordinates(91.0, -100.0) {
+            Err(err) => err,
+            Ok(_) => panic!("91,-100 should be invalid"),
+        };
+        assert_eq!(
+            err,
+            ParseError::InvalidCoordinates {
+                lat: 91.0,
+                lon: -100.0,
+                reason: "latitude out of range"
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "invalid coordinates 91,-100: latitude out of range"
+        );
+    }
+
+    fn hail_report(size: &str) -> String {
+        format!(
+            r#"Icon: 47.617706,-111.215248,000,4,4,"Reported By: Test Human\nHail\nTime: 2018-09-20 22:49:29 UTC\nSize: {}\nNotes: None""#,
+            size
+        )
+    }
+
+    #[test]
+    fn parse_should_match_parse_with_default_options() {
+        let parser = ReportParser::new();
+        let report = hail_report("1.75\" (Golf Ball)");
+        let parsed = parser.parse(&report).unwrap().unwrap();
+        let with_options = parser
+            .parse_with_options(&report, &ParseOptions::default())
+            .unwrap()
+            .unwrap();
+
+        assert!(parsed == with_options);
+        assert_eq!(parser.options, ParseOptions::default());
+    }
+
+    #[test]
+    fn parse_with_options_should_reject_implausible_magnitudes() {
+        let parser = ReportParser::new();
+        let options = ParseOptions {
+            reject_implausible_magnitudes: true,
+            ..ParseOptions::default()
+        };
+
+        assert!(parser.parse(&hail_report("50.00\"")).is_ok());
+        assert!(parser
+            .parse_with_options(&hail_report("50.00\""), &options)
+            .is_err());
+        assert!(parser
+            .parse_with_options(&hail_report("4.00\""), &options)
+            .is_ok());
+    }
+
+    #[test]
+    fn report_age_should_respect_window() {
+        let hour = 3_600 * 1_000_000;
+        let now = 1_537_484_040_000_000;
+        let options = ParseOptions {
+            max_age_hours: 6,
+            future_tolerance_hours: 1,
+            ..ParseOptions::default()
+        };
+
+        assert!(check_report_age(now - 5 * hour, now, &options).is_ok());
+        assert!(check_report_age(now - 7 * hour, now, &options).is_err());
+        assert!(check_report_age(now + hour / 2, now, &options).is_ok());
+        assert!(check_report_age(now + 2 * hour, now, &options).is_err());
+        assert!(check_report_age(0, now, &ParseOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn label_override_should_replace_code_hazard() {
+        let parser = ReportParser::new();
+        let report = r#"Icon: 43.112000,-94.639999,000,3,8,"Reported By: Test Human\nFreezing Drizzle\nTime: 2018-09-20 22:52:00 UTC\nNotes: Glaze on trees""#;
+        let mut options = ParseOptions::default();
+        options
+            .label_overrides
+            .insert("Freezing Drizzle".to_string(), Hazard::FreezingRain);
+
+        let event = parser.parse(report).unwrap().unwrap();
+        assert!(event.report.unwrap().hazard == HazardType::Other);
+
+        let event = parser
+            .parse_with_options(report, &options)
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.title, "Report: Freezing Rain");
+        assert!(event.report.unwrap().hazard == HazardType::FreezingRain);
+    }
+
+    #[test]
+    fn label_override_should_not_apply_to_other_labels() {
+        let parser = ReportParser::new();
+        let mut options = ParseOptions::default();
+        options
+            .label_overrides
+            .insert("Dense Fog".to_string(), Hazard::Other);
+
+        let event = parser
+            .parse_with_options(&hail_report("1.00\""), &options)
+            .unwrap()
+            .unwrap();
+        assert!(event.report.unwrap().hazard == HazardType::Hail);
+    }
+
+    #[test]
+    fn parse_with_options_should_reject_old_reports() {
+        let parser = ReportParser::new();
+        let options = ParseOptions {
+            max_age_hours: 24,
+            ..ParseOptions::default()
+        };
+
+        // The fixture is from 2018
+        assert!(parser
+            .parse_with_options(&hail_report("1.00\""), &options)
+            .is_err());
+    }
+
+    #[test]
+    fn report_should_parse_size_variants() {
+        let parser = ReportParser::new();
+        let cases = vec![
+            (r#"1.00" (Quarter)"#, 1.0),
+            ("1", 1.0),
+            ("1 inch", 1.0),
+            ("1.5 inches", 1.5),
+            ("2 cm", 0.79),
+            ("5cm", 1.97),
+        ];
+
+        for (size, expected) in cases {
+            let event = parser.parse(&hail_report(size)).unwrap().unwrap();
+            let report = event.report.unwrap();
+            assert!(report.magnitude == Some(expected), "size: {}", size);
+            assert!(report.units == Some(Units::Inches));
+        }
+    }
+
+    #[test]
+    fn report_should_title_metric_sizes_in_inches() {
+        let parser = ReportParser::new();
+        let event = parser.parse(&hail_report("2 cm")).unwrap().unwrap();
+        assert_eq!(event.title, "Report: 0.79\" Hail");
+    }
+
     #[test]
     fn report_should_parse_optional_size() {
         let parser = ReportParser::new();
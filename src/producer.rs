@@ -0,0 +1,2799 @@
+use crate::bloom::BloomFilter;
+use crate::domain::{round_coordinates, KNOWN_HAZARD_CODES};
+use crate::metrics::LatencyTracker;
+use crate::parser::{ParseOptions, ReportParser};
+use crate::random::Rng;
+use crate::sink::{EventSink, StoreError};
+use crate::state;
+use crate::{
+    get_comparison_filtered, get_feed_timestamp, get_report_identity, normalize_line,
+    split_partial_line, strip_bom, Config, ReportSet, SeenSet,
+};
+use chrono::prelude::*;
+use fnv::{FnvBuildHasher, FnvHashSet};
+use indexmap::IndexMap;
+use slog::Logger;
+use std::collections::{BTreeMap, VecDeque};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use wx::domain::{Event, EventType};
+use wx::error::Error;
+
+const BLOOM_FALSE_POSITIVE_PROBABILITY: f64 = 0.001;
+
+/// Source of raw SpotterNetwork feed bodies.
+pub trait Fetcher {
+    fn fetch(&self) -> Result<String, Error>;
+}
+
+/**
+ * Identifies a submission independently of its exact text, so a spotter submitting the same
+ * report twice within a few minutes (with e.g. slightly different coordinates or notes) can be
+ * caught even though the feed lines differ.
+ */
+#[derive(Debug, Eq, Hash, PartialEq)]
+pub struct SecondaryKey {
+    pub reporter: String,
+    pub hazard_code: u8,
+    pub ts_bucket: u64,
+}
+
+impl SecondaryKey {
+    pub fn new(report: &str, event: &Event, window_minutes: u64) -> Option<SecondaryKey> {
+        // The hazard code is the fifth field: `Icon: lat,lon,000,age,hazard_code,...`
+        let hazard_code = report.split(',').nth(4)?.parse().ok()?;
+        let reporter = event.report.as_ref()?.reporter.clone();
+
+        Some(SecondaryKey {
+            reporter,
+            hazard_code,
+            ts_bucket: get_ts_bucket(event.event_ts, window_minutes),
+        })
+    }
+}
+
+fn get_ts_bucket(ts: u64, window_minutes: u64) -> u64 {
+    ts / (window_minutes.max(1) * 60 * 1_000_000)
+}
+
+/// The lower median, reordering `values` in the process.
+fn median(values: &mut [i64]) -> Option<i64> {
+    if values.is_empty() {
+        return None;
+    }
+
+    values.sort_unstable();
+    Some(values[(values.len() - 1) / 2])
+}
+
+fn has_point(event: &Event) -> bool {
+    event
+        .location
+        .as_ref()
+        .and_then(|x| x.point.as_ref())
+        .is_some()
+}
+
+fn system_now() -> u64 {
+    Utc::now().timestamp() as u64 * 1_000_000
+}
+
+/**
+ * Fetches the feed once and checks the sink's health, logging each result, so a wrong URL or
+ * unreachable store shows up at startup instead of after the first poll interval. Both checks
+ * always run, and the first failure is returned. The fetched body is discarded.
+ */
+pub fn preflight<F: Fetcher + ?Sized, S: EventSink + ?Sized>(
+    fetcher: &F,
+    sink: &S,
+    logger: &Logger,
+) -> Result<(), Error> {
+    let fetched = fetcher.fetch();
+    match &fetched {
+        Ok(body) => info!(logger, "preflight fetch ok"; "body_bytes" => body.len()),
+        Err(e) => error!(logger, "preflight fetch failed"; "error" => e.to_string()),
+    }
+
+    let health = sink.check_health();
+    match &health {
+        Ok(()) => info!(logger, "preflight store ok";),
+        Err(e) => error!(logger, "preflight store failed"; "error" => e.to_string()),
+    }
+
+    fetched.and(health)
+}
+
+/// Applied to each event before it's stored, see `Producer::with_transform`.
+pub type Transform = Box<dyn FnMut(&mut Event)>;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PollResult {
+    pub fetch_failed: bool,
+    /// The fetch succeeded but returned nothing, so the seen set was left as is.
+    pub empty_body: bool,
+    /// The feed was much smaller than the seen set, so its reports were added to the seen set
+    /// rather than replacing it.
+    pub truncated_body: bool,
+    /// Seconds between the feed's own generation time and now, if the feed includes one.
+    pub feed_lag_secs: Option<i64>,
+    /// The body was cut off mid-record, so its last line was dropped.
+    pub partial_line: bool,
+    pub total_icon_lines: usize,
+    /// Icon lines repeated within the latest body, as opposed to carried over from earlier polls.
+    pub duplicate_lines: usize,
+    /// Icon lines repeated byte for byte, which the feed's age digit churn doesn't explain.
+    pub exact_duplicate_lines: usize,
+    pub new_events: usize,
+    pub parse_errors: usize,
+    /// Hazard codes seen for the first time this poll that the parser doesn't know about.
+    pub new_hazard_codes: usize,
+    /// New reports stored this poll.
+    pub stored: usize,
+    /// Events from the dead letter queue stored on retry this poll, not counted in `stored`.
+    pub retried: usize,
+    pub duplicates: usize,
+    pub probable_duplicates: usize,
+    pub out_of_bounds: usize,
+    /// Reports dropped for falling in one of the `exclusion_zones`.
+    pub excluded: usize,
+    /// Reports without a point, rejected unless `allow_missing_coordinates` is set.
+    pub missing_coordinates: usize,
+    /// Includes writes a queuing sink, like `ChannelSink`, reported failing since the last poll.
+    pub store_failures: usize,
+    /// Events waiting in the dead letter queue after this poll.
+    pub dead_letters: usize,
+    /// Set when `exit_on_store_unavailable` is enabled and the store has failed persistently.
+    pub store_unavailable: bool,
+    pub deferred: usize,
+    /// New reports skipped, not deferred, because `max_events_per_poll` were already stored.
+    pub overflow: usize,
+    /// New reports held back until they've been in `confirm_after_polls` consecutive polls.
+    pub unconfirmed: usize,
+    /// Median seconds between new reports' timestamps and now, negative when reports are older.
+    pub clock_offset_secs: Option<i64>,
+    /// The median offset exceeded `max_clock_offset_secs`, so the host clock may be wrong.
+    pub clock_drift: bool,
+    /// A diagnostic event summarizing a burst of parse failures was written.
+    pub parse_failure_event: bool,
+    /// A heartbeat was written since no report had been stored for `heartbeat_interval_secs`.
+    pub heartbeat: bool,
+}
+
+/// What `poll_once` would do with a report line, see `Producer::would_emit`.
+#[derive(Debug, PartialEq)]
+pub enum EmitDecision {
+    Emit,
+    /// Already in the seen set, so it isn't new.
+    AlreadySeen,
+    /// Parsed to nothing, e.g. an Other report without notes.
+    Skipped,
+    OutOfBounds,
+    /// Within one of the `exclusion_zones`.
+    Excluded,
+    /// Has no point and `allow_missing_coordinates` isn't set.
+    MissingCoordinates,
+    /// Matches a recent submission from the same reporter.
+    ProbableDuplicate,
+    ParseError(String),
+}
+
+/// An event a poll would store, see `Producer::dry_run`.
+pub struct DryRunEvent {
+    pub event: Event,
+    /// The report's raw capture groups, if `include_raw_captures` is set.
+    pub raw_captures: Option<BTreeMap<String, String>>,
+}
+
+pub struct Producer<F, S: EventSink> {
+    config: Config,
+    fetcher: F,
+    sink: S,
+    /// Where heartbeats and parse failure events go, kept apart from reports and their counters.
+    diagnostic_sink: Option<Box<dyn EventSink>>,
+    parser: ReportParser,
+    logger: Logger,
+    seen: SeenSet,
+    /// Set once `seen_warning_size` has been warned about, until the seen set shrinks below it.
+    seen_size_warned: bool,
+    /**
+     * Holds every hash in `seen` when `use_bloom_filter` is enabled, plus hashes that have since
+     * left the feed, which only cost a set lookup.
+     */
+    bloom: Option<BloomFilter>,
+    /// Size of the latest feed, used to pre-allocate the next one.
+    expected_feed_size: usize,
+    secondary_seen: FnvHashSet<SecondaryKey>,
+    deferred: VecDeque<String>,
+    /// Set until the first poll that fetches a non-empty feed, unless seen state was loaded.
+    cold_start: bool,
+    /**
+     * Reports first seen on a cold start's first poll, exempt from `max_report_age_hours` until
+     * they're processed, even if they're deferred or waiting on confirmation.
+     */
+    cold_start_reports: FnvHashSet<String>,
+    /// Source of jitter, seeded from entropy unless replaced with `with_seed`.
+    rng: Rng,
+    clock: Box<dyn Fn() -> u64>,
+    store_latency: LatencyTracker,
+    latency_window_start: Option<u64>,
+    consecutive_store_failures: usize,
+    /// Fetches failed in a row, for backing off the next poll.
+    consecutive_fetch_failures: u32,
+    transforms: Vec<Transform>,
+    /// Offsets between each new report's timestamp and now for the current poll, in seconds.
+    report_offsets: Vec<i64>,
+    /// Events that failed to store, retried at the start of the next poll.
+    dead_letters: VecDeque<Event>,
+    /// New reports waiting on confirmation, with the number of consecutive polls they've been in.
+    unconfirmed: IndexMap<String, usize, FnvBuildHasher>,
+    /// The first report that failed to parse this poll.
+    parse_failure_sample: Option<String>,
+    last_poll_result: Option<PollResult>,
+    /// The most recently stored events, oldest first, up to `recent_events_capacity`.
+    recent_events: VecDeque<Event>,
+    /// When a report or heartbeat was last written, or the first poll ran, for heartbeats.
+    last_activity_ts: Option<u64>,
+    /// Hazard codes already seen, starting with the known ones, so new codes are only warned once.
+    seen_hazard_codes: FnvHashSet<String>,
+}
+
+impl<F, S: EventSink> Producer<F, S> {
+    pub fn new(config: Config, fetcher: F, sink: S, logger: Logger) -> Producer<F, S> {
+        let mut parser = ReportParser::new();
+        parser.options.skip_other_none = !config.emit_empty_other;
+        parser.options.max_notes_bytes = config.max_notes_bytes.unwrap_or(usize::MAX);
+        parser.options.label_overrides = config.hazard_label_overrides.clone();
+        parser.options.normalize_notes = config.normalize_notes;
+        parser.options.estimate_bad_timestamps = config.estimate_bad_timestamps;
+        parser.options.max_age_hours = config.max_report_age_hours;
+        parser.text_template = config.text_template.clone();
+        parser.title_template = config.title_template.clone();
+        parser.logger = logger.clone();
+        if config.tag_source_url {
+            parser.source_url = Some(config.api_url.clone());
+        }
+
+        let loaded = match config.seen_state_path {
+            Some(ref path) if Path::new(path).exists() => {
+                match state::load_seen(Path::new(path), config.dedup_key) {
+                    Ok(seen) => {
+                        info!(logger, "loaded seen state"; "path" => path, "seen" => seen.len());
+                        Some(seen)
+                    }
+                    Err(e) => {
+                        warn!(logger, "unable to load seen state"; "path" => path,
+                        "error" => e.to_string());
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+        // A loaded seen set means this isn't a cold start, so its first poll is filtered as usual
+        let cold_start = loaded.is_none();
+        let seen = loaded.unwrap_or_default();
+
+        let mut producer = Producer {
+            config,
+            fetcher,
+            sink,
+            diagnostic_sink: None,
+            parser,
+            logger,
+            seen,
+            seen_size_warned: false,
+            bloom: None,
+            expected_feed_size: 64,
+            secondary_seen: FnvHashSet::default(),
+            deferred: VecDeque::new(),
+            cold_start,
+            cold_start_reports: FnvHashSet::default(),
+            clock: Box::new(system_now),
+            rng: Rng::from_entropy(),
+            store_latency: LatencyTracker::default(),
+            latency_window_start: None,
+            consecutive_store_failures: 0,
+            consecutive_fetch_failures: 0,
+            transforms: Vec::new(),
+            report_offsets: Vec::new(),
+            parse_failure_sample: None,
+            unconfirmed: IndexMap::default(),
+            dead_letters: VecDeque::new(),
+            last_poll_result: None,
+            recent_events: VecDeque::new(),
+            last_activity_ts: None,
+            seen_hazard_codes: KNOWN_HAZARD_CODES.iter().map(|x| x.to_string()).collect(),
+        };
+        producer.rebuild_bloom_filter();
+        producer
+    }
+
+    /**
+     * Registers a transform applied to each event after parsing and just before it's stored, e.g.
+     * to redact notes or add a tag. Transforms run in the order they were registered.
+     */
+    pub fn with_transform<T: FnMut(&mut Event) + 'static>(
+        mut self,
+        transform: T,
+    ) -> Producer<F, S> {
+        self.transforms.push(Box::new(transform));
+        self
+    }
+
+    /**
+     * Sends heartbeats and parse failure events to `sink` rather than the report sink, so they're
+     * never mistaken for reports or counted as stored. Without one, neither is emitted, which
+     * `Config::validate` rejects when either is enabled.
+     */
+    pub fn with_diagnostic_sink<D: EventSink + 'static>(mut self, sink: D) -> Producer<F, S> {
+        self.diagnostic_sink = Some(Box::new(sink));
+        self
+    }
+
+    /// Overrides the source of the current time (microseconds since epoch).
+    #[cfg(test)]
+    pub fn with_clock<C: Fn() -> u64 + 'static>(mut self, clock: C) -> Producer<F, S> {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    /// Seeds the producer's randomness, so jitter repeats exactly, e.g. in tests.
+    pub fn with_seed(mut self, seed: u64) -> Producer<F, S> {
+        self.rng = Rng::with_seed(seed);
+        self
+    }
+
+    /// A random delay up to `max` to add to a wait, so instances don't poll in lockstep.
+    pub fn jitter(&mut self, max: Duration) -> Duration {
+        self.rng.jitter(max)
+    }
+
+    /**
+     * How long to wait after a poll before the next, backing off after an empty body. After a
+     * failed fetch the wait is a random duration between the poll interval and double it for each
+     * failure in a row, up to `max_fetch_backoff_ms`, so a struggling feed isn't hit in lockstep.
+     */
+    pub fn next_wait(&mut self, result: &PollResult) -> Duration {
+        let poll_ms = self.config.poll_interval_ms;
+        let mut wait = Duration::from_millis(poll_ms);
+        if result.empty_body {
+            wait += Duration::from_millis(self.config.empty_body_backoff_ms);
+        }
+
+        if result.fetch_failed {
+            let factor = 1u64 << self.consecutive_fetch_failures.min(32);
+            let backoff_ms = poll_ms
+                .saturating_mul(factor)
+                .min(self.config.max_fetch_backoff_ms);
+            wait += self.jitter(Duration::from_millis(backoff_ms.saturating_sub(poll_ms)));
+        }
+
+        wait + self.jitter(Duration::from_millis(self.config.poll_jitter_ms))
+    }
+
+    pub fn fetcher(&self) -> &F {
+        &self.fetcher
+    }
+
+    /// The summary of the most recent poll, e.g. for health checks. `None` until the first poll.
+    pub fn last_poll_result(&self) -> Option<&PollResult> {
+        self.last_poll_result.as_ref()
+    }
+
+    /**
+     * Forgets every report seen so far, including secondary dedup keys and reports deferred or
+     * waiting on confirmation, so the next poll emits the whole feed again, e.g. after the store
+     * has been wiped. Dead letters are kept, since they're events already accepted for writing
+     * rather than dedup state. This doesn't count as a cold start.
+     */
+    pub fn reset_seen(&mut self) {
+        self.seen.clear();
+        self.secondary_seen.clear();
+        self.unconfirmed.clear();
+        self.deferred.clear();
+        self.cold_start_reports.clear();
+        self.rebuild_bloom_filter();
+        info!(self.logger, "seen set cleared";);
+    }
+
+    /// The most recently stored events, oldest first, for a quick look at what just came through.
+    pub fn recent_events(&self) -> impl Iterator<Item = &Event> {
+        self.recent_events.iter()
+    }
+
+    /**
+     * Explains what the next poll would do with `line` if it showed up in the feed, without side
+     * effects. Checks run in the same order as `prepare`, but on the event as parsed, since
+     * transforms may keep state and aren't run, so one that removes the point isn't reflected.
+     * Store-side outcomes like duplicate keys can't be predicted.
+     */
+    pub fn would_emit(&self, line: &str) -> EmitDecision {
+        let report = normalize_line(line);
+
+        if self.seen.contains(&self.config.dedup_key.hash(&report)) {
+            return EmitDecision::AlreadySeen;
+        }
+
+        match self.parse(&report) {
+            Ok(Some(event)) if !self.is_in_bounds(&event) => EmitDecision::OutOfBounds,
+            Ok(Some(event)) if self.is_excluded(&event) => EmitDecision::Excluded,
+            Ok(Some(event)) if self.has_secondary_key(&report, &event) => {
+                EmitDecision::ProbableDuplicate
+            }
+            Ok(Some(event)) if !self.config.allow_missing_coordinates && !has_point(&event) => {
+                EmitDecision::MissingCoordinates
+            }
+            Ok(Some(_)) => EmitDecision::Emit,
+            Ok(None) => EmitDecision::Skipped,
+            Err(e) => EmitDecision::ParseError(e.to_string()),
+        }
+    }
+}
+
+impl<F: Fetcher, S: EventSink> Producer<F, S> {
+    /**
+     * Fetches the feed and returns the events a poll would store from it, parsed, filtered and
+     * transformed per the config, without storing anything. Every report counts as new, and the
+     * seen set and dedup state are left alone, e.g. for `--geojson`.
+     */
+    pub fn dry_run(&mut self) -> Result<Vec<DryRunEvent>, Error> {
+        let body = self.fetcher.fetch()?;
+        let (body, _) = split_partial_line(strip_bom(&body));
+        let key = self.config.dedup_key;
+        let reports = get_comparison_filtered(body, &SeenSet::default(), None, 0, key).new;
+
+        let mut options = self.parser.options.clone();
+        options.include_raw_captures = self.config.include_raw_captures;
+        if self.cold_start && self.config.emit_stale_on_cold_start {
+            options.max_age_hours = 0;
+        }
+
+        let mut result = PollResult::default();
+        let mut events = Vec::new();
+
+        for report in &reports {
+            let now = (self.clock)();
+            let parsed = match self.parser.parse_report_at(report, &options, now) {
+                Ok(Some(parsed)) => parsed,
+                Ok(None) => continue,
+                Err(e) => {
+                    warn!(self.logger, "parse"; "reason" => e.to_string(), "report" => report);
+                    continue;
+                }
+            };
+
+            let event = self.parser.build_event(&parsed);
+            if let Some(event) = self.prepare(report, event, &mut result, false) {
+                events.push(DryRunEvent {
+                    event,
+                    raw_captures: parsed.raw_captures,
+                });
+            }
+        }
+
+        Ok(events)
+    }
+
+    /**
+     * Runs a single poll cycle. Reports deferred by `max_events_per_cycle` on a previous cycle
+     * are processed ahead of anything new in the latest feed body.
+     */
+    pub fn poll_once(&mut self) -> PollResult {
+        let started = Instant::now();
+        let fetched = self.fetcher.fetch();
+        self.poll_with(fetched, started.elapsed())
+    }
+}
+
+impl<F, S: EventSink> Producer<F, S> {
+    /**
+     * Runs a poll cycle on a body that's already been fetched, e.g. by an async client, logging
+     * its size and `fetch_elapsed`, how long the fetch took.
+     */
+    pub fn poll_with(
+        &mut self,
+        fetched: Result<String, Error>,
+        fetch_elapsed: Duration,
+    ) -> PollResult {
+        let body_bytes = fetched.as_ref().map_or(0, |body| body.len());
+        info!(self.logger, "fetch complete"; "body_bytes" => body_bytes,
+            "fetch_ms" => fetch_elapsed.as_millis() as u64);
+
+        let mut result = PollResult::default();
+        let mut pending: Vec<String> = self.deferred.drain(..).collect();
+        // Everything is new on the first poll, including reports that are hours old
+        let is_first_poll = self.seen.is_empty();
+        self.report_offsets.clear();
+        self.parse_failure_sample = None;
+        self.retry_dead_letters(&mut result);
+
+        if self.config.enable_secondary_dedup {
+            self.evict_secondary_keys();
+        }
+
+        if fetched.is_ok() {
+            self.consecutive_fetch_failures = 0;
+        }
+
+        // `trim` leaves a BOM alone, so strip it first or a BOM-only body would wipe `seen`
+        match fetched.as_deref().map(strip_bom) {
+            Ok(body) if body.trim().is_empty() => {
+                result.empty_body = true;
+                warn!(self.logger, "empty response body, keeping seen reports";
+                    "seen" => self.seen.len());
+            }
+            Ok(body) => {
+                if let Some(feed_ts) = get_feed_timestamp(body) {
+                    let lag_secs = ((self.clock)() as i64 - feed_ts as i64) / 1_000_000;
+                    result.feed_lag_secs = Some(lag_secs);
+                    info!(self.logger, "feed lag"; "lag_secs" => lag_secs);
+                }
+
+                let (body, partial_line) = split_partial_line(body);
+                if let Some(line) = partial_line {
+                    result.partial_line = true;
+                    warn!(self.logger, "body truncated mid-record, dropping partial line";
+                        "line" => line);
+                }
+
+                let capacity = self.expected_feed_size * 2;
+                let bloom = self.bloom.as_ref();
+                let key = self.config.dedup_key;
+                let comparison = get_comparison_filtered(body, &self.seen, bloom, capacity, key);
+                self.expected_feed_size = comparison.latest_set.len();
+                result.total_icon_lines = comparison.icon_lines;
+                result.duplicate_lines = comparison.icon_lines - comparison.latest_set.len();
+                result.new_events = comparison.new.len();
+
+                if self.cold_start {
+                    self.cold_start = false;
+                    if self.config.emit_stale_on_cold_start {
+                        self.cold_start_reports = comparison.new.iter().cloned().collect();
+                    }
+                }
+
+                if result.duplicate_lines > 0 {
+                    debug!(self.logger, "duplicate lines within body";
+                        "count" => result.duplicate_lines);
+                }
+
+                result.exact_duplicate_lines = comparison.exact_duplicates;
+                if result.exact_duplicate_lines > self.config.exact_duplicate_threshold {
+                    warn!(self.logger, "excessive exact duplicates within body, feed may be corrupt";
+                        "count" => result.exact_duplicate_lines,
+                        "threshold" => self.config.exact_duplicate_threshold);
+                }
+
+                let confirmed = self.confirm(comparison.new, &comparison.latest_set);
+
+                let seen_changed = if self.is_truncated(&comparison.latest_set) {
+                    result.truncated_body = true;
+                    warn!(self.logger, "feed much smaller than seen set, keeping seen reports";
+                        "feed" => comparison.latest_set.len(), "seen" => self.seen.len());
+                    let seen_before = self.seen.len();
+                    self.seen.extend(comparison.latest_hashes);
+                    self.seen.len() != seen_before
+                } else {
+                    let seen_changed = self.seen != comparison.latest_hashes;
+                    self.seen = comparison.latest_hashes;
+                    seen_changed
+                };
+
+                self.update_bloom_filter(&comparison.new_hashes);
+                pending.extend(confirmed);
+                if !self.cold_start_reports.is_empty() {
+                    let seen = &self.seen;
+                    self.cold_start_reports
+                        .retain(|x| seen.contains(&key.hash(x)));
+                }
+                self.check_seen_size();
+                // Most polls see the same feed as the last, so there's nothing new to write
+                if seen_changed {
+                    self.save_seen_state();
+                }
+            }
+            Err(e) => {
+                result.fetch_failed = true;
+                self.consecutive_fetch_failures += 1;
+                warn!(self.logger, "fetch_reports"; "error" => e.to_string(),
+                    "consecutive_failures" => self.consecutive_fetch_failures);
+            }
+        }
+
+        if let Some(max) = self.config.max_events_per_cycle {
+            if pending.len() > max {
+                self.deferred.extend(pending.drain(max..));
+                info!(self.logger, "deferring reports"; "count" => self.deferred.len());
+            }
+        }
+
+        let processed = pending.len();
+
+        for report in pending {
+            match self.config.max_events_per_poll {
+                Some(max) if result.stored >= max => result.overflow += 1,
+                _ => self.process(&report, &mut result),
+            }
+        }
+
+        if result.overflow > 0 {
+            error!(self.logger, "too many new reports, skipped the rest";
+                "max_events_per_poll" => self.config.max_events_per_poll,
+                "overflow" => result.overflow);
+        }
+
+        if self.config.emit_parse_failure_events {
+            self.emit_parse_failure_event(processed, &mut result);
+        }
+
+        if !is_first_poll {
+            self.check_clock_offset(&mut result);
+        }
+
+        if let Some(interval_secs) = self.config.heartbeat_interval_secs {
+            self.emit_heartbeat(interval_secs, &mut result);
+        }
+
+        if self.sink.writes_later() {
+            self.collect_completed_writes(&mut result);
+        }
+
+        result.deferred = self.deferred.len();
+        result.dead_letters = self.dead_letters.len();
+        result.unconfirmed = self.unconfirmed.len();
+        result.store_unavailable = self.config.exit_on_store_unavailable
+            && self.consecutive_store_failures >= self.config.store_failure_threshold;
+        info!(self.logger, "poll complete"; "new_events" => result.new_events,
+            "parse_errors" => result.parse_errors, "stored" => result.stored,
+            "retried" => result.retried, "duplicates" => result.duplicates,
+            "store_failures" => result.store_failures);
+        self.log_latency_summary();
+        self.last_poll_result = Some(result.clone());
+
+        result
+    }
+
+    fn process(&mut self, report: &str, result: &mut PollResult) {
+        self.check_hazard_code(report, result);
+        let parsed = self.parse(report);
+        self.cold_start_reports.remove(report);
+
+        if let Ok(Some(event)) = &parsed {
+            let offset_us = event.event_ts as i64 - (self.clock)() as i64;
+            self.report_offsets.push(offset_us / 1_000_000);
+        }
+
+        let event = match parsed {
+            Ok(Some(event)) => event,
+            Ok(None) => return,
+            Err(e) => {
+                result.parse_errors += 1;
+                warn!(self.logger, "parse"; "reason" => e.to_string());
+                if self.parse_failure_sample.is_none() {
+                    self.parse_failure_sample = Some(report.to_string());
+                }
+                return;
+            }
+        };
+
+        let event = match self.prepare(report, event, result, true) {
+            Some(event) => event,
+            None => return,
+        };
+
+        match self.put_event(&event) {
+            Ok(_) => {
+                result.stored += 1;
+                info!(self.logger, "stored event";)
+            }
+            Err(StoreError::DuplicateKey) => {
+                result.duplicates += 1;
+                debug!(self.logger, "event already stored";)
+            }
+            Err(StoreError::Uncertain(e)) => {
+                // Retrying could store it twice, so it's not dead-lettered
+                result.store_failures += 1;
+                let reason = format!("unable to confirm event was stored: {}", e);
+                error!(self.logger, "processing"; "reason" => reason);
+            }
+            Err(StoreError::Rejected(e)) => {
+                // It would only be rejected again, so it's not dead-lettered either
+                result.store_failures += 1;
+                let reason = format!("store rejected event: {}", e);
+                error!(self.logger, "processing"; "reason" => reason);
+            }
+            Err(e) => {
+                result.store_failures += 1;
+                let reason = format!("unable to store event: {}", e);
+                error!(self.logger, "processing"; "reason" => reason);
+                self.dead_letter(event);
+            }
+        }
+    }
+
+    /**
+     * Filters and transforms a parsed event the way a poll would before storing it, returning
+     * `None` if it's filtered out. Secondary dedup is skipped without `dedup`, so nothing is
+     * recorded.
+     */
+    fn prepare(
+        &mut self,
+        report: &str,
+        mut event: Event,
+        result: &mut PollResult,
+        dedup: bool,
+    ) -> Option<Event> {
+        if !self.is_in_bounds(&event) {
+            result.out_of_bounds += 1;
+            debug!(self.logger, "report out of bounds"; "report" => report);
+            return None;
+        }
+
+        if self.is_excluded(&event) {
+            result.excluded += 1;
+            debug!(self.logger, "report in exclusion zone"; "report" => report);
+            return None;
+        }
+
+        if dedup && self.is_probable_duplicate(report, &event) {
+            result.probable_duplicates += 1;
+            warn!(self.logger, "probable duplicate submission"; "report" => report);
+            return None;
+        }
+
+        if let Some(decimals) = self.config.output_coord_precision {
+            let point = event.location.as_mut().and_then(|x| x.point.as_mut());
+            if let Some(point) = point {
+                round_coordinates(point, decimals);
+            }
+        }
+
+        for transform in self.transforms.iter_mut() {
+            transform(&mut event);
+        }
+
+        // Checked last so it also covers anything a transform removed
+        if !self.config.allow_missing_coordinates && !has_point(&event) {
+            result.missing_coordinates += 1;
+            warn!(self.logger, "report without coordinates, skipping"; "report" => report);
+            return None;
+        }
+
+        Some(event)
+    }
+
+    /**
+     * Parses against the producer's clock, so report age follows `with_clock`. Reports from a cold
+     * start's first poll skip the age check when `emit_stale_on_cold_start` is set.
+     */
+    fn parse(&self, report: &str) -> Result<Option<Event>, Error> {
+        let now = (self.clock)();
+
+        if self.cold_start_reports.contains(report) {
+            let options = ParseOptions {
+                max_age_hours: 0,
+                ..self.parser.options.clone()
+            };
+            return self.parser.parse_at(report, &options, now);
+        }
+
+        self.parser.parse_at(report, &self.parser.options, now)
+    }
+
+    fn put_event(&mut self, event: &Event) -> Result<(), StoreError> {
+        let started = Instant::now();
+        let result = self.sink.put_event(event);
+        self.store_latency
+            .record(started.elapsed().as_micros() as u64);
+
+        // Queuing says nothing about the store, see `collect_completed_writes`
+        if !self.sink.writes_later() {
+            self.count_store_result(&result);
+        }
+
+        if result.is_ok() {
+            self.remember_recent(event);
+        }
+
+        result
+    }
+
+    fn count_store_result(&mut self, result: &Result<(), StoreError>) {
+        match result {
+            Err(StoreError::Store(_)) | Err(StoreError::Uncertain(_)) => {
+                self.consecutive_store_failures += 1
+            }
+            _ => self.consecutive_store_failures = 0,
+        }
+    }
+
+    /**
+     * Handles writes a queuing sink finished since the last poll the way `process` handles inline
+     * ones, so failures count toward `exit_on_store_unavailable` and are dead-lettered.
+     */
+    fn collect_completed_writes(&mut self, result: &mut PollResult) {
+        for completed in self.sink.take_completed() {
+            self.count_store_result(&completed.result);
+
+            match completed.result {
+                Ok(_) => {}
+                Err(StoreError::DuplicateKey) => debug!(self.logger, "event already stored";),
+                Err(StoreError::Uncertain(e)) => {
+                    result.store_failures += 1;
+                    let reason = format!("unable to confirm event was stored: {}", e);
+                    error!(self.logger, "processing"; "reason" => reason);
+                }
+                Err(StoreError::Rejected(e)) => {
+                    result.store_failures += 1;
+                    let reason = format!("store rejected event: {}", e);
+                    error!(self.logger, "processing"; "reason" => reason);
+                }
+                Err(e) => {
+                    result.store_failures += 1;
+                    let reason = format!("unable to store event: {}", e);
+                    error!(self.logger, "processing"; "reason" => reason);
+                    self.dead_letter(completed.event);
+                }
+            }
+        }
+    }
+
+    /// Keeps a copy of a stored event, evicting the oldest once `recent_events_capacity` is reached.
+    fn remember_recent(&mut self, event: &Event) {
+        if self.config.recent_events_capacity == 0 {
+            return;
+        }
+
+        if self.recent_events.len() >= self.config.recent_events_capacity {
+            self.recent_events.pop_front();
+        }
+
+        self.recent_events.push_back(event.clone());
+    }
+
+    /// Warns the first time a report shows up with a hazard code that isn't already known.
+    fn check_hazard_code(&mut self, report: &str, result: &mut PollResult) {
+        let code = match get_report_identity(report) {
+            Some(identity) => identity[2],
+            None => return,
+        };
+
+        if !self.seen_hazard_codes.contains(code) {
+            self.seen_hazard_codes.insert(code.to_string());
+            result.new_hazard_codes += 1;
+            warn!(self.logger, "new hazard code encountered"; "code" => code);
+        }
+    }
+
+    /// Warns the first time the seen set grows past `seen_warning_size`, rather than every poll.
+    fn check_seen_size(&mut self) {
+        let threshold = self.config.seen_warning_size;
+        let is_over = threshold > 0 && self.seen.len() > threshold;
+
+        if is_over && !self.seen_size_warned {
+            warn!(self.logger, "seen set unusually large, feed may be growing abnormally";
+                "seen" => self.seen.len(), "threshold" => threshold);
+        }
+
+        self.seen_size_warned = is_over;
+    }
+
+    /**
+     * Builds a new bloom filter from the seen set, since entries can't be removed from one. It's
+     * sized for twice the seen set so it only needs rebuilding after as many reports again.
+     */
+    fn rebuild_bloom_filter(&mut self) {
+        if !self.config.use_bloom_filter {
+            return;
+        }
+
+        let minimum = self.config.bloom_filter_expected_items;
+        let expected_items = minimum.max(self.seen.len() * 2);
+        let mut bloom = BloomFilter::new(expected_items, BLOOM_FALSE_POSITIVE_PROBABILITY);
+        for hash in &self.seen {
+            bloom.insert(*hash);
+        }
+        self.bloom = Some(bloom);
+    }
+
+    /// Adds newly seen hashes to the bloom filter, rebuilding it once it's over capacity.
+    fn update_bloom_filter(&mut self, new_hashes: &[u64]) {
+        let full = match self.bloom {
+            Some(ref mut bloom) => {
+                for hash in new_hashes {
+                    bloom.insert(*hash);
+                }
+                bloom.is_full()
+            }
+            None => false,
+        };
+
+        if full {
+            self.rebuild_bloom_filter();
+        }
+    }
+
+    /// Writes the seen set to `seen_state_path`, if configured.
+    fn save_seen_state(&self) {
+        if let Some(ref path) = self.config.seen_state_path {
+            let path = Path::new(path);
+            let compress = state::should_compress(path, self.config.compress_seen_state);
+
+            if let Err(e) = state::save_seen(path, &self.seen, self.config.dedup_key, compress) {
+                warn!(self.logger, "unable to save seen state"; "error" => e.to_string());
+            }
+        }
+    }
+
+    /// Queues an event that failed to store, evicting the oldest if the queue is full.
+    fn dead_letter(&mut self, event: Event) {
+        if self.config.dead_letter_capacity == 0 {
+            return;
+        }
+
+        if self.dead_letters.len() >= self.config.dead_letter_capacity {
+            self.dead_letters.pop_front();
+            warn!(self.logger, "dead letter queue full, dropped oldest event";);
+        }
+
+        self.dead_letters.push_back(event);
+    }
+
+    /**
+     * Retries queued events in order, stopping at the first failure since the store is still down.
+     * An event whose retry may have been stored, or was rejected, isn't queued again.
+     */
+    fn retry_dead_letters(&mut self, result: &mut PollResult) {
+        while let Some(event) = self.dead_letters.pop_front() {
+            match self.put_event(&event) {
+                Ok(_) => result.retried += 1,
+                Err(StoreError::DuplicateKey) => result.duplicates += 1,
+                Err(StoreError::Uncertain(e)) => {
+                    warn!(self.logger, "dead letter retry may have been stored, dropping it";
+                        "error" => e.to_string());
+                    break;
+                }
+                Err(StoreError::Rejected(e)) => {
+                    warn!(self.logger, "dead letter retry rejected, dropping it";
+                        "error" => e.to_string());
+                }
+                Err(e) => {
+                    debug!(self.logger, "dead letter retry failed"; "error" => e.to_string());
+                    self.dead_letters.push_front(event);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Logs store latency percentiles once per `latency_summary_interval_secs`.
+    fn log_latency_summary(&mut self) {
+        let now = (self.clock)();
+        let window_start = *self.latency_window_start.get_or_insert(now);
+        let interval = self.config.latency_summary_interval_secs * 1_000_000;
+
+        if now.saturating_sub(window_start) < interval {
+            return;
+        }
+
+        self.latency_window_start = Some(now);
+
+        if let Some(summary) = self.store_latency.take_summary() {
+            info!(self.logger, "store latency"; "count" => summary.count, "p50_us" => summary.p50_us,
+                "p95_us" => summary.p95_us, "max_us" => summary.max_us);
+        }
+    }
+
+    /**
+     * New reports should be only minutes old, so if most of them appear far in the past or future
+     * our clock is probably off, which would break anything filtering on report age.
+     */
+    fn check_clock_offset(&mut self, result: &mut PollResult) {
+        let offset_secs = match median(&mut self.report_offsets) {
+            Some(offset_secs) => offset_secs,
+            None => return,
+        };
+
+        result.clock_offset_secs = Some(offset_secs);
+
+        if offset_secs.unsigned_abs() > self.config.max_clock_offset_secs {
+            result.clock_drift = true;
+            warn!(self.logger, "possible clock drift"; "median_offset_secs" => offset_secs,
+                "reports" => self.report_offsets.len());
+        }
+    }
+
+    /**
+     * Writes a diagnostic event when at least `parse_failure_ratio` of this poll's reports failed
+     * to parse, which usually means the feed format changed. It goes to the diagnostic sink, so
+     * consumers of the report sink never see it.
+     */
+    fn emit_parse_failure_event(&mut self, processed: usize, result: &mut PollResult) {
+        let ratio = result.parse_errors as f64 / processed as f64;
+
+        if result.parse_errors == 0 || ratio < self.config.parse_failure_ratio {
+            return;
+        }
+
+        let sink = match self.diagnostic_sink {
+            Some(ref sink) => sink,
+            None => return,
+        };
+
+        let sample = self.parse_failure_sample.take().unwrap_or_default();
+        let event = Event {
+            event_ts: (self.clock)(),
+            event_type: EventType::SnReport,
+            expires_ts: None,
+            ext_uri: self.parser.source_url.clone(),
+            ingest_ts: 0, // set when storing
+            location: None,
+            md: None,
+            outlook: None,
+            report: None,
+            text: Some(format!("Sample: {}", sample)),
+            title: format!(
+                "Parse failures: {} of {} reports",
+                result.parse_errors, processed
+            ),
+            valid_ts: None,
+            warning: None,
+            watch: None,
+        };
+
+        match sink.put_event(&event) {
+            Ok(_) => result.parse_failure_event = true,
+            Err(e) => error!(self.logger, "unable to store parse failure event";
+                "error" => e.to_string()),
+        }
+    }
+
+    /**
+     * Writes a heartbeat event to the diagnostic sink once nothing has been stored for
+     * `interval_secs`, so consumers can tell a quiet feed from a dead producer. `wx` has no
+     * heartbeat event type, so it's an `SnReport` without a report, titled `Heartbeat`, which is
+     * why it's kept out of the report sink. Its text counts the unknown hazard codes seen since
+     * startup.
+     */
+    fn emit_heartbeat(&mut self, interval_secs: u64, result: &mut PollResult) {
+        let now = (self.clock)();
+        let last_activity = *self.last_activity_ts.get_or_insert(now);
+
+        if result.stored + result.retried > 0 {
+            self.last_activity_ts = Some(now);
+            return;
+        }
+
+        if now.saturating_sub(last_activity) < interval_secs * 1_000_000 {
+            return;
+        }
+
+        let sink = match self.diagnostic_sink {
+            Some(ref sink) => sink,
+            None => return,
+        };
+
+        let new_hazard_codes = self.seen_hazard_codes.len() - KNOWN_HAZARD_CODES.len();
+        let event = Event {
+            event_ts: now,
+            event_type: EventType::SnReport,
+            expires_ts: None,
+            ext_uri: self.parser.source_url.clone(),
+            ingest_ts: 0, // set when storing
+            location: None,
+            md: None,
+            outlook: None,
+            report: None,
+            text: Some(format!("New hazard codes seen: {}", new_hazard_codes)),
+            title: "Heartbeat".to_string(),
+            valid_ts: None,
+            warning: None,
+            watch: None,
+        };
+
+        match sink.put_event(&event) {
+            Ok(_) => {
+                result.heartbeat = true;
+                self.last_activity_ts = Some(now);
+            }
+            Err(e) => error!(self.logger, "unable to store heartbeat event";
+                "error" => e.to_string()),
+        }
+    }
+
+    /**
+     * Holds new reports back until they've appeared in `confirm_after_polls` consecutive polls,
+     * so a report that's briefly in the feed before being corrected or removed is never emitted.
+     * Returns the reports that are now confirmed, in the order they were first seen.
+     */
+    fn confirm(&mut self, new: Vec<String>, latest: &ReportSet) -> Vec<String> {
+        let threshold = self.config.confirm_after_polls;
+
+        if threshold <= 1 && self.unconfirmed.is_empty() {
+            return new;
+        }
+
+        let mut confirmed = Vec::new();
+        let logger = &self.logger;
+
+        self.unconfirmed.retain(|report, polls| {
+            if !latest.contains(report) {
+                debug!(logger, "dropping unconfirmed report"; "report" => report.as_str());
+                return false;
+            }
+
+            *polls += 1;
+            if *polls >= threshold {
+                confirmed.push(report.clone());
+                return false;
+            }
+
+            true
+        });
+
+        for report in new {
+            if threshold <= 1 {
+                confirmed.push(report);
+            } else {
+                self.unconfirmed.insert(report, 1);
+            }
+        }
+
+        confirmed
+    }
+
+    /// A feed that shrinks below `min_expected_fraction` of the seen set is probably truncated.
+    fn is_truncated(&self, latest_set: &ReportSet) -> bool {
+        let min_expected = self.seen.len() as f64 * self.config.min_expected_fraction;
+        (latest_set.len() as f64) < min_expected
+    }
+
+    fn is_in_bounds(&self, event: &Event) -> bool {
+        let point = event.location.as_ref().and_then(|x| x.point.as_ref());
+
+        match (&self.config.bounding_box, point) {
+            (Some(bounding_box), Some(point)) => bounding_box.contains(point),
+            _ => true,
+        }
+    }
+
+    fn is_excluded(&self, event: &Event) -> bool {
+        match event.location.as_ref().and_then(|x| x.point.as_ref()) {
+            Some(point) => self
+                .config
+                .exclusion_zones
+                .iter()
+                .any(|x| x.contains(point)),
+            None => false,
+        }
+    }
+
+    /// Records the report's secondary key, returning true if it had already been recorded.
+    fn is_probable_duplicate(&mut self, report: &str, event: &Event) -> bool {
+        if !self.config.enable_secondary_dedup {
+            return false;
+        }
+
+        match SecondaryKey::new(report, event, self.config.secondary_dedup_window_minutes) {
+            Some(key) => !self.secondary_seen.insert(key),
+            None => false,
+        }
+    }
+
+    /// Like `is_probable_duplicate`, but without recording the key.
+    fn has_secondary_key(&self, report: &str, event: &Event) -> bool {
+        if !self.config.enable_secondary_dedup {
+            return false;
+        }
+
+        match SecondaryKey::new(report, event, self.config.secondary_dedup_window_minutes) {
+            Some(key) => self.secondary_seen.contains(&key),
+            None => false,
+        }
+    }
+
+    /// Keys only need to live for the current and previous window to catch resubmissions.
+    fn evict_secondary_keys(&mut self) {
+        let window = self.config.secondary_dedup_window_minutes;
+        let current_bucket = get_ts_bucket((self.clock)(), window);
+        self.secondary_seen
+            .retain(|key| key.ts_bucket + 1 >= current_bucket);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{BoundingBox, ExclusionZone};
+    use crate::test_util::{
+        temp_path, test_logger, wind_report, wind_reports, CapturingDrain, FailingSink,
+        MockFetcher, MockSink, WIND_REPORT,
+    };
+    use crate::DedupKey;
+    use std::cell::{Cell, RefCell};
+    use std::fs::File;
+    use std::io::Read;
+    use std::rc::Rc;
+    use wx::domain::HazardType;
+    use wx::error::WxError;
+
+    struct TimeoutFetcher;
+
+    impl Fetcher for TimeoutFetcher {
+        fn fetch(&self) -> Result<String, Error> {
+            Err(Error::Wx(<WxError>::new("Request timed out")))
+        }
+    }
+
+    #[test]
+    fn failed_fetches_should_back_off_exponentially() {
+        let config = Config {
+            poll_interval_ms: 1_000,
+            max_fetch_backoff_ms: 8_000,
+            ..Config::default()
+        };
+        let mut producer =
+            Producer::new(config, TimeoutFetcher, MockSink::default(), test_logger()).with_seed(7);
+
+        let mut waits = Vec::new();
+        for _ in 0..20 {
+            let result = producer.poll_once();
+            assert!(result.fetch_failed);
+            waits.push(producer.next_wait(&result).as_millis() as u64);
+        }
+
+        assert!(waits[0] >= 1_000 && waits[0] <= 2_000);
+        assert!(waits.iter().all(|x| *x >= 1_000 && *x <= 8_000));
+        assert!(waits[3..].iter().any(|x| *x > 4_000));
+
+        let result = producer.poll_with(Ok(wind_reports(1)), Duration::from_millis(0));
+        assert_eq!(producer.next_wait(&result), Duration::from_millis(1_000));
+    }
+
+    #[test]
+    fn preflight_should_pass_with_reachable_feed_and_store() {
+        let fetcher = MockFetcher::new(vec![wind_reports(1)]);
+
+        assert!(preflight(&fetcher, &MockSink::default(), &test_logger()).is_ok());
+    }
+
+    #[test]
+    fn preflight_should_fail_and_log_each_failed_check() {
+        let drain = CapturingDrain::default();
+        let logger = Logger::root(drain.clone(), o!());
+        let sink = FailingSink {
+            reason: "connection refused",
+        };
+
+        let result = preflight(&TimeoutFetcher, &sink, &logger);
+        assert!(result.unwrap_err().to_string().contains("timed out"));
+        let messages = drain.messages.lock().unwrap();
+        assert!(messages
+            .iter()
+            .any(|x| x.starts_with("preflight fetch failed")));
+        assert!(messages
+            .iter()
+            .any(|x| x.starts_with("preflight store failed")));
+
+        let fetcher = MockFetcher::new(vec![wind_reports(1)]);
+        assert!(preflight(&fetcher, &sink, &test_logger()).is_err());
+    }
+
+    fn read_fixture(path: &str) -> String {
+        let mut body = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut body))
+            .expect("unable to read fixture");
+        body
+    }
+
+    #[test]
+    fn poll_once_should_count_new_events_and_dedupe_on_repeat() {
+        let body = read_fixture("data/reports");
+        let fetcher = MockFetcher::new(vec![body.clone(), body]);
+        let mut producer = Producer::new(
+            Config::default(),
+            fetcher,
+            MockSink::default(),
+            test_logger(),
+        );
+
+        let result = producer.poll_once();
+        assert_eq!(result.total_icon_lines, 23);
+        assert_eq!(result.new_events, 23);
+        assert_eq!(result.parse_errors, 0);
+        assert_eq!(result.store_failures, 0);
+
+        let result = producer.poll_once();
+        assert_eq!(result.total_icon_lines, 23);
+        assert_eq!(result.new_events, 0);
+        assert_eq!(result.stored, 0);
+    }
+
+    #[test]
+    fn bloom_filter_should_not_change_poll_results() {
+        let config = || Config {
+            use_bloom_filter: true,
+            ..Config::default()
+        };
+        let bodies = vec![
+            read_fixture("data/reports"),
+            read_fixture("data/reports-updates"),
+            read_fixture("data/reports-updates"),
+        ];
+        let mut with_bloom = Producer::new(
+            config(),
+            MockFetcher::new(bodies.clone()),
+            MockSink::default(),
+            test_logger(),
+        )
+        .with_clock(test_clock);
+        let mut without_bloom = Producer::new(
+            Config::default(),
+            MockFetcher::new(bodies),
+            MockSink::default(),
+            test_logger(),
+        )
+        .with_clock(test_clock);
+
+        for _ in 0..3 {
+            assert_eq!(with_bloom.poll_once(), without_bloom.poll_once());
+        }
+    }
+
+    #[test]
+    fn bloom_filter_should_grow_with_seen_set() {
+        let config = Config {
+            use_bloom_filter: true,
+            bloom_filter_expected_items: 4,
+            ..Config::default()
+        };
+        let fetcher = MockFetcher::new(vec![wind_reports(3), wind_reports(10), wind_reports(10)]);
+        let mut producer = Producer::new(config, fetcher, MockSink::default(), test_logger());
+
+        producer.poll_once();
+        assert_eq!(producer.bloom.as_ref().unwrap().len(), 3);
+
+        // Seven more overflow a filter sized for four, so it's rebuilt for twice the seen set
+        assert_eq!(producer.poll_once().new_events, 7);
+        let bloom = producer.bloom.as_ref().unwrap();
+        assert_eq!(bloom.len(), 10);
+        assert!(!bloom.is_full());
+
+        assert_eq!(producer.poll_once().new_events, 0);
+        assert_eq!(producer.bloom.as_ref().unwrap().len(), 10);
+    }
+
+    #[test]
+    fn output_coord_precision_should_round_stored_points_only() {
+        let config = Config {
+            output_coord_precision: Some(2),
+            ..Config::default()
+        };
+        let first = WIND_REPORT;
+        // Rounds to the same point, but it's a different report
+        let second = r#"Icon: 43.114000,-94.641999,000,3,5,"Reported By: Test Human\nHigh Wind\nTime: 2018-09-20 22:52:00 UTC\n60 mph [Measured]\nNotes: None""#;
+        let fetcher = MockFetcher::new(vec![format!("{}\n{}", first, second)]);
+        let sink = MockSink::default();
+        let mut producer = Producer::new(config, fetcher, sink.clone(), test_logger());
+
+        let result = producer.poll_once();
+        assert_eq!(result.new_events, 2);
+        assert_eq!(result.stored, 2);
+
+        for event in sink.events.borrow().iter() {
+            let point = event.location.as_ref().unwrap().point.as_ref().unwrap();
+            assert!((point.lat - 43.11).abs() < 1e-5);
+            assert!((point.lon - -94.64).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn reports_without_coordinates_should_be_rejected_by_default() {
+        // Stands in for a relaxed parser that can produce reports without a point
+        let strip_point = |event: &mut Event| event.location = None;
+        let fetcher = MockFetcher::new(vec![wind_reports(2)]);
+        let sink = MockSink::default();
+        let mut producer = Producer::new(Config::default(), fetcher, sink.clone(), test_logger())
+            .with_transform(strip_point);
+
+        let result = producer.poll_once();
+        assert_eq!(result.missing_coordinates, 2);
+        assert_eq!(result.stored, 0);
+        assert!(sink.events.borrow().is_empty());
+    }
+
+    #[test]
+    fn reports_without_coordinates_should_be_stored_when_allowed() {
+        let config = Config {
+            allow_missing_coordinates: true,
+            ..Config::default()
+        };
+        let strip_point = |event: &mut Event| event.location = None;
+        let fetcher = MockFetcher::new(vec![wind_reports(2)]);
+        let mut producer = Producer::new(config, fetcher, MockSink::default(), test_logger())
+            .with_transform(strip_point);
+
+        let result = producer.poll_once();
+        assert_eq!(result.missing_coordinates, 0);
+        assert_eq!(result.stored, 2);
+    }
+
+    #[test]
+    fn reset_seen_should_re_emit_whole_feed() {
+        let body = read_fixture("data/reports");
+        let config = Config {
+            enable_secondary_dedup: true,
+            ..Config::default()
+        };
+        let fetcher = MockFetcher::new(vec![body.clone(), body]);
+        let mut producer = Producer::new(config, fetcher, MockSink::default(), test_logger());
+
+        let first = producer.poll_once();
+        assert!(first.new_events > 0);
+        producer.reset_seen();
+
+        let result = producer.poll_once();
+        assert_eq!(result.new_events, result.total_icon_lines);
+        assert_eq!(result.new_events, first.new_events);
+        // Only resubmissions within the feed itself, not every report from the first poll
+        assert_eq!(result.probable_duplicates, first.probable_duplicates);
+    }
+
+    #[test]
+    fn reset_seen_should_drop_pending_reports() {
+        let config = Config {
+            max_events_per_cycle: Some(1),
+            confirm_after_polls: 2,
+            ..Config::default()
+        };
+        let fetcher = MockFetcher::new(vec![wind_reports(3), wind_reports(3), wind_reports(3)]);
+        let sink = MockSink::default();
+        let mut producer = Producer::new(config, fetcher, sink.clone(), test_logger());
+
+        assert_eq!(producer.poll_once().unconfirmed, 3);
+        assert_eq!(producer.poll_once().deferred, 2);
+        producer.reset_seen();
+
+        // Everything is unconfirmed again rather than also coming back from the deferred queue
+        let result = producer.poll_once();
+        assert_eq!(result.stored, 0);
+        assert_eq!(result.deferred, 0);
+        assert_eq!(result.unconfirmed, 3);
+        assert_eq!(sink.events.borrow().len(), 1);
+    }
+
+    #[test]
+    fn reset_seen_should_not_re_enable_cold_start() {
+        let config = Config {
+            max_report_age_hours: 6,
+            emit_stale_on_cold_start: true,
+            ..Config::default()
+        };
+        let fetcher = MockFetcher::new(vec![wind_reports(2), wind_reports(2)]);
+        let mut producer = Producer::new(config, fetcher, MockSink::default(), test_logger());
+
+        assert_eq!(producer.poll_once().stored, 2);
+        producer.reset_seen();
+
+        let result = producer.poll_once();
+        assert_eq!(result.new_events, 2);
+        assert_eq!(result.stored, 0);
+    }
+
+    #[test]
+    fn last_poll_result_should_track_latest_poll() {
+        let fetcher = MockFetcher::new(vec![
+            read_fixture("data/reports"),
+            read_fixture("data/reports-updates"),
+        ]);
+        let mut producer = Producer::new(
+            Config::default(),
+            fetcher,
+            MockSink::default(),
+            test_logger(),
+        );
+        assert!(producer.last_poll_result().is_none());
+
+        let result = producer.poll_once();
+        assert_eq!(producer.last_poll_result().unwrap().new_events, 23);
+        assert_eq!(producer.last_poll_result(), Some(&result));
+
+        let result = producer.poll_once();
+        assert_eq!(producer.last_poll_result().unwrap().new_events, 2);
+        assert_eq!(producer.last_poll_result(), Some(&result));
+    }
+
+    #[test]
+    fn poll_once_should_truncate_notes_to_configured_length() {
+        let body = wind_report(&"x".repeat(4_097));
+        let config = Config {
+            max_notes_bytes: Some(1024),
+            ..Config::default()
+        };
+        let sink = MockSink::default();
+        let mut producer = Producer::new(
+            config,
+            MockFetcher::new(vec![body]),
+            sink.clone(),
+            test_logger(),
+        );
+
+        assert_eq!(producer.poll_once().stored, 1);
+        let text = sink.events.borrow()[0].text.clone().unwrap();
+        let expected = format!(
+            "Wind reported by Test Human. {}… [truncated]",
+            "x".repeat(1024)
+        );
+        assert_eq!(text, expected);
+    }
+
+    #[test]
+    fn poll_once_should_not_wipe_seen_on_empty_body() {
+        let body = wind_reports(3);
+        let fetcher = MockFetcher::new(vec![body.clone(), String::new(), body]);
+        let mut producer = Producer::new(
+            Config::default(),
+            fetcher,
+            MockSink::default(),
+            test_logger(),
+        );
+
+        assert_eq!(producer.poll_once().stored, 3);
+
+        let result = producer.poll_once();
+        assert!(result.empty_body);
+        assert!(!result.fetch_failed);
+        assert_eq!(producer.seen.len(), 3);
+
+        let result = producer.poll_once();
+        assert!(!result.empty_body);
+        assert_eq!(result.new_events, 0);
+    }
+
+    #[test]
+    fn poll_once_should_keep_seen_on_truncated_body() {
+        let full = wind_reports(20);
+        let truncated = wind_reports(2);
+        let fetcher = MockFetcher::new(vec![full.clone(), truncated, full]);
+        let config = Config {
+            min_expected_fraction: 0.25,
+            ..Config::default()
+        };
+        let mut producer = Producer::new(config, fetcher, MockSink::default(), test_logger());
+
+        assert_eq!(producer.poll_once().stored, 20);
+
+        let result = producer.poll_once();
+        assert!(result.truncated_body);
+        assert_eq!(result.new_events, 0);
+        assert_eq!(producer.seen.len(), 20);
+
+        let result = producer.poll_once();
+        assert!(!result.truncated_body);
+        assert_eq!(result.new_events, 0);
+    }
+
+    #[test]
+    fn poll_once_should_replace_seen_when_truncation_check_disabled() {
+        let fetcher = MockFetcher::new(vec![wind_reports(20), wind_reports(2)]);
+        let mut producer = Producer::new(
+            Config::default(),
+            fetcher,
+            MockSink::default(),
+            test_logger(),
+        );
+        producer.poll_once();
+
+        assert!(!producer.poll_once().truncated_body);
+        assert_eq!(producer.seen.len(), 2);
+    }
+
+    #[test]
+    fn poll_once_should_tag_events_with_source_url_when_enabled() {
+        let config = || Config {
+            api_url: "http://mirror.example.com/reports.txt".to_string(),
+            ..Config::default()
+        };
+        let fetcher = MockFetcher::new(vec![wind_reports(2)]);
+        let sink = MockSink::default();
+        let mut producer = Producer::new(config(), fetcher, sink.clone(), test_logger());
+        producer.poll_once();
+        assert!(sink.events.borrow().iter().all(|x| x.ext_uri.is_none()));
+
+        let config = Config {
+            tag_source_url: true,
+            ..config()
+        };
+        let fetcher = MockFetcher::new(vec![wind_reports(2)]);
+        let sink = MockSink::default();
+        let mut producer = Producer::new(config, fetcher, sink.clone(), test_logger());
+        producer.poll_once();
+
+        let events = sink.events.borrow();
+        assert_eq!(events.len(), 2);
+        for event in events.iter() {
+            assert_eq!(
+                event.ext_uri.as_ref().unwrap(),
+                "http://mirror.example.com/reports.txt"
+            );
+        }
+    }
+
+    #[test]
+    fn poll_once_should_write_parse_failure_event_to_diagnostic_sink() {
+        let config = Config {
+            emit_parse_failure_events: true,
+            ..Config::default()
+        };
+        let body = read_fixture("data/reports-missing-fields");
+        let sink = MockSink::default();
+        let diagnostics = MockSink::default();
+        let mut producer = Producer::new(
+            config,
+            MockFetcher::new(vec![body]),
+            sink.clone(),
+            test_logger(),
+        )
+        .with_diagnostic_sink(diagnostics.clone());
+
+        let result = producer.poll_once();
+        assert!(result.parse_failure_event);
+        assert_eq!(result.stored, 0);
+        assert!(sink.events.borrow().is_empty());
+        assert_eq!(producer.recent_events().count(), 0);
+
+        let events = diagnostics.events.borrow();
+        assert_eq!(events.len(), 1);
+        assert!(events[0].report.is_none());
+        assert_eq!(
+            events[0].title,
+            format!(
+                "Parse failures: {} of {} reports",
+                result.parse_errors, result.total_icon_lines
+            )
+        );
+        assert!(events[0]
+            .text
+            .as_ref()
+            .unwrap()
+            .starts_with("Sample: Icon:"));
+    }
+
+    #[test]
+    fn poll_once_should_not_store_parse_failure_event_below_ratio() {
+        let config = Config {
+            emit_parse_failure_events: true,
+            ..Config::default()
+        };
+        let body = format!("{}\nIcon: garbage\n", wind_reports(3));
+        let sink = MockSink::default();
+        let mut producer = Producer::new(
+            config,
+            MockFetcher::new(vec![body]),
+            sink.clone(),
+            test_logger(),
+        );
+
+        let result = producer.poll_once();
+        assert_eq!(result.parse_errors, 1);
+        assert!(!result.parse_failure_event);
+        assert_eq!(sink.events.borrow().len(), 3);
+    }
+
+    #[test]
+    fn poll_once_should_drop_record_truncated_mid_line() {
+        let reports = wind_reports(3);
+        let truncated = &reports[..reports.len() - 20];
+        let fetcher = MockFetcher::new(vec![truncated.to_string(), reports.clone()]);
+        let sink = MockSink::default();
+        let mut producer = Producer::new(Config::default(), fetcher, sink.clone(), test_logger());
+
+        let result = producer.poll_once();
+        assert!(result.partial_line);
+        assert_eq!(result.total_icon_lines, 2);
+        assert_eq!(result.parse_errors, 0);
+        assert_eq!(result.stored, 2);
+
+        // Once the full record shows up it's new, not already seen
+        let result = producer.poll_once();
+        assert!(!result.partial_line);
+        assert_eq!(result.new_events, 1);
+        assert_eq!(sink.events.borrow().len(), 3);
+    }
+
+    #[test]
+    fn poll_once_should_count_duplicate_lines_within_body() {
+        let line = wind_reports(1);
+        let body = format!("{}\n{}\n{}", line, line, wind_reports(2));
+        let mut producer = Producer::new(
+            Config::default(),
+            MockFetcher::new(vec![body]),
+            MockSink::default(),
+            test_logger(),
+        );
+
+        let result = producer.poll_once();
+        assert_eq!(result.total_icon_lines, 4);
+        assert_eq!(result.duplicate_lines, 2);
+        assert_eq!(result.stored, 2);
+    }
+
+    #[test]
+    fn large_seen_set_should_warn_once() {
+        let drain = CapturingDrain::default();
+        let config = Config {
+            seen_warning_size: 3,
+            ..Config::default()
+        };
+        let bodies = vec![
+            wind_reports(2),
+            wind_reports(5),
+            wind_reports(6),
+            wind_reports(6),
+        ];
+        let mut producer = Producer::new(
+            config,
+            MockFetcher::new(bodies),
+            MockSink::default(),
+            Logger::root(drain.clone(), o!()),
+        );
+
+        for _ in 0..4 {
+            producer.poll_once();
+        }
+
+        let messages = drain.messages.lock().unwrap();
+        let warnings = messages
+            .iter()
+            .filter(|x| x.starts_with("seen set unusually large"))
+            .count();
+        assert_eq!(warnings, 1);
+    }
+
+    #[test]
+    fn poll_once_should_warn_on_excessive_exact_duplicates() {
+        let drain = CapturingDrain::default();
+        let line = wind_reports(1);
+        let body = vec![line; 8].join("\n");
+        let mut producer = Producer::new(
+            Config::default(),
+            MockFetcher::new(vec![body]),
+            MockSink::default(),
+            Logger::root(drain.clone(), o!()),
+        );
+
+        let result = producer.poll_once();
+        assert_eq!(result.exact_duplicate_lines, 7);
+        let messages = drain.messages.lock().unwrap();
+        assert!(messages
+            .iter()
+            .any(|x| x.starts_with("excessive exact duplicates")));
+    }
+
+    #[test]
+    fn age_digit_churn_should_not_count_as_exact_duplicates() {
+        let drain = CapturingDrain::default();
+        let line = wind_reports(1);
+        let body = (3..6)
+            .map(|age| line.replacen(",000,3,", &format!(",000,{},", age), 1))
+            .collect::<Vec<String>>()
+            .join("\n");
+        let mut producer = Producer::new(
+            Config::default(),
+            MockFetcher::new(vec![body]),
+            MockSink::default(),
+            Logger::root(drain.clone(), o!()),
+        );
+
+        let result = producer.poll_once();
+        assert_eq!(result.exact_duplicate_lines, 0);
+        assert_eq!(result.duplicate_lines, 2);
+        let messages = drain.messages.lock().unwrap();
+        assert!(!messages
+            .iter()
+            .any(|x| x.starts_with("excessive exact duplicates")));
+    }
+
+    #[test]
+    fn new_hazard_code_should_only_warn_once() {
+        let drain = CapturingDrain::default();
+        let unknown = |minute: usize| {
+            wind_reports(minute + 1)
+                .lines()
+                .last()
+                .unwrap()
+                .replace(",000,3,5,", ",000,3,11,")
+        };
+        let bodies = vec![
+            format!("{}\n{}\n{}", unknown(0), unknown(1), wind_reports(2)),
+            format!("{}\n{}", unknown(2), wind_reports(3)),
+        ];
+        let mut producer = Producer::new(
+            Config::default(),
+            MockFetcher::new(bodies),
+            MockSink::default(),
+            Logger::root(drain.clone(), o!()),
+        );
+
+        let result = producer.poll_once();
+        assert_eq!(result.new_hazard_codes, 1);
+        assert_eq!(result.parse_errors, 2);
+
+        let result = producer.poll_once();
+        assert_eq!(result.new_hazard_codes, 0);
+
+        let messages = drain.messages.lock().unwrap();
+        let warnings = messages
+            .iter()
+            .filter(|x| *x == "new hazard code encountered")
+            .count();
+        assert_eq!(warnings, 1);
+    }
+
+    fn confirm_config() -> Config {
+        Config {
+            confirm_after_polls: 2,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn poll_once_should_emit_report_confirmed_by_second_poll() {
+        let sink = MockSink::default();
+        let fetcher = MockFetcher::new(vec![wind_reports(2), wind_reports(2)]);
+        let mut producer = Producer::new(confirm_config(), fetcher, sink.clone(), test_logger());
+
+        let result = producer.poll_once();
+        assert_eq!(result.new_events, 2);
+        assert_eq!(result.unconfirmed, 2);
+        assert_eq!(result.stored, 0);
+
+        let result = producer.poll_once();
+        assert_eq!(result.unconfirmed, 0);
+        assert_eq!(result.stored, 2);
+        assert_eq!(sink.events.borrow().len(), 2);
+    }
+
+    #[test]
+    fn poll_once_should_not_emit_one_poll_blip() {
+        let sink = MockSink::default();
+        let blip = r#"Icon: 43.112000,-94.639999,000,3,1,"Reported By: Test Human\nTornado\nTime: 2018-09-20 22:50:00 UTC\nNotes: Oops, wrong hazard""#;
+        let bodies = vec![
+            wind_reports(1),
+            format!("{}\n{}", wind_reports(1), blip),
+            wind_reports(1),
+            wind_reports(1),
+        ];
+        let fetcher = MockFetcher::new(bodies);
+        let mut producer = Producer::new(confirm_config(), fetcher, sink.clone(), test_logger());
+
+        let stored: usize = (0..4).map(|_| producer.poll_once().stored).sum();
+        assert_eq!(stored, 1);
+        assert!(sink.events.borrow()[0].report.as_ref().unwrap().hazard == HazardType::Wind);
+        assert_eq!(producer.unconfirmed.len(), 0);
+    }
+
+    #[test]
+    fn poll_once_should_count_parse_errors() {
+        let body = read_fixture("data/reports-missing-fields");
+        let fetcher = MockFetcher::new(vec![body]);
+        let mut producer = Producer::new(
+            Config::default(),
+            fetcher,
+            MockSink::default(),
+            test_logger(),
+        );
+
+        let result = producer.poll_once();
+        assert_eq!(result.new_events, result.total_icon_lines);
+        assert_eq!(result.parse_errors, result.total_icon_lines);
+        assert_eq!(result.stored, 0);
+    }
+
+    #[test]
+    fn poll_once_should_compute_feed_lag_from_header() {
+        let body = "Title: Spotter Network - Reports Only\nGenerated: 2018-09-20 22:50:00 UTC\n";
+        let fetcher = MockFetcher::new(vec![body.to_string(), wind_reports(1)]);
+        let mut producer = Producer::new(
+            Config::default(),
+            fetcher,
+            MockSink::default(),
+            test_logger(),
+        )
+        .with_clock(test_clock);
+
+        assert_eq!(producer.poll_once().feed_lag_secs, Some(240));
+        assert_eq!(producer.poll_once().feed_lag_secs, None);
+    }
+
+    #[test]
+    fn transforms_should_apply_to_stored_events_in_order() {
+        let sink = MockSink::default();
+        let fetcher = MockFetcher::new(vec![wind_reports(2)]);
+        let mut producer = Producer::new(Config::default(), fetcher, sink.clone(), test_logger())
+            .with_transform(|event| event.title = event.title.replace("Report", "SN Report"))
+            .with_transform(|event| event.title.push_str(" [tagged]"));
+
+        producer.poll_once();
+
+        let events = sink.events.borrow();
+        assert_eq!(events.len(), 2);
+        assert!(events
+            .iter()
+            .all(|x| x.title == "SN Report: 60 mph Wind [tagged]"));
+    }
+
+    #[test]
+    fn poll_once_should_flag_fetch_failures() {
+        let mut producer = Producer::new(
+            Config::default(),
+            TimeoutFetcher,
+            MockSink::default(),
+            test_logger(),
+        );
+
+        let result = producer.poll_once();
+
+        assert!(result.fetch_failed);
+        assert_eq!(result.stored, 0);
+    }
+
+    #[test]
+    fn poll_once_should_collapse_age_variants_within_one_body() {
+        let body = r#"Icon: 47.617706,-111.215248,000,3,4,"Reported By: Test User\nHail\nTime: 2018-09-20 22:39:00 UTC\nSize: 0.75" (Penny)\nNotes: None"
+Icon: 47.617706,-111.215248,000,5,4,"Reported By: Test User\nHail\nTime: 2018-09-20 22:39:00 UTC\nSize: 0.75" (Penny)\nNotes: None""#;
+        let sink = MockSink::default();
+        let fetcher = MockFetcher::new(vec![body.to_string()]);
+        let mut producer = Producer::new(Config::default(), fetcher, sink.clone(), test_logger());
+
+        let result = producer.poll_once();
+
+        assert_eq!(result.stored, 1);
+        assert_eq!(producer.seen.len(), 1);
+        assert_eq!(sink.events.borrow().len(), 1);
+    }
+
+    #[test]
+    fn poll_once_should_store_all_new_reports_by_default() {
+        let sink = MockSink::default();
+        let fetcher = MockFetcher::new(vec![wind_reports(10)]);
+        let mut producer = Producer::new(Config::default(), fetcher, sink.clone(), test_logger());
+
+        let result = producer.poll_once();
+
+        assert_eq!(result.stored, 10);
+        assert_eq!(result.deferred, 0);
+        assert_eq!(sink.events.borrow().len(), 10);
+    }
+
+    #[test]
+    fn max_events_per_cycle_should_defer_remaining_reports() {
+        let config = Config {
+            max_events_per_cycle: Some(5),
+            ..Config::default()
+        };
+        let sink = MockSink::default();
+        let body = wind_reports(10);
+        let fetcher = MockFetcher::new(vec![body.clone(), body]);
+        let mut producer = Producer::new(config, fetcher, sink.clone(), test_logger());
+
+        let result = producer.poll_once();
+        assert_eq!(result.stored, 5);
+        assert_eq!(result.deferred, 5);
+        assert_eq!(sink.events.borrow().len(), 5);
+
+        let result = producer.poll_once();
+        assert_eq!(result.stored, 5);
+        assert_eq!(result.deferred, 0);
+        assert_eq!(sink.events.borrow().len(), 10);
+    }
+
+    #[test]
+    fn duplicate_key_errors_should_be_counted_separately() {
+        let fetcher = MockFetcher::new(vec![wind_reports(3)]);
+        let sink = FailingSink {
+            reason: "Duplicate key: event already exists",
+        };
+        let mut producer = Producer::new(Config::default(), fetcher, sink, test_logger());
+
+        let result = producer.poll_once();
+
+        assert_eq!(result.stored, 0);
+        assert_eq!(result.duplicates, 3);
+        assert_eq!(result.store_failures, 0);
+    }
+
+    #[test]
+    fn other_store_errors_should_count_as_failures() {
+        let fetcher = MockFetcher::new(vec![wind_reports(3)]);
+        let sink = FailingSink {
+            reason: "connection refused",
+        };
+        let mut producer = Producer::new(Config::default(), fetcher, sink, test_logger());
+
+        let result = producer.poll_once();
+
+        assert_eq!(result.duplicates, 0);
+        assert_eq!(result.store_failures, 3);
+    }
+
+    /// Fails every write until `failing` is cleared.
+    #[derive(Clone, Default)]
+    struct ToggleSink {
+        failing: Rc<Cell<bool>>,
+        events: Rc<RefCell<Vec<Event>>>,
+    }
+
+    impl EventSink for ToggleSink {
+        fn put_event(&self, event: &Event) -> Result<(), StoreError> {
+            if self.failing.get() {
+                return Err(StoreError::from(Error::Wx(<WxError>::new(
+                    "connection refused",
+                ))));
+            }
+            self.events.borrow_mut().push(event.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn seen_state_should_survive_restart() {
+        let path = temp_path("producer_seen.gz");
+        let config = || Config {
+            seen_state_path: Some(path.to_str().unwrap().to_string()),
+            ..Config::default()
+        };
+        let fetcher = MockFetcher::new(vec![wind_reports(3)]);
+        let mut producer = Producer::new(config(), fetcher, MockSink::default(), test_logger());
+        assert_eq!(producer.poll_once().new_events, 3);
+
+        let fetcher = MockFetcher::new(vec![wind_reports(3)]);
+        let mut producer = Producer::new(config(), fetcher, MockSink::default(), test_logger());
+        assert_eq!(producer.poll_once().new_events, 0);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn seen_state_should_only_be_saved_when_seen_changes() {
+        let path = temp_path("producer_seen_unchanged.txt");
+        let config = Config {
+            seen_state_path: Some(path.to_str().unwrap().to_string()),
+            ..Config::default()
+        };
+        let bodies = vec![wind_reports(3), wind_reports(3), wind_reports(4)];
+        let fetcher = MockFetcher::new(bodies);
+        let mut producer = Producer::new(config, fetcher, MockSink::default(), test_logger());
+
+        producer.poll_once();
+        std::fs::remove_file(&path).unwrap();
+        producer.poll_once();
+        assert!(!path.exists());
+
+        producer.poll_once();
+        let loaded = state::load_seen(&path, DedupKey::Identity).unwrap();
+        assert_eq!(loaded, producer.seen);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn max_events_per_poll_should_store_up_to_cap_and_skip_the_rest() {
+        let drain = CapturingDrain::default();
+        let config = Config {
+            max_events_per_poll: Some(3),
+            ..Config::default()
+        };
+        let fetcher = MockFetcher::new(vec![wind_reports(5), wind_reports(5)]);
+        let sink = MockSink::default();
+        let logger = Logger::root(drain.clone(), o!());
+        let mut producer = Producer::new(config, fetcher, sink.clone(), logger);
+
+        let result = producer.poll_once();
+        assert_eq!(result.stored, 3);
+        assert_eq!(result.overflow, 2);
+        assert!(drain
+            .messages
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|x| x.starts_with("too many new reports")));
+
+        // Skipped reports are already seen, so they aren't picked up later
+        let result = producer.poll_once();
+        assert_eq!(result.stored, 0);
+        assert_eq!(sink.events.borrow().len(), 3);
+    }
+
+    #[test]
+    fn max_events_per_poll_should_not_count_dead_letter_retries() {
+        let config = Config {
+            max_events_per_poll: Some(3),
+            ..Config::default()
+        };
+        let fetcher = MockFetcher::new(vec![wind_reports(3), wind_reports(6)]);
+        let sink = ToggleSink::default();
+        sink.failing.set(true);
+        let mut producer = Producer::new(config, fetcher, sink.clone(), test_logger());
+        assert_eq!(producer.poll_once().dead_letters, 3);
+
+        sink.failing.set(false);
+        let result = producer.poll_once();
+        assert_eq!(result.retried, 3);
+        assert_eq!(result.stored, 3);
+        assert_eq!(result.overflow, 0);
+        assert_eq!(sink.events.borrow().len(), 6);
+    }
+
+    #[test]
+    fn seeded_producers_should_jitter_identically() {
+        let producer = |seed| {
+            let fetcher = MockFetcher::new(vec![]);
+            Producer::new(
+                Config::default(),
+                fetcher,
+                MockSink::default(),
+                test_logger(),
+            )
+            .with_seed(seed)
+        };
+        let max = Duration::from_secs(10);
+        let sequence = |seed| {
+            let mut producer = producer(seed);
+            (0..8)
+                .map(|_| producer.jitter(max))
+                .collect::<Vec<Duration>>()
+        };
+
+        assert_eq!(sequence(42), sequence(42));
+        assert_ne!(sequence(42), sequence(43));
+    }
+
+    #[test]
+    fn cold_start_should_emit_stale_reports_once() {
+        let config = Config {
+            max_report_age_hours: 6,
+            emit_stale_on_cold_start: true,
+            ..Config::default()
+        };
+        let fetcher = MockFetcher::new(vec![wind_reports(2), wind_reports(3)]);
+        let sink = MockSink::default();
+        let mut producer = Producer::new(config, fetcher, sink.clone(), test_logger());
+
+        assert_eq!(producer.poll_once().stored, 2);
+
+        // The first two are seen, and the new one is as stale but no longer exempt
+        let result = producer.poll_once();
+        assert_eq!(result.new_events, 1);
+        assert_eq!(result.stored, 0);
+        assert_eq!(sink.events.borrow().len(), 2);
+    }
+
+    #[test]
+    fn cold_start_should_cover_deferred_and_unconfirmed_reports() {
+        let config = Config {
+            max_report_age_hours: 6,
+            emit_stale_on_cold_start: true,
+            max_events_per_cycle: Some(1),
+            confirm_after_polls: 2,
+            ..Config::default()
+        };
+        let fetcher = MockFetcher::new(vec![wind_reports(3); 4]);
+        let sink = MockSink::default();
+        let mut producer = Producer::new(config, fetcher, sink.clone(), test_logger());
+
+        for _ in 0..4 {
+            producer.poll_once();
+        }
+        assert_eq!(sink.events.borrow().len(), 3);
+    }
+
+    #[test]
+    fn cold_start_should_wait_for_a_non_empty_feed() {
+        let config = Config {
+            max_report_age_hours: 6,
+            emit_stale_on_cold_start: true,
+            ..Config::default()
+        };
+        let fetcher = MockFetcher::new(vec![String::new(), wind_reports(2)]);
+        let mut producer = Producer::new(config, fetcher, MockSink::default(), test_logger());
+
+        assert!(producer.poll_once().empty_body);
+        assert_eq!(producer.poll_once().stored, 2);
+    }
+
+    #[test]
+    fn cold_start_should_not_apply_after_loading_seen_state() {
+        let path = temp_path("producer_cold_start.txt");
+        let config = || Config {
+            seen_state_path: Some(path.to_str().unwrap().to_string()),
+            max_report_age_hours: 6,
+            emit_stale_on_cold_start: true,
+            ..Config::default()
+        };
+        let fetcher = MockFetcher::new(vec![wind_reports(2)]);
+        let mut producer = Producer::new(config(), fetcher, MockSink::default(), test_logger());
+        assert_eq!(producer.poll_once().stored, 2);
+
+        let fetcher = MockFetcher::new(vec![wind_reports(3)]);
+        let mut producer = Producer::new(config(), fetcher, MockSink::default(), test_logger());
+        let result = producer.poll_once();
+        assert_eq!(result.new_events, 1);
+        assert_eq!(result.stored, 0);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn max_report_age_should_drop_stale_reports_without_cold_start_toggle() {
+        let config = Config {
+            max_report_age_hours: 6,
+            ..Config::default()
+        };
+        let fetcher = MockFetcher::new(vec![wind_reports(2)]);
+        let mut producer = Producer::new(config, fetcher, MockSink::default(), test_logger());
+
+        let result = producer.poll_once();
+        assert_eq!(result.new_events, 2);
+        assert_eq!(result.stored, 0);
+    }
+
+    #[test]
+    fn max_report_age_should_follow_the_producer_clock() {
+        let config = Config {
+            max_report_age_hours: 6,
+            ..Config::default()
+        };
+        let fetcher = MockFetcher::new(vec![wind_reports(2)]);
+        let mut producer = Producer::new(config, fetcher, MockSink::default(), test_logger())
+            .with_clock(|| 1_537_484_400_000_000);
+
+        assert_eq!(producer.poll_once().stored, 2);
+    }
+
+    #[test]
+    fn store_failures_should_go_to_dead_letter_queue_and_retry() {
+        let fetcher = MockFetcher::new(vec![wind_reports(5), wind_reports(5)]);
+        let sink = ToggleSink::default();
+        sink.failing.set(true);
+        let mut producer = Producer::new(Config::default(), fetcher, sink.clone(), test_logger());
+
+        let result = producer.poll_once();
+        assert_eq!(result.store_failures, 5);
+        assert_eq!(result.dead_letters, 5);
+        assert_eq!(producer.dead_letters.len(), 5);
+
+        sink.failing.set(false);
+        let result = producer.poll_once();
+        assert_eq!(result.new_events, 0);
+        assert_eq!(result.stored, 0);
+        assert_eq!(result.retried, 5);
+        assert_eq!(result.dead_letters, 0);
+        assert_eq!(sink.events.borrow().len(), 5);
+    }
+
+    #[test]
+    fn uncertain_store_failures_should_not_be_dead_lettered() {
+        let fetcher = MockFetcher::new(vec![wind_reports(3)]);
+        let sink = FailingSink {
+            reason: "store request timed out",
+        };
+        let mut producer = Producer::new(Config::default(), fetcher, sink, test_logger());
+
+        let result = producer.poll_once();
+        assert_eq!(result.store_failures, 3);
+        assert_eq!(result.dead_letters, 0);
+    }
+
+    #[test]
+    fn dead_letter_queue_should_evict_oldest_when_full() {
+        let config = Config {
+            dead_letter_capacity: 2,
+            ..Config::default()
+        };
+        let fetcher = MockFetcher::new(vec![wind_reports(3)]);
+        let sink = FailingSink {
+            reason: "connection refused",
+        };
+        let mut producer = Producer::new(config, fetcher, sink, test_logger());
+
+        let result = producer.poll_once();
+        assert_eq!(result.store_failures, 3);
+        assert_eq!(result.dead_letters, 2);
+    }
+
+    #[test]
+    fn recent_events_should_keep_latest_stored_in_order() {
+        let config = Config {
+            recent_events_capacity: 3,
+            ..Config::default()
+        };
+        let fetcher = MockFetcher::new(vec![wind_reports(5)]);
+        let sink = MockSink::default();
+        let mut producer = Producer::new(config, fetcher, sink.clone(), test_logger());
+        assert_eq!(producer.recent_events().count(), 0);
+
+        producer.poll_once();
+        let stored = sink.events.borrow();
+        let recent: Vec<&Event> = producer.recent_events().collect();
+
+        assert_eq!(recent.len(), 3);
+        assert!(recent.into_iter().eq(stored[2..].iter()));
+    }
+
+    #[test]
+    fn recent_events_should_be_disabled_by_zero_capacity() {
+        let config = Config {
+            recent_events_capacity: 0,
+            ..Config::default()
+        };
+        let fetcher = MockFetcher::new(vec![wind_reports(5)]);
+        let mut producer = Producer::new(config, fetcher, MockSink::default(), test_logger());
+
+        producer.poll_once();
+        assert_eq!(producer.recent_events().count(), 0);
+    }
+
+    #[test]
+    fn persistent_store_failures_should_flag_store_unavailable_when_enabled() {
+        let config = Config {
+            exit_on_store_unavailable: true,
+            store_failure_threshold: 5,
+            ..Config::default()
+        };
+        let fetcher = MockFetcher::new(vec![wind_reports(3), wind_reports(6)]);
+        let sink = FailingSink {
+            reason: "connection refused",
+        };
+        let mut producer = Producer::new(config, fetcher, sink, test_logger());
+
+        assert!(!producer.poll_once().store_unavailable);
+        assert!(producer.poll_once().store_unavailable);
+    }
+
+    #[test]
+    fn persistent_store_failures_should_not_flag_store_unavailable_by_default() {
+        let config = Config {
+            store_failure_threshold: 1,
+            ..Config::default()
+        };
+        let fetcher = MockFetcher::new(vec![wind_reports(3)]);
+        let sink = FailingSink {
+            reason: "connection refused",
+        };
+        let mut producer = Producer::new(config, fetcher, sink, test_logger());
+
+        assert!(!producer.poll_once().store_unavailable);
+    }
+
+    #[test]
+    fn bounding_box_should_drop_reports_outside_it() {
+        let config = Config {
+            bounding_box: Some(BoundingBox {
+                min_lat: 40.0,
+                max_lat: 45.0,
+                min_lon: -100.0,
+                max_lon: -90.0,
+            }),
+            ..Config::default()
+        };
+        let inside = WIND_REPORT;
+        let outside = r#"Icon: 47.617706,-111.215248,000,4,4,"Reported By: Test User\nHail\nTime: 2018-09-20 22:39:00 UTC\nSize: 0.75" (Penny)\nNotes: None""#;
+        let fetcher = MockFetcher::new(vec![format!("{}\n{}", inside, outside)]);
+        let mut producer = Producer::new(config, fetcher, MockSink::default(), test_logger());
+
+        let result = producer.poll_once();
+
+        assert_eq!(result.stored, 1);
+        assert_eq!(result.out_of_bounds, 1);
+    }
+
+    #[test]
+    fn leading_bom_should_not_hide_first_report() {
+        let body = format!("\u{feff}{}", wind_reports(2));
+        let fetcher = MockFetcher::new(vec![body.clone()]);
+        let mut producer = Producer::new(
+            Config::default(),
+            fetcher,
+            MockSink::default(),
+            test_logger(),
+        );
+
+        assert_eq!(producer.dry_run().unwrap().len(), 2);
+        // Bodies fetched some other way, e.g. by the async client, go through poll_with
+        let result = producer.poll_with(Ok(body), Duration::from_millis(0));
+        assert_eq!(result.new_events, 2);
+        assert_eq!(result.stored, 2);
+    }
+
+    #[test]
+    fn bom_only_body_should_not_wipe_seen() {
+        let body = wind_reports(3);
+        let fetcher =
+            MockFetcher::new(vec![body, "\u{feff}".to_string(), "\u{feff}\n".to_string()]);
+        let mut producer = Producer::new(
+            Config::default(),
+            fetcher,
+            MockSink::default(),
+            test_logger(),
+        );
+
+        assert_eq!(producer.poll_once().stored, 3);
+
+        for _ in 0..2 {
+            let result = producer.poll_once();
+            assert!(result.empty_body);
+            assert_eq!(producer.seen.len(), 3);
+        }
+    }
+
+    #[test]
+    fn dry_run_should_filter_and_transform_without_storing() {
+        let config = Config {
+            bounding_box: Some(BoundingBox {
+                min_lat: 40.0,
+                max_lat: 45.0,
+                min_lon: -100.0,
+                max_lon: -90.0,
+            }),
+            include_raw_captures: true,
+            ..Config::default()
+        };
+        let inside = WIND_REPORT;
+        let outside = r#"Icon: 47.617706,-111.215248,000,4,4,"Reported By: Test User\nHail\nTime: 2018-09-20 22:39:00 UTC\nSize: 0.75" (Penny)\nNotes: None""#;
+        let body = format!("Icon: garbage\n{}\n{}", inside, outside);
+        let fetcher = MockFetcher::new(vec![body.clone(), body]);
+        let sink = MockSink::default();
+        let drain = CapturingDrain::default();
+        let logger = Logger::root(drain.clone(), o!());
+        let mut producer = Producer::new(config, fetcher, sink.clone(), logger)
+            .with_transform(|event| event.title = event.title.to_uppercase());
+
+        let events = producer.dry_run().unwrap();
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event.title, "REPORT: 60 MPH WIND");
+        assert_eq!(events[0].raw_captures.as_ref().unwrap()["mph"], "60");
+        assert!(sink.events.borrow().is_empty());
+        let parse_failures = drain
+            .messages
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|x| *x == "parse")
+            .count();
+        assert_eq!(parse_failures, 1);
+        // Nothing was recorded as seen
+        assert_eq!(producer.poll_once().stored, 1);
+    }
+
+    #[test]
+    fn exclusion_zones_should_drop_reports_inside_them() {
+        // Both reports are about 1.1km north of the zone's center
+        let config = Config {
+            exclusion_zones: vec![ExclusionZone {
+                lat: 43.102,
+                lon: -94.64,
+                radius_km: 1.2,
+            }],
+            ..Config::default()
+        };
+        let fetcher = MockFetcher::new(vec![WIND_REPORT.to_string()]);
+        let mut producer = Producer::new(config, fetcher, MockSink::default(), test_logger());
+
+        let result = producer.poll_once();
+        assert_eq!(result.excluded, 1);
+        assert_eq!(result.stored, 0);
+    }
+
+    #[test]
+    fn exclusion_zones_should_keep_reports_just_outside_them() {
+        let config = Config {
+            exclusion_zones: vec![ExclusionZone {
+                lat: 43.102,
+                lon: -94.64,
+                radius_km: 1.0,
+            }],
+            ..Config::default()
+        };
+        let fetcher = MockFetcher::new(vec![WIND_REPORT.to_string()]);
+        let mut producer = Producer::new(config, fetcher, MockSink::default(), test_logger());
+
+        let result = producer.poll_once();
+        assert_eq!(result.excluded, 0);
+        assert_eq!(result.stored, 1);
+    }
+
+    fn secondary_dedup_config() -> Config {
+        Config {
+            enable_secondary_dedup: true,
+            ..Config::default()
+        }
+    }
+
+    // 2018-09-20 22:54:00 UTC
+    fn test_clock() -> u64 {
+        1_537_484_040_000_000
+    }
+
+    #[test]
+    fn quiet_stretch_should_write_one_heartbeat_to_diagnostic_sink() {
+        let config = Config {
+            heartbeat_interval_secs: Some(60),
+            ..Config::default()
+        };
+        let now = Rc::new(Cell::new(test_clock()));
+        let clock = now.clone();
+        let body = wind_reports(2);
+        let fetcher = MockFetcher::new(vec![body.clone(), body.clone(), body.clone(), body]);
+        let sink = MockSink::default();
+        let diagnostics = MockSink::default();
+        let mut producer = Producer::new(config, fetcher, sink.clone(), test_logger())
+            .with_clock(move || clock.get())
+            .with_diagnostic_sink(diagnostics.clone());
+
+        assert!(!producer.poll_once().heartbeat);
+        let heartbeats: Vec<bool> = [30, 61, 90]
+            .iter()
+            .map(|secs| {
+                now.set(test_clock() + secs * 1_000_000);
+                producer.poll_once().heartbeat
+            })
+            .collect();
+
+        assert_eq!(heartbeats, vec![false, true, false]);
+        assert_eq!(sink.events.borrow().len(), 2);
+        assert_eq!(producer.recent_events().count(), 2);
+        let heartbeats = diagnostics.events.borrow();
+        assert_eq!(heartbeats.len(), 1);
+        assert_eq!(heartbeats[0].title, "Heartbeat");
+        assert_eq!(
+            heartbeats[0].text.as_deref(),
+            Some("New hazard codes seen: 0")
+        );
+        assert!(heartbeats[0].report.is_none());
+        assert_eq!(heartbeats[0].event_ts, test_clock() + 61_000_000);
+    }
+
+    #[test]
+    fn heartbeats_should_need_a_diagnostic_sink() {
+        let config = Config {
+            heartbeat_interval_secs: Some(60),
+            ..Config::default()
+        };
+        let now = Rc::new(Cell::new(test_clock()));
+        let clock = now.clone();
+        let fetcher = MockFetcher::new(vec![wind_reports(2)]);
+        let sink = MockSink::default();
+        let mut producer = Producer::new(config, fetcher, sink.clone(), test_logger())
+            .with_clock(move || clock.get());
+
+        producer.poll_once();
+        now.set(test_clock() + 61_000_000);
+        assert!(!producer.poll_once().heartbeat);
+        assert_eq!(sink.events.borrow().len(), 2);
+    }
+
+    #[test]
+    fn heartbeats_should_be_disabled_by_default() {
+        let now = Rc::new(Cell::new(test_clock()));
+        let clock = now.clone();
+        let fetcher = MockFetcher::new(vec![wind_reports(2)]);
+        let sink = MockSink::default();
+        let mut producer = Producer::new(Config::default(), fetcher, sink.clone(), test_logger())
+            .with_clock(move || clock.get());
+
+        producer.poll_once();
+        now.set(test_clock() + 86_400_000_000);
+        assert!(!producer.poll_once().heartbeat);
+        assert_eq!(sink.events.borrow().len(), 2);
+    }
+
+    #[test]
+    fn median_should_ignore_outliers() {
+        assert_eq!(median(&mut []), None);
+        assert_eq!(median(&mut [5]), Some(5));
+        assert_eq!(median(&mut [-90_000, 3, 1, 2, 80_000]), Some(2));
+        assert_eq!(median(&mut [4, 1, 3, 2]), Some(2));
+    }
+
+    #[test]
+    fn poll_once_should_detect_clock_drift() {
+        // The reports are from 2018-09-20 22:0x UTC, but our clock says it's a day later
+        let day_later = || test_clock() + 86_400 * 1_000_000;
+        let fetcher = MockFetcher::new(vec![wind_reports(1), wind_reports(5)]);
+        let mut producer = Producer::new(
+            Config::default(),
+            fetcher,
+            MockSink::default(),
+            test_logger(),
+        )
+        .with_clock(day_later);
+
+        let result = producer.poll_once();
+        assert_eq!(result.clock_offset_secs, None);
+        assert!(!result.clock_drift);
+
+        let result = producer.poll_once();
+        assert_eq!(result.clock_offset_secs, Some(-89_520));
+        assert!(result.clock_drift);
+    }
+
+    #[test]
+    fn poll_once_should_not_flag_recent_reports_as_drift() {
+        // 2018-09-20 22:05:00 UTC, just after the reports
+        let clock = || 1_537_481_100_000_000;
+        let fetcher = MockFetcher::new(vec![wind_reports(1), wind_reports(5)]);
+        let mut producer = Producer::new(
+            Config::default(),
+            fetcher,
+            MockSink::default(),
+            test_logger(),
+        )
+        .with_clock(clock);
+        producer.poll_once();
+
+        let result = producer.poll_once();
+        assert_eq!(result.clock_offset_secs, Some(-180));
+        assert!(!result.clock_drift);
+    }
+
+    #[test]
+    fn secondary_key_should_bucket_by_window() {
+        let parser = ReportParser::new();
+        let event = parser.parse(WIND_REPORT).unwrap().unwrap();
+        let key = SecondaryKey::new(WIND_REPORT, &event, 5).unwrap();
+
+        assert_eq!(key.reporter, "Test Human");
+        assert_eq!(key.hazard_code, 5);
+        assert_eq!(key.ts_bucket, 1_537_483_920 / 300);
+    }
+
+    #[test]
+    fn secondary_dedup_should_skip_resubmissions_across_cycles() {
+        let first = WIND_REPORT;
+        let second = r#"Icon: 43.113000,-94.639999,000,3,5,"Reported By: Test Human\nHigh Wind\nTime: 2018-09-20 22:53:00 UTC\n60 mph [Measured]\nNotes: Tree down""#;
+        let sink = MockSink::default();
+        let fetcher = MockFetcher::new(vec![first.to_string(), second.to_string()]);
+        let mut producer = Producer::new(
+            secondary_dedup_config(),
+            fetcher,
+            sink.clone(),
+            test_logger(),
+        )
+        .with_clock(test_clock);
+
+        assert_eq!(producer.poll_once().stored, 1);
+        let result = producer.poll_once();
+
+        assert_eq!(result.stored, 0);
+        assert_eq!(result.probable_duplicates, 1);
+        assert_eq!(sink.events.borrow().len(), 1);
+    }
+
+    #[test]
+    fn would_emit_should_explain_each_decision() {
+        let wind = wind_reports(1);
+        let other = r#"Icon: 43.112000,-94.639999,000,3,8,"Reported By: Test Human\nOther\nTime: 2018-09-20 22:52:00 UTC\nNotes: None""#;
+        let far_away = r#"Icon: 61.217000,-149.900000,000,3,5,"Reported By: Test Human\nHigh Wind\nTime: 2018-09-20 22:52:00 UTC\n60 mph [Measured]\nNotes: None""#;
+        let resubmitted = r#"Icon: 43.113000,-94.639999,000,3,5,"Reported By: Test Human\nHigh Wind\nTime: 2018-09-20 22:01:00 UTC\n60 mph [Measured]\nNotes: Tree down""#;
+        let config = Config {
+            bounding_box: Some(BoundingBox {
+                min_lat: 25.0,
+                max_lat: 50.0,
+                min_lon: -125.0,
+                max_lon: -65.0,
+            }),
+            ..secondary_dedup_config()
+        };
+        let fetcher = MockFetcher::new(vec![wind.clone()]);
+        let mut producer = Producer::new(config, fetcher, MockSink::default(), test_logger())
+            .with_clock(test_clock);
+
+        assert_eq!(producer.would_emit(&wind), EmitDecision::Emit);
+        producer.poll_once();
+
+        assert_eq!(producer.would_emit(&wind), EmitDecision::AlreadySeen);
+        assert_eq!(producer.would_emit(other), EmitDecision::Skipped);
+        assert_eq!(producer.would_emit(far_away), EmitDecision::OutOfBounds);
+        assert_eq!(
+            producer.would_emit(resubmitted),
+            EmitDecision::ProbableDuplicate
+        );
+        assert!(matches!(
+            producer.would_emit("Icon: garbage"),
+            EmitDecision::ParseError(_)
+        ));
+    }
+
+    #[test]
+    fn would_emit_should_explain_exclusions() {
+        let wind = wind_reports(1);
+        let config = Config {
+            exclusion_zones: vec![ExclusionZone {
+                lat: 43.102,
+                lon: -94.64,
+                radius_km: 1.2,
+            }],
+            ..Config::default()
+        };
+        let producer = Producer::new(
+            config,
+            MockFetcher::new(vec![]),
+            MockSink::default(),
+            test_logger(),
+        )
+        .with_clock(test_clock);
+
+        assert_eq!(producer.would_emit(&wind), EmitDecision::Excluded);
+    }
+
+    #[test]
+    fn would_emit_should_not_run_transforms() {
+        let calls = Rc::new(Cell::new(0));
+        let counter = calls.clone();
+        let producer = Producer::new(
+            Config::default(),
+            MockFetcher::new(vec![]),
+            MockSink::default(),
+            test_logger(),
+        )
+        .with_clock(test_clock)
+        .with_transform(move |_| counter.set(counter.get() + 1));
+
+        assert_eq!(producer.would_emit(&wind_reports(1)), EmitDecision::Emit);
+        assert_eq!(calls.get(), 0);
+    }
+
+    #[test]
+    fn would_emit_should_not_change_state() {
+        let wind = wind_reports(1);
+        let fetcher = MockFetcher::new(vec![wind.clone()]);
+        let mut producer = Producer::new(
+            secondary_dedup_config(),
+            fetcher,
+            MockSink::default(),
+            test_logger(),
+        )
+        .with_clock(test_clock);
+
+        assert_eq!(producer.would_emit(&wind), EmitDecision::Emit);
+        assert_eq!(producer.would_emit(&wind), EmitDecision::Emit);
+        assert!(producer.seen.is_empty());
+        assert!(producer.secondary_seen.is_empty());
+        assert_eq!(producer.poll_once().stored, 1);
+    }
+
+    #[test]
+    fn secondary_dedup_should_be_disabled_by_default() {
+        let first = WIND_REPORT;
+        let second = r#"Icon: 43.113000,-94.639999,000,3,5,"Reported By: Test Human\nHigh Wind\nTime: 2018-09-20 22:53:00 UTC\n60 mph [Measured]\nNotes: Tree down""#;
+        let fetcher = MockFetcher::new(vec![format!("{}\n{}", first, second)]);
+        let mut producer = Producer::new(
+            Config::default(),
+            fetcher,
+            MockSink::default(),
+            test_logger(),
+        );
+
+        assert_eq!(producer.poll_once().stored, 2);
+    }
+
+    #[test]
+    fn secondary_keys_should_expire_after_window() {
+        let first = WIND_REPORT;
+        let fetcher = MockFetcher::new(vec![first.to_string()]);
+        let mut producer = Producer::new(
+            secondary_dedup_config(),
+            fetcher,
+            MockSink::default(),
+            test_logger(),
+        )
+        .with_clock(test_clock);
+
+        producer.poll_once();
+        assert_eq!(producer.secondary_seen.len(), 1);
+
+        producer.clock = Box::new(|| test_clock() + 10 * 60 * 1_000_000);
+        producer.poll_once();
+        assert_eq!(producer.secondary_seen.len(), 0);
+    }
+}
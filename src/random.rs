@@ -0,0 +1,68 @@
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Added to the state each step, the golden ratio in fixed point as splitmix64 uses.
+const GOLDEN_GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// The splitmix64 finalizer.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/**
+ * A seedable splitmix64 generator for jitter and backoff. Its output is fine for spreading out
+ * requests but isn't cryptographically secure. Seed it with `with_seed` for reproducible tests.
+ */
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn with_seed(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    /// Seeded from the clock and process id, so instances started together still differ.
+    pub fn from_entropy() -> Rng {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_nanos() as u64)
+            .unwrap_or_default();
+        Rng::with_seed(nanos ^ (u64::from(process::id()) << 32))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(GOLDEN_GAMMA);
+        mix(self.state)
+    }
+
+    /// A random duration from zero up to `max`, inclusive, in whole milliseconds.
+    pub fn jitter(&mut self, max: Duration) -> Duration {
+        let max_ms = max.as_millis() as u64;
+        if max_ms == 0 {
+            return Duration::from_millis(0);
+        }
+
+        Duration::from_millis(self.next_u64() % (max_ms + 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jitter_should_stay_within_max() {
+        let mut rng = Rng::with_seed(7);
+        let max = Duration::from_millis(250);
+
+        assert!((0..1000).all(|_| rng.jitter(max) <= max));
+        assert_eq!(
+            rng.jitter(Duration::from_millis(0)),
+            Duration::from_millis(0)
+        );
+    }
+}
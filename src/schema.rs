@@ -0,0 +1,113 @@
+use serde_json::{json, Value};
+
+/**
+ * A JSON Schema (draft 7) for the events this producer emits, printed by `--schema`. It's written
+ * by hand since `Event` lives in `wx`, so it has to be kept in step with `ReportParser::parse`.
+ * Fields that are always null for Spotter Network reports, like `watch`, are typed as `null`.
+ */
+#[must_use]
+pub fn event_schema() -> Value {
+    let nullable_u64 = json!({ "type": ["integer", "null"], "minimum": 0 });
+    let coordinates = json!({
+        "type": "object",
+        "required": ["lat", "lon"],
+        "properties": {
+            "lat": { "type": "number", "minimum": -90, "maximum": 90 },
+            "lon": { "type": "number", "minimum": -180, "maximum": 180 }
+        }
+    });
+
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "Event",
+        "description": "A Spotter Network report normalized to the shared sigtor.org event",
+        "type": "object",
+        "required": ["event_ts", "event_type", "ingest_ts", "title"],
+        "properties": {
+            "event_ts": {
+                "description": "Report time in microseconds since epoch",
+                "type": "integer",
+                "minimum": 0
+            },
+            "event_type": { "enum": ["SnReport"] },
+            "expires_ts": nullable_u64,
+            "ext_uri": { "type": ["string", "null"] },
+            "ingest_ts": {
+                "description": "Set by the store, 0 until then",
+                "type": "integer",
+                "minimum": 0
+            },
+            "location": {
+                "type": ["object", "null"],
+                "properties": {
+                    "county": { "type": ["string", "null"] },
+                    "wfo": { "type": ["string", "null"] },
+                    "point": { "anyOf": [coordinates, { "type": "null" }] },
+                    "poly": { "type": "null" }
+                }
+            },
+            "md": { "type": "null" },
+            "outlook": { "type": "null" },
+            "report": {
+                "type": ["object", "null"],
+                "required": ["hazard", "reporter"],
+                "properties": {
+                    "hazard": {
+                        "enum": [
+                            "Tornado", "Funnel", "WallCloud", "Hail", "Wind", "Flood", "Other",
+                            "FreezingRain", "Snow"
+                        ]
+                    },
+                    "magnitude": { "type": ["number", "null"] },
+                    "report_ts": {
+                        "description": "When SpotterNetwork posted the report, if the feed says",
+                        "type": ["integer", "null"],
+                        "minimum": 0
+                    },
+                    "reporter": { "type": "string" },
+                    "units": { "enum": ["Inches", "Mph", "Knots", null] },
+                    "was_measured": { "type": ["boolean", "null"] }
+                }
+            },
+            "text": { "type": ["string", "null"] },
+            "title": { "type": "string" },
+            "valid_ts": nullable_u64,
+            "warning": { "type": "null" },
+            "watch": { "type": "null" }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::wind_event;
+
+    #[test]
+    fn schema_should_be_valid_json_with_key_fields() {
+        let schema: Value = serde_json::from_str(&event_schema().to_string()).unwrap();
+        let properties = schema["properties"].as_object().unwrap();
+
+        assert!(properties.contains_key("event_ts"));
+        assert!(properties.contains_key("report"));
+        assert_eq!(schema["required"][0], "event_ts");
+    }
+
+    #[test]
+    fn schema_should_cover_every_emitted_field() {
+        let event = serde_json::to_value(&wind_event()).unwrap();
+        let schema = event_schema();
+
+        for field in event.as_object().unwrap().keys() {
+            assert!(
+                schema["properties"].get(field).is_some(),
+                "missing {}",
+                field
+            );
+        }
+        for field in event["report"].as_object().unwrap().keys() {
+            let properties = &schema["properties"]["report"]["properties"];
+            assert!(properties.get(field).is_some(), "missing report.{}", field);
+        }
+    }
+}
@@ -0,0 +1,561 @@
+use fnv::{FnvHashSet, FnvHasher};
+use std::collections::VecDeque;
+use std::fmt;
+use std::hash::Hasher;
+#[cfg(unix)]
+use std::io;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use wx::domain::Event;
+use wx::error::{Error, WxError};
+
+/// Destination for parsed events.
+pub trait EventSink {
+    fn put_event(&self, event: &Event) -> Result<(), StoreError>;
+
+    /**
+     * Checks the destination is reachable without writing an event, see `preflight`. Sinks that
+     * can't check, like `wx::store::Client` which only exposes `put_event`, report healthy.
+     */
+    fn check_health(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /**
+     * Whether `put_event` only queues the event to be written later, like `ChannelSink`. The
+     * producer then learns how each write went from `take_completed` instead.
+     */
+    fn writes_later(&self) -> bool {
+        false
+    }
+
+    /// Writes finished since the last call, for sinks that `writes_later`.
+    fn take_completed(&self) -> Vec<CompletedWrite> {
+        Vec::new()
+    }
+}
+
+/// A queued event and how writing it went.
+#[derive(Debug)]
+pub struct CompletedWrite {
+    pub event: Event,
+    pub result: Result<(), StoreError>,
+}
+
+impl EventSink for wx::store::Client {
+    fn put_event(&self, event: &Event) -> Result<(), StoreError> {
+        wx::store::Client::put_event(self, event).map_err(StoreError::from)
+    }
+}
+
+impl<S: EventSink + ?Sized> EventSink for Box<S> {
+    fn put_event(&self, event: &Event) -> Result<(), StoreError> {
+        (**self).put_event(event)
+    }
+
+    fn check_health(&self) -> Result<(), Error> {
+        (**self).check_health()
+    }
+
+    fn writes_later(&self) -> bool {
+        (**self).writes_later()
+    }
+
+    fn take_completed(&self) -> Vec<CompletedWrite> {
+        (**self).take_completed()
+    }
+}
+
+#[derive(Debug)]
+pub enum StoreError {
+    /// The event is already in the store, e.g. written by another instance or before a restart.
+    DuplicateKey,
+    /// The store turned the event away, so it's safe to write again later.
+    Store(Error),
+    /// The store refused this event itself, e.g. as too large, so writing it again would too.
+    Rejected(Error),
+    /**
+     * The write failed without saying whether the store kept the event, e.g. it timed out waiting
+     * for a reply. Writing it again could store it twice.
+     */
+    Uncertain(Error),
+}
+
+impl From<Error> for StoreError {
+    fn from(error: Error) -> StoreError {
+        // wx::store doesn't expose an error kind, so the message is all we have to go on
+        let message = error.to_string().to_lowercase();
+        if message.contains("duplicate key") {
+            StoreError::DuplicateKey
+        } else if message.contains("timed out") || message.contains("timeout") {
+            StoreError::Uncertain(error)
+        } else {
+            StoreError::Store(error)
+        }
+    }
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StoreError::DuplicateKey => write!(f, "duplicate key"),
+            StoreError::Store(e) => write!(f, "{}", e),
+            StoreError::Rejected(e) => write!(f, "{}, event rejected", e),
+            StoreError::Uncertain(e) => write!(f, "{}, event may have been stored", e),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Encoding {
+    /// Newline-delimited JSON.
+    Json,
+    /// Bincode, prefixed with the payload length as a big-endian u32.
+    #[cfg(feature = "binary")]
+    Binary,
+}
+
+pub fn encode_event(event: &Event, encoding: Encoding) -> Result<Vec<u8>, Error> {
+    match encoding {
+        Encoding::Json => match serde_json::to_vec(event) {
+            Ok(mut bytes) => {
+                bytes.push(b'\n');
+                Ok(bytes)
+            }
+            Err(e) => {
+                let reason = format!("unable to serialize event: {}", e);
+                Err(Error::Wx(<WxError>::new(&reason)))
+            }
+        },
+        #[cfg(feature = "binary")]
+        Encoding::Binary => match bincode::serialize(event) {
+            Ok(payload) => {
+                let mut bytes = (payload.len() as u32).to_be_bytes().to_vec();
+                bytes.extend(payload);
+                Ok(bytes)
+            }
+            Err(e) => {
+                let reason = format!("unable to serialize event: {}", e);
+                Err(Error::Wx(<WxError>::new(&reason)))
+            }
+        },
+    }
+}
+
+/**
+ * Identifies an event by hashing its JSON, standing in for an event ID until events carry one.
+ * Events parsed from the same report line always hash the same.
+ */
+pub fn event_key(event: &Event) -> Result<u64, Error> {
+    let bytes = encode_event(event, Encoding::Json)?;
+    let mut hasher = FnvHasher::default();
+    hasher.write(&bytes);
+    Ok(hasher.finish())
+}
+
+#[derive(Default)]
+struct WrittenKeys {
+    order: VecDeque<u64>,
+    keys: FnvHashSet<u64>,
+    /// Keys being written right now, so concurrent writers can't both write the same event.
+    in_flight: FnvHashSet<u64>,
+}
+
+/**
+ * Makes writes idempotent by remembering the keys of the last `window` events written and skipping
+ * repeats with `StoreError::DuplicateKey`. `wx::store` has no put-if-absent or existence check, so
+ * this only catches repeats from this process, e.g. an event queued twice before a restart.
+ */
+pub struct IdempotentSink<S: EventSink> {
+    inner: S,
+    window: usize,
+    written: Mutex<WrittenKeys>,
+    skipped_duplicate_total: AtomicU64,
+}
+
+impl<S: EventSink> IdempotentSink<S> {
+    pub fn new(inner: S, window: usize) -> IdempotentSink<S> {
+        IdempotentSink {
+            inner,
+            window,
+            written: Mutex::new(WrittenKeys::default()),
+            skipped_duplicate_total: AtomicU64::new(0),
+        }
+    }
+
+    /// Writes skipped so far because the event had already been written.
+    pub fn skipped_duplicate_total(&self) -> u64 {
+        self.skipped_duplicate_total.load(Ordering::Relaxed)
+    }
+}
+
+impl<S: EventSink> EventSink for IdempotentSink<S> {
+    fn put_event(&self, event: &Event) -> Result<(), StoreError> {
+        let key = event_key(event)?;
+
+        {
+            let mut written = self.written.lock().unwrap();
+            if written.keys.contains(&key) || !written.in_flight.insert(key) {
+                self.skipped_duplicate_total.fetch_add(1, Ordering::Relaxed);
+                return Err(StoreError::DuplicateKey);
+            }
+        }
+
+        let result = self.inner.put_event(event);
+
+        let mut written = self.written.lock().unwrap();
+        written.in_flight.remove(&key);
+        // Only remembered if it may have been written, so a failed write can be retried
+        if let Err(StoreError::Store(_)) | Err(StoreError::Rejected(_)) = result {
+            return result;
+        }
+        if written.order.len() >= self.window {
+            if let Some(oldest) = written.order.pop_front() {
+                written.keys.remove(&oldest);
+            }
+        }
+        if self.window > 0 && written.keys.insert(key) {
+            written.order.push_back(key);
+        }
+
+        result
+    }
+
+    fn check_health(&self) -> Result<(), Error> {
+        self.inner.check_health()
+    }
+}
+
+/// Writes encoded events to stdout, a file, or anything else implementing `Write`.
+pub struct WriterSink<W: Write> {
+    writer: Mutex<W>,
+    encoding: Encoding,
+}
+
+impl<W: Write> WriterSink<W> {
+    pub fn new(writer: W, encoding: Encoding) -> WriterSink<W> {
+        WriterSink {
+            writer: Mutex::new(writer),
+            encoding,
+        }
+    }
+}
+
+impl<W: Write> EventSink for WriterSink<W> {
+    fn put_event(&self, event: &Event) -> Result<(), StoreError> {
+        let bytes = encode_event(event, self.encoding)?;
+        let mut writer = self.writer.lock().unwrap();
+
+        writer
+            .write_all(&bytes)
+            .and_then(|_| writer.flush())
+            .map_err(|e| {
+                let reason = format!("unable to write event: {}", e);
+                StoreError::Store(Error::Wx(<WxError>::new(&reason)))
+            })
+    }
+}
+
+/**
+ * Writes encoded events to a Unix domain socket for co-located consumers. Connects on the first
+ * event, and if a write fails, e.g. because the consumer restarted, reconnects and tries once more.
+ */
+#[cfg(unix)]
+pub struct UnixSocketSink {
+    path: PathBuf,
+    encoding: Encoding,
+    stream: Mutex<Option<UnixStream>>,
+}
+
+#[cfg(unix)]
+impl UnixSocketSink {
+    pub fn new<P: Into<PathBuf>>(path: P, encoding: Encoding) -> UnixSocketSink {
+        UnixSocketSink {
+            path: path.into(),
+            encoding,
+            stream: Mutex::new(None),
+        }
+    }
+
+    fn write(&self, stream: &mut Option<UnixStream>, bytes: &[u8]) -> io::Result<()> {
+        if stream.is_none() {
+            *stream = Some(UnixStream::connect(&self.path)?);
+        }
+
+        let result = stream.as_mut().unwrap().write_all(bytes);
+        if result.is_err() {
+            *stream = None;
+        }
+
+        result
+    }
+}
+
+#[cfg(unix)]
+impl EventSink for UnixSocketSink {
+    fn put_event(&self, event: &Event) -> Result<(), StoreError> {
+        let bytes = encode_event(event, self.encoding)?;
+        let mut stream = self.stream.lock().unwrap();
+
+        self.write(&mut stream, &bytes)
+            .or_else(|_| self.write(&mut stream, &bytes))
+            .map_err(|e| {
+                let reason = format!("unable to write event to {}: {}", self.path.display(), e);
+                StoreError::Store(Error::Wx(<WxError>::new(&reason)))
+            })
+    }
+
+    fn check_health(&self) -> Result<(), Error> {
+        let mut stream = self.stream.lock().unwrap();
+        if stream.is_none() {
+            *stream = Some(UnixStream::connect(&self.path)?);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::wind_event;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn writer_sink_should_write_json_lines() {
+        let sink = WriterSink::new(Vec::new(), Encoding::Json);
+        sink.put_event(&wind_event()).unwrap();
+        sink.put_event(&wind_event()).unwrap();
+
+        let output = String::from_utf8(sink.writer.into_inner().unwrap()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert_eq!(lines.len(), 2);
+        let event: Event = serde_json::from_str(lines[0]).unwrap();
+        assert!(event == wind_event());
+    }
+
+    #[derive(Default)]
+    struct CountingSink {
+        events: Mutex<Vec<Event>>,
+    }
+
+    impl EventSink for CountingSink {
+        fn put_event(&self, event: &Event) -> Result<(), StoreError> {
+            self.events.lock().unwrap().push(event.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn idempotent_sink_should_write_repeated_event_once() {
+        let sink = IdempotentSink::new(CountingSink::default(), 10);
+
+        sink.put_event(&wind_event()).unwrap();
+        assert!(matches!(
+            sink.put_event(&wind_event()),
+            Err(StoreError::DuplicateKey)
+        ));
+
+        assert_eq!(sink.inner.events.lock().unwrap().len(), 1);
+        assert_eq!(sink.skipped_duplicate_total(), 1);
+    }
+
+    #[test]
+    fn idempotent_sink_should_forget_keys_outside_window() {
+        let sink = IdempotentSink::new(CountingSink::default(), 1);
+        let mut other = wind_event();
+        other.event_ts += 1;
+
+        sink.put_event(&wind_event()).unwrap();
+        sink.put_event(&other).unwrap();
+        sink.put_event(&wind_event()).unwrap();
+
+        assert_eq!(sink.inner.events.lock().unwrap().len(), 3);
+        assert_eq!(sink.skipped_duplicate_total(), 0);
+    }
+
+    #[test]
+    fn idempotent_sink_should_write_concurrent_duplicates_once() {
+        struct SlowSink(CountingSink);
+
+        impl EventSink for SlowSink {
+            fn put_event(&self, event: &Event) -> Result<(), StoreError> {
+                thread::sleep(Duration::from_millis(50));
+                self.0.put_event(event)
+            }
+        }
+
+        let sink = Arc::new(IdempotentSink::new(SlowSink(CountingSink::default()), 10));
+        let writers: Vec<_> = (0..4)
+            .map(|_| {
+                let sink = sink.clone();
+                thread::spawn(move || sink.put_event(&wind_event()).is_ok())
+            })
+            .collect();
+        let results: Vec<bool> = writers.into_iter().map(|x| x.join().unwrap()).collect();
+
+        assert_eq!(results.iter().filter(|&&x| x).count(), 1);
+        assert_eq!(sink.inner.0.events.lock().unwrap().len(), 1);
+        assert_eq!(sink.skipped_duplicate_total(), 3);
+    }
+
+    #[test]
+    fn idempotent_sink_should_release_key_when_write_fails() {
+        struct FlakySink {
+            failures: Mutex<usize>,
+            inner: CountingSink,
+        }
+
+        impl EventSink for FlakySink {
+            fn put_event(&self, event: &Event) -> Result<(), StoreError> {
+                let mut failures = self.failures.lock().unwrap();
+                if *failures > 0 {
+                    *failures -= 1;
+                    let e = Error::Wx(<WxError>::new("connection refused"));
+                    return Err(StoreError::Store(e));
+                }
+                self.inner.put_event(event)
+            }
+        }
+
+        let flaky = FlakySink {
+            failures: Mutex::new(1),
+            inner: CountingSink::default(),
+        };
+        let sink = IdempotentSink::new(flaky, 10);
+
+        assert!(matches!(
+            sink.put_event(&wind_event()),
+            Err(StoreError::Store(_))
+        ));
+        sink.put_event(&wind_event()).unwrap();
+        assert_eq!(sink.inner.inner.events.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn store_error_should_classify_messages() {
+        let classify = |reason| StoreError::from(Error::Wx(<WxError>::new(reason)));
+
+        assert!(matches!(
+            classify("Duplicate key: event already exists"),
+            StoreError::DuplicateKey
+        ));
+        assert!(matches!(
+            classify("connection refused"),
+            StoreError::Store(_)
+        ));
+        assert!(matches!(
+            classify("operation timed out"),
+            StoreError::Uncertain(_)
+        ));
+    }
+
+    #[test]
+    fn event_key_should_differ_between_events() {
+        let mut other = wind_event();
+        other.event_ts += 1;
+
+        assert_eq!(
+            event_key(&wind_event()).unwrap(),
+            event_key(&wind_event()).unwrap()
+        );
+        assert_ne!(
+            event_key(&wind_event()).unwrap(),
+            event_key(&other).unwrap()
+        );
+    }
+
+    #[cfg(unix)]
+    mod unix_socket {
+        use super::*;
+        use std::env;
+        use std::fs;
+        use std::io::{BufRead, BufReader};
+        use std::os::unix::net::UnixListener;
+        use std::sync::mpsc;
+        use std::thread;
+
+        fn bind_temp_socket(name: &str) -> (PathBuf, UnixListener) {
+            let path = env::temp_dir().join(name);
+            let _ = fs::remove_file(&path);
+            let listener = UnixListener::bind(&path).unwrap();
+            (path, listener)
+        }
+
+        #[test]
+        fn unix_socket_sink_should_write_json_lines() {
+            let (path, listener) = bind_temp_socket("sn_loader_sink.sock");
+            let sink = UnixSocketSink::new(&path, Encoding::Json);
+
+            sink.put_event(&wind_event()).unwrap();
+            sink.put_event(&wind_event()).unwrap();
+            drop(sink);
+
+            let (stream, _) = listener.accept().unwrap();
+            let lines: Vec<String> = BufReader::new(stream).lines().map(|x| x.unwrap()).collect();
+            assert_eq!(lines.len(), 2);
+            let event: Event = serde_json::from_str(&lines[0]).unwrap();
+            assert!(event == wind_event());
+            fs::remove_file(path).unwrap();
+        }
+
+        #[test]
+        fn unix_socket_sink_should_reconnect_after_consumer_restarts() {
+            let (path, listener) = bind_temp_socket("sn_loader_sink_reconnect.sock");
+            let (sender, receiver) = mpsc::channel();
+
+            thread::spawn(move || {
+                for _ in 0..2 {
+                    let (stream, _) = listener.accept().unwrap();
+                    let mut line = String::new();
+                    BufReader::new(stream).read_line(&mut line).unwrap();
+                    // The connection is dropped before the line is handed back
+                    sender.send(line).unwrap();
+                }
+            });
+
+            let sink = UnixSocketSink::new(&path, Encoding::Json);
+            sink.put_event(&wind_event()).unwrap();
+            assert!(receiver.recv().unwrap().ends_with('\n'));
+
+            sink.put_event(&wind_event()).unwrap();
+            let event: Event = serde_json::from_str(&receiver.recv().unwrap()).unwrap();
+            assert!(event == wind_event());
+            fs::remove_file(path).unwrap();
+        }
+
+        #[test]
+        fn unix_socket_sink_should_fail_without_consumer() {
+            let path = env::temp_dir().join("sn_loader_sink_missing.sock");
+            let _ = fs::remove_file(&path);
+            let sink = UnixSocketSink::new(&path, Encoding::Json);
+
+            assert!(matches!(
+                sink.put_event(&wind_event()),
+                Err(StoreError::Store(_))
+            ));
+        }
+    }
+
+    #[cfg(feature = "binary")]
+    #[test]
+    fn binary_encoding_should_round_trip() {
+        let event = wind_event();
+        let bytes = encode_event(&event, Encoding::Binary).unwrap();
+
+        let mut length = [0u8; 4];
+        length.copy_from_slice(&bytes[..4]);
+        assert_eq!(u32::from_be_bytes(length) as usize, bytes.len() - 4);
+
+        let decoded: Event = bincode::deserialize(&bytes[4..]).unwrap();
+        assert!(decoded == event);
+    }
+}
@@ -0,0 +1,195 @@
+use crate::{DedupKey, SeenSet};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use wx::error::{Error, WxError};
+
+/// The first two bytes of every gzip stream.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/**
+ * Starts the first line of the current format, followed by the `DedupKey` the hashes were made
+ * with. Files without it are from before the seen set was hashed, with one report per line.
+ */
+const HEADER: &str = "seen-state v2 ";
+
+/// Whether a state file should be compressed, either by config or a `.gz` extension.
+#[must_use]
+pub fn should_compress(path: &Path, compress: bool) -> bool {
+    compress || path.extension().and_then(|ext| ext.to_str()) == Some("gz")
+}
+
+/**
+ * Writes the seen set one hex hash per line after a header naming `key`, gzip-compressed if
+ * `compress` is set. The file is written alongside and renamed into place so a crash mid-write
+ * can't leave a partial state file.
+ */
+pub fn save_seen(path: &Path, seen: &SeenSet, key: DedupKey, compress: bool) -> Result<(), Error> {
+    let tmp_path = path.with_extension("tmp");
+    let file = BufWriter::new(File::create(&tmp_path)?);
+
+    if compress {
+        let mut encoder = GzEncoder::new(file, Compression::default());
+        write_lines(&mut encoder, seen, key)?;
+        encoder.finish()?.flush()?;
+    } else {
+        let mut file = file;
+        write_lines(&mut file, seen, key)?;
+        file.flush()?;
+    }
+
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/**
+ * Reads a seen set written by `save_seen`, detecting gzip from the file contents. Reports in the
+ * older one report per line format are hashed by `key` as they're read. Hashes made with a
+ * different key wouldn't match anything, so those files are rejected.
+ */
+pub fn load_seen(path: &Path, key: DedupKey) -> Result<SeenSet, Error> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let is_gzip = reader.fill_buf()?.starts_with(&GZIP_MAGIC);
+    let reader: Box<dyn Read> = if is_gzip {
+        Box::new(GzDecoder::new(reader))
+    } else {
+        Box::new(reader)
+    };
+
+    let mut lines = BufReader::new(reader).lines().peekable();
+    let header = match lines.peek() {
+        Some(Ok(line)) if line.starts_with(HEADER) => Some(line[HEADER.len()..].to_string()),
+        _ => None,
+    };
+
+    let mut seen = SeenSet::default();
+    match header {
+        Some(saved_key) => {
+            if saved_key != format!("{:?}", key) {
+                let reason = format!("seen state hashed by {}, not {:?}", saved_key, key);
+                return Err(Error::Wx(<WxError>::new(&reason)));
+            }
+
+            for line in lines.skip(1) {
+                let line = line?;
+                if !line.is_empty() {
+                    seen.insert(parse_hash(&line)?);
+                }
+            }
+        }
+        None => {
+            for line in lines {
+                let line = line?;
+                if !line.is_empty() {
+                    seen.insert(key.hash(&line));
+                }
+            }
+        }
+    }
+
+    Ok(seen)
+}
+
+fn parse_hash(line: &str) -> Result<u64, Error> {
+    u64::from_str_radix(line, 16).map_err(|e| {
+        let reason = format!("invalid seen state line {:?}: {}", line, e);
+        Error::Wx(<WxError>::new(&reason))
+    })
+}
+
+fn write_lines<W: Write>(writer: &mut W, seen: &SeenSet, key: DedupKey) -> Result<(), Error> {
+    writeln!(writer, "{}{:?}", HEADER, key)?;
+    for hash in seen {
+        writeln!(writer, "{:016x}", hash)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{temp_path, WIND_REPORT};
+    use crate::{content_hash, line_hash};
+
+    const HAIL_REPORT: &str = r#"Icon: 47.617706,-111.215248,000,0,4,"Reported By: Test User\nHail\nTime: 2018-09-20 22:49:29 UTC\nSize: 0.75" (Penny)\nNotes: None""#;
+
+    fn seen_set() -> SeenSet {
+        let mut seen = SeenSet::default();
+        seen.insert(content_hash(WIND_REPORT));
+        seen.insert(content_hash(HAIL_REPORT));
+        // Leading zeros have to survive the round trip
+        seen.insert(0xff);
+        seen
+    }
+
+    #[test]
+    fn load_should_reject_invalid_lines() {
+        let path = temp_path("seen_invalid.txt");
+        fs::write(&path, "seen-state v2 Line\nIcon: 43.112000,-94.639999\n").unwrap();
+
+        assert!(load_seen(&path, DedupKey::Line).is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn compressed_seen_state_should_round_trip() {
+        let path = temp_path("seen_round_trip.gz");
+        let seen = seen_set();
+
+        save_seen(&path, &seen, DedupKey::Line, should_compress(&path, false)).unwrap();
+        assert!(fs::read(&path).unwrap().starts_with(&GZIP_MAGIC));
+
+        let loaded = load_seen(&path, DedupKey::Line).unwrap();
+        assert_eq!(loaded, seen);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn load_should_read_uncompressed_seen_state() {
+        let path = temp_path("seen_plain.txt");
+        let seen = seen_set();
+
+        save_seen(&path, &seen, DedupKey::Line, should_compress(&path, false)).unwrap();
+        assert!(!fs::read(&path).unwrap().starts_with(&GZIP_MAGIC));
+
+        assert_eq!(load_seen(&path, DedupKey::Line).unwrap(), seen);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn load_should_hash_reports_from_the_old_format() {
+        let path = temp_path("seen_old_format.txt");
+        fs::write(&path, format!("{}\n{}\n", WIND_REPORT, HAIL_REPORT)).unwrap();
+
+        let loaded = load_seen(&path, DedupKey::Identity).unwrap();
+        let expected: SeenSet = vec![content_hash(WIND_REPORT), content_hash(HAIL_REPORT)]
+            .into_iter()
+            .collect();
+        assert_eq!(loaded, expected);
+
+        let loaded = load_seen(&path, DedupKey::Line).unwrap();
+        assert!(loaded.contains(&line_hash(WIND_REPORT)));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn load_should_reject_hashes_made_with_another_key() {
+        let path = temp_path("seen_other_key.txt");
+        save_seen(&path, &seen_set(), DedupKey::Line, false).unwrap();
+
+        assert!(load_seen(&path, DedupKey::Identity).is_err());
+        assert_eq!(load_seen(&path, DedupKey::Line).unwrap(), seen_set());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn compress_flag_should_override_extension() {
+        assert!(should_compress(Path::new("seen.gz"), false));
+        assert!(should_compress(Path::new("seen.txt"), true));
+        assert!(!should_compress(Path::new("seen.txt"), false));
+    }
+}
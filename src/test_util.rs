@@ -0,0 +1,129 @@
+use crate::parser::ReportParser;
+use crate::producer::Fetcher;
+use crate::sink::{EventSink, StoreError};
+use slog::{Drain, Key, Logger, Never, OwnedKVList, Record, Serializer, KV};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::env;
+use std::path::PathBuf;
+use std::process;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use wx::domain::Event;
+use wx::error::{Error, WxError};
+
+static TEMP_PATHS: AtomicUsize = AtomicUsize::new(0);
+
+/// A path in the temp dir ending in `name` that no other test, in this run or another, will use.
+pub fn temp_path(name: &str) -> PathBuf {
+    let n = TEMP_PATHS.fetch_add(1, Ordering::Relaxed);
+    env::temp_dir().join(format!("sn_loader_{}_{}_{}", process::id(), n, name))
+}
+
+/// Hands out `bodies` in order, then empty bodies.
+pub struct MockFetcher {
+    pub bodies: RefCell<VecDeque<String>>,
+}
+
+impl MockFetcher {
+    pub fn new(bodies: Vec<String>) -> MockFetcher {
+        MockFetcher {
+            bodies: RefCell::new(bodies.into_iter().collect()),
+        }
+    }
+}
+
+impl Fetcher for MockFetcher {
+    fn fetch(&self) -> Result<String, Error> {
+        Ok(self.bodies.borrow_mut().pop_front().unwrap_or_default())
+    }
+}
+
+/// Keeps every event written, shared between clones so tests can hand one to a producer.
+#[derive(Clone, Default)]
+pub struct MockSink {
+    pub events: Rc<RefCell<Vec<Event>>>,
+}
+
+impl EventSink for MockSink {
+    fn put_event(&self, event: &Event) -> Result<(), StoreError> {
+        self.events.borrow_mut().push(event.clone());
+        Ok(())
+    }
+}
+
+/// Fails every write and health check with `reason`.
+pub struct FailingSink {
+    pub reason: &'static str,
+}
+
+impl EventSink for FailingSink {
+    fn put_event(&self, _event: &Event) -> Result<(), StoreError> {
+        Err(StoreError::from(Error::Wx(<WxError>::new(self.reason))))
+    }
+
+    fn check_health(&self) -> Result<(), Error> {
+        Err(Error::Wx(<WxError>::new(self.reason)))
+    }
+}
+
+pub fn test_logger() -> Logger {
+    Logger::root(slog::Discard, o!())
+}
+
+/// A feed body of `count` wind reports a minute apart.
+pub fn wind_reports(count: usize) -> String {
+    (0..count)
+        .map(|i| {
+            format!(
+                r#"Icon: 43.112000,-94.639999,000,3,5,"Reported By: Test Human\nHigh Wind\nTime: 2018-09-20 22:{:02}:00 UTC\n60 mph [Measured]\nNotes: None""#,
+                i
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// A measured 60 mph wind report from 2018-09-20 22:52 UTC, without notes.
+pub const WIND_REPORT: &str = r#"Icon: 43.112000,-94.639999,000,3,5,"Reported By: Test Human\nHigh Wind\nTime: 2018-09-20 22:52:00 UTC\n60 mph [Measured]\nNotes: None""#;
+
+/// `WIND_REPORT` with `notes` in place of `None`.
+pub fn wind_report(notes: &str) -> String {
+    WIND_REPORT.replace("Notes: None", &format!("Notes: {}", notes))
+}
+
+/// `WIND_REPORT`, parsed.
+pub fn wind_event() -> Event {
+    ReportParser::new().parse(WIND_REPORT).unwrap().unwrap()
+}
+
+/// Collects log messages, and each record's own key-value fields, so tests can assert on them.
+#[derive(Clone, Default)]
+pub struct CapturingDrain {
+    pub messages: Arc<Mutex<Vec<String>>>,
+    pub fields: Arc<Mutex<Vec<(String, String)>>>,
+}
+
+struct FieldCollector(Vec<(String, String)>);
+
+impl Serializer for FieldCollector {
+    fn emit_arguments(&mut self, key: Key, val: &std::fmt::Arguments) -> slog::Result {
+        self.0.push((key.to_string(), val.to_string()));
+        Ok(())
+    }
+}
+
+impl Drain for CapturingDrain {
+    type Ok = ();
+    type Err = Never;
+
+    fn log(&self, record: &Record, _values: &OwnedKVList) -> Result<(), Never> {
+        self.messages.lock().unwrap().push(record.msg().to_string());
+
+        let mut collector = FieldCollector(Vec::new());
+        let _ = record.kv().serialize(record, &mut collector);
+        self.fields.lock().unwrap().extend(collector.0);
+        Ok(())
+    }
+}
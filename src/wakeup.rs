@@ -0,0 +1,163 @@
+use signal_hook::iterator::Signals;
+use std::io;
+use std::os::raw::c_int;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+/// Reasons to cut the wait between polls short.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Wakeup {
+    /// Poll right away, e.g. after the store comes back.
+    ForcePoll,
+    /// Reload configuration.
+    Reload,
+    /// Stop polling and exit once queued events are written.
+    Shutdown,
+}
+
+/// SIGUSR2 forces a poll, SIGHUP reloads, and SIGTERM or SIGINT shut down.
+const SIGNALS: [c_int; 4] = [
+    signal_hook::SIGUSR2,
+    signal_hook::SIGHUP,
+    signal_hook::SIGTERM,
+    signal_hook::SIGINT,
+];
+
+fn wakeup_for(signal: c_int) -> Option<Wakeup> {
+    match signal {
+        signal_hook::SIGUSR2 => Some(Wakeup::ForcePoll),
+        signal_hook::SIGHUP => Some(Wakeup::Reload),
+        signal_hook::SIGTERM | signal_hook::SIGINT => Some(Wakeup::Shutdown),
+        _ => None,
+    }
+}
+
+/// The wait between polls, which can be ended early by sending a `Wakeup`.
+pub struct PollTimer {
+    sender: Sender<Wakeup>,
+    receiver: Receiver<Wakeup>,
+}
+
+impl PollTimer {
+    pub fn new() -> PollTimer {
+        let (sender, receiver) = channel();
+        PollTimer { sender, receiver }
+    }
+
+    pub fn waker(&self) -> Sender<Wakeup> {
+        self.sender.clone()
+    }
+
+    /// Waits up to `timeout`, returning early with the wakeup if one arrives.
+    pub fn wait(&self, timeout: Duration) -> Option<Wakeup> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+}
+
+impl Default for PollTimer {
+    fn default() -> Self {
+        PollTimer::new()
+    }
+}
+
+/// Forwards signals to `waker` as wakeups on a background thread.
+pub fn forward_signals(waker: Sender<Wakeup>) -> io::Result<()> {
+    forward_signals_with(move |wakeup| waker.send(wakeup).is_ok())
+}
+
+/**
+ * Hands signals to `forward` as wakeups on a background thread, e.g. to send them down an async
+ * channel. Stops once `forward` returns false.
+ */
+pub fn forward_signals_with<W>(forward: W) -> io::Result<()>
+where
+    W: Fn(Wakeup) -> bool + Send + 'static,
+{
+    forward_only(&SIGNALS, forward)
+}
+
+/// Like `forward_signals_with`, but only registers handlers for `signals`.
+fn forward_only<W>(signals: &[c_int], forward: W) -> io::Result<()>
+where
+    W: Fn(Wakeup) -> bool + Send + 'static,
+{
+    let signals = Signals::new(signals)?;
+
+    thread::spawn(move || {
+        for wakeup in signals.forever().filter_map(wakeup_for) {
+            if !forward(wakeup) {
+                break;
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::{self, Command};
+    use std::time::Instant;
+
+    #[test]
+    fn wait_should_time_out_without_wakeup() {
+        let timer = PollTimer::new();
+        assert_eq!(timer.wait(Duration::from_millis(10)), None);
+    }
+
+    #[test]
+    fn wakeup_should_end_wait_early() {
+        let timer = PollTimer::new();
+        let waker = timer.waker();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            waker.send(Wakeup::ForcePoll).unwrap();
+        });
+
+        let start = Instant::now();
+        assert_eq!(timer.wait(Duration::from_secs(60)), Some(Wakeup::ForcePoll));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn shutdown_should_end_wait_early() {
+        let timer = PollTimer::new();
+        timer.waker().send(Wakeup::Shutdown).unwrap();
+
+        let start = Instant::now();
+        assert_eq!(timer.wait(Duration::from_secs(60)), Some(Wakeup::Shutdown));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn signals_should_map_to_wakeups() {
+        assert_eq!(wakeup_for(signal_hook::SIGUSR2), Some(Wakeup::ForcePoll));
+        assert_eq!(wakeup_for(signal_hook::SIGHUP), Some(Wakeup::Reload));
+        assert_eq!(wakeup_for(signal_hook::SIGTERM), Some(Wakeup::Shutdown));
+        assert_eq!(wakeup_for(signal_hook::SIGINT), Some(Wakeup::Shutdown));
+        assert_eq!(wakeup_for(signal_hook::SIGUSR1), None);
+    }
+
+    #[test]
+    fn sigusr2_should_force_poll() {
+        let timer = PollTimer::new();
+        let waker = timer.waker();
+        // Only SIGUSR2, so Ctrl-C still stops the test run
+        forward_only(&[signal_hook::SIGUSR2], move |wakeup| {
+            waker.send(wakeup).is_ok()
+        })
+        .unwrap();
+
+        let status = Command::new("kill")
+            .args(["-USR2", &process::id().to_string()])
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let start = Instant::now();
+        assert_eq!(timer.wait(Duration::from_secs(60)), Some(Wakeup::ForcePoll));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}